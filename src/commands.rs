@@ -0,0 +1,186 @@
+// maintenance subcommands that run instead of the server
+
+use anyhow::{Context, Result};
+use axum::http::Method;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::io::{self, BufRead, IsTerminal};
+
+use crate::config::{Cli, Command, TokenCommand, TokenConfig, TokenScope, load_configuration};
+use crate::utils::passwords::{HashAlgorithm, hash_password};
+use crate::utils::share::{ShareLinks, parse_duration};
+use crate::utils::tokens::{ApiToken, generate_token, hash_token};
+
+/// run a subcommand to completion
+pub fn run(cli: &Cli, command: &Command) -> Result<()> {
+    match command {
+        Command::HashPassword {
+            algorithm,
+            username,
+        } => run_hash_password(*algorithm, username.as_deref()),
+        Command::Sign {
+            path,
+            ttl,
+            method,
+            prefix,
+            url,
+            vhost,
+        } => run_sign(
+            cli,
+            &SignRequest {
+                path,
+                ttl,
+                method,
+                prefix: *prefix,
+                url: url.as_deref(),
+                vhost: vhost.as_deref(),
+            },
+        ),
+        Command::Token {
+            command:
+                TokenCommand::Create {
+                    name,
+                    scopes,
+                    paths,
+                    expires,
+                },
+        } => run_token_create(name, scopes, paths, *expires),
+    }
+}
+
+fn run_hash_password(algorithm: HashAlgorithm, username: Option<&str>) -> Result<()> {
+    if let Some(username) = username
+        && (username.is_empty() || username.contains(':'))
+    {
+        anyhow::bail!("invalid user name '{username}'");
+    }
+
+    let password = read_password()?;
+    if password.is_empty() {
+        anyhow::bail!("refusing to hash an empty password");
+    }
+
+    let hash = hash_password(&password, algorithm)?;
+    match username {
+        Some(username) => println!("{username}:{hash}"),
+        None => println!("{hash}"),
+    }
+    Ok(())
+}
+
+// prompt without echo on a terminal, otherwise take the first line of stdin
+fn read_password() -> Result<String> {
+    if io::stdin().is_terminal() {
+        let password =
+            rpassword::prompt_password("password: ").context("failed to read password")?;
+        let confirmation =
+            rpassword::prompt_password("confirm password: ").context("failed to read password")?;
+        if password != confirmation {
+            anyhow::bail!("passwords do not match");
+        }
+        return Ok(password);
+    }
+
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("failed to read password from stdin")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+struct SignRequest<'a> {
+    path: &'a str,
+    ttl: &'a str,
+    method: &'a str,
+    prefix: bool,
+    url: Option<&'a str>,
+    vhost: Option<&'a str>,
+}
+
+fn run_sign(cli: &Cli, request: &SignRequest<'_>) -> Result<()> {
+    let config = load_configuration(cli)?;
+    let (config, site) = match request.vhost {
+        Some(name) => {
+            let vhost = config
+                .vhosts
+                .iter()
+                .find(|vhost| vhost.names.iter().any(|vhost_name| vhost_name == name))
+                .with_context(|| format!("no vhost is named '{name}'"))?;
+            (
+                config.vhost_config(vhost),
+                vhost.canonical_name().to_string(),
+            )
+        }
+        None => (config, String::new()),
+    };
+
+    let share_links = ShareLinks::new(config.security.share_secret.as_deref(), &site);
+    let method = Method::from_bytes(request.method.to_ascii_uppercase().as_bytes())
+        .with_context(|| format!("invalid method '{}'", request.method))?;
+    let ttl =
+        chrono::Duration::from_std(parse_duration(request.ttl)?).context("ttl is too long")?;
+    let expires = Utc::now()
+        .checked_add_signed(ttl)
+        .context("ttl is too long")?;
+
+    let signed = share_links.sign(
+        request.path,
+        request.prefix,
+        &method,
+        None,
+        expires.timestamp(),
+    )?;
+    let url = request.url.unwrap_or("").trim_end_matches('/');
+    println!("{url}{}{signed}", config.base_path());
+    eprintln!(
+        "valid until {}",
+        expires.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    Ok(())
+}
+
+// `[[security.token]]` as it appears in the config file
+#[derive(Serialize)]
+struct TokenSnippet<'a> {
+    security: TokenSnippetSecurity<'a>,
+}
+
+#[derive(Serialize)]
+struct TokenSnippetSecurity<'a> {
+    token: [&'a TokenConfig; 1],
+}
+
+fn run_token_create(
+    name: &str,
+    scopes: &[TokenScope],
+    paths: &[String],
+    expires: Option<DateTime<Utc>>,
+) -> Result<()> {
+    if name.is_empty() || name.contains(':') {
+        anyhow::bail!("invalid token name '{name}'");
+    }
+    if expires.is_some_and(|expires| expires <= Utc::now()) {
+        anyhow::bail!("token expiry is in the past");
+    }
+
+    let token = generate_token();
+    let config = TokenConfig {
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        scopes: scopes.to_vec(),
+        paths: paths.to_vec(),
+        expires,
+    };
+    ApiToken::from_config(&config).context("invalid token")?;
+
+    let snippet = toml::to_string(&TokenSnippet {
+        security: TokenSnippetSecurity { token: [&config] },
+    })
+    .context("failed to format the config entry")?;
+    println!("{token}");
+    println!();
+    println!("# add to the config file; only the hash is stored, keep the token above");
+    print!("{snippet}");
+    Ok(())
+}
@@ -8,8 +8,16 @@ use figment::{
 use serde::Serialize;
 use tracing::{debug, info};
 
-use super::types::{AppConfig, Cli, SecurityPolicy};
-use std::path::PathBuf;
+use super::types::{AppConfig, Cli, SecurityPolicy, ServerConfig};
+use crate::utils::access::AccessRules;
+use crate::utils::cidr::TrustedProxies;
+use crate::utils::hosts::HostPattern;
+use crate::utils::ignore::{load_ignore_rules, validate_nested_ignore_files};
+use crate::utils::passwords::Users;
+use crate::utils::share::MIN_SECRET_LEN;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::{fs, io::ErrorKind};
 
 /// load and merge configuration from multiple sources
@@ -47,7 +55,7 @@ pub fn load_configuration(cli: &Cli) -> Result<AppConfig> {
     }
 
     // merge cli overrides - highest precedence
-    figment = figment.merge(cli_overrides(cli));
+    figment = figment.merge(cli_overrides(cli)).merge(tls_overrides(cli));
 
     // extract final configuration
     let config: AppConfig = figment.extract().context("failed to parse configuration")?;
@@ -72,6 +80,8 @@ struct ServerConfigOverrides {
     enable_upload: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     cors_origins: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listen: Option<Vec<String>>,
 }
 
 fn cli_overrides(cli: &Cli) -> Serialized<ServerConfigOverrides> {
@@ -85,38 +95,127 @@ fn cli_overrides(cli: &Cli) -> Serialized<ServerConfigOverrides> {
         } else {
             Some(cli.cors.clone())
         },
+        listen: if cli.listen.is_empty() {
+            None
+        } else {
+            Some(cli.listen.clone())
+        },
     };
 
     Serialized::defaults(server_overrides).key("server")
 }
 
+#[derive(Serialize)]
+struct TlsConfigOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    self_signed: Option<bool>,
+}
+
+fn tls_overrides(cli: &Cli) -> Serialized<TlsConfigOverrides> {
+    let tls_overrides = TlsConfigOverrides {
+        cert: cli.tls_cert.clone(),
+        key: cli.tls_key.clone(),
+        self_signed: if cli.tls_self_signed {
+            Some(true)
+        } else {
+            None
+        },
+    };
+
+    Serialized::defaults(tls_overrides).key("tls")
+}
+
 /// validate configuration for consistency and security
 fn validate_configuration(config: &AppConfig) -> Result<()> {
-    // validate public directory exists
-    match fs::metadata(&config.server.public_dir) {
-        Ok(metadata) => {
-            if !metadata.is_dir() {
-                anyhow::bail!(
-                    "public directory is not a directory: {}",
-                    config.server.public_dir.display()
-                );
+    validate_site(config)?;
+
+    // validate virtual hosts, each checked as the site it describes
+    let mut names = HashSet::new();
+    let mut has_default = false;
+    for vhost in &config.vhosts {
+        if vhost.names.is_empty() && !vhost.default {
+            anyhow::bail!("vhost needs at least one name or default = true");
+        }
+        if vhost.default && std::mem::replace(&mut has_default, true) {
+            anyhow::bail!("only one vhost can be the default");
+        }
+        for name in &vhost.names {
+            let pattern: HostPattern = name.parse().context("invalid vhost name")?;
+            if !names.insert(pattern) {
+                anyhow::bail!("vhost name '{name}' is configured more than once");
             }
         }
-        Err(err) if err.kind() == ErrorKind::NotFound => {
-            anyhow::bail!(
-                "public directory does not exist: {}",
-                config.server.public_dir.display()
-            );
+        // without its own directory a vhost would serve the working directory
+        if vhost.server.public_dir == ServerConfig::default().public_dir {
+            anyhow::bail!("vhost {} needs its own public_dir", vhost.label());
         }
-        Err(err) => {
-            anyhow::bail!(
-                "failed to read public directory metadata: {} ({})",
-                config.server.public_dir.display(),
-                err
-            );
+        validate_site(&config.vhost_config(vhost))
+            .with_context(|| format!("invalid vhost {}", vhost.label()))?;
+    }
+
+    // validate port range
+    if config.server.port == 0 {
+        anyhow::bail!("port cannot be 0");
+    }
+
+    // validate listen addresses
+    for entry in &config.server.listen {
+        validate_listen_entry(entry)?;
+    }
+
+    // validate unix socket ownership syntax
+    if let Some(owner) = &config.server.unix_socket_owner
+        && (owner.is_empty() || owner.split(':').count() > 2)
+    {
+        anyhow::bail!("unix_socket_owner must be 'user', 'user:group' or ':group'");
+    }
+    if let Some(mode) = config.server.unix_socket_mode
+        && mode > 0o7777
+    {
+        anyhow::bail!("unix_socket_mode must be an octal permission mode such as 0o660");
+    }
+
+    // validate reverse proxy settings
+    let trusted_proxies =
+        TrustedProxies::new(&config.server.trusted_proxies).context("invalid trusted_proxies")?;
+    if config.server.proxy_protocol && trusted_proxies.is_empty() {
+        anyhow::bail!("proxy_protocol requires trusted_proxies to be set");
+    }
+
+    // validate tls configuration
+    let tls = &config.tls;
+    if tls.cert.is_some() != tls.key.is_some() {
+        anyhow::bail!("both tls cert and key must be provided to enable https");
+    }
+    if tls.self_signed && tls.cert.is_some() {
+        anyhow::bail!("tls self_signed cannot be combined with a tls cert and key");
+    }
+    for path in [&tls.cert, &tls.key].into_iter().flatten() {
+        if !path.is_file() {
+            anyhow::bail!("tls file does not exist: {}", path.display());
+        }
+    }
+    if let Some(redirect_port) = tls.redirect_http_port {
+        if !tls.is_enabled() {
+            anyhow::bail!("redirect_http_port requires tls to be enabled");
+        }
+        if redirect_port == 0 || redirect_port == config.server.port {
+            anyhow::bail!("redirect_http_port must be non-zero and differ from the https port");
         }
     }
 
+    Ok(())
+}
+
+/// validate the directories, uploads and authentication of one site
+fn validate_site(config: &AppConfig) -> Result<()> {
+    // validate public directory exists
+    validate_directory(&config.server.public_dir, "public directory")?;
+
     // validate upload directory if uploads are enabled
     if config.server.enable_upload {
         let upload_dir = config.upload_dir();
@@ -147,6 +246,29 @@ fn validate_configuration(config: &AppConfig) -> Result<()> {
         }
     }
 
+    // compile ignore rules up front so pattern errors surface at startup
+    let enforce_ignore = config.listing.enforce_ignore;
+    if let Some(ignore_file) = &config.listing.ignore_file {
+        validate_ignore_files(&config.server.public_dir, ignore_file, enforce_ignore)?;
+    }
+
+    // validate mounted directories and their ignore files
+    let mut mount_paths = HashSet::new();
+    for mount in &config.mounts {
+        validate_mount_path(&mount.path)?;
+        if !mount_paths.insert(mount.path.trim_matches('/')) {
+            anyhow::bail!("mount path '{}' is configured more than once", mount.path);
+        }
+        validate_directory(&mount.dir, "mount directory")?;
+        if let Some(ignore_file) = mount
+            .ignore_file
+            .as_ref()
+            .or(config.listing.ignore_file.as_ref())
+        {
+            validate_ignore_files(&mount.dir, ignore_file, enforce_ignore)?;
+        }
+    }
+
     // validate authentication configuration
     if config.security.policy != SecurityPolicy::AuthenticateNone
         && matches!(
@@ -156,15 +278,125 @@ fn validate_configuration(config: &AppConfig) -> Result<()> {
     {
         anyhow::bail!("both username and password must be provided for authentication");
     }
+    let users = Users::load(&config.security).context("invalid users")?;
+    AccessRules::new(&config.access_rules)
+        .and_then(|rules| rules.check_names(&users))
+        .context("invalid access_rule")?;
+    let rate_limit = &config.security.rate_limit;
+    if rate_limit.enabled && (rate_limit.max_failures == 0 || rate_limit.lockout == 0) {
+        anyhow::bail!("rate_limit max_failures and lockout must be greater than 0");
+    }
+    if let Some(secret) = &config.security.share_secret
+        && secret.len() < MIN_SECRET_LEN
+    {
+        anyhow::bail!("share_secret must be at least {MIN_SECRET_LEN} bytes long");
+    }
 
-    // validate port range
-    if config.server.port == 0 {
-        anyhow::bail!("port cannot be 0");
+    // validate the mount prefix, which must be a plain path
+    if let Some(base_path) = &config.server.base_path {
+        validate_base_path(base_path)?;
     }
 
     Ok(())
 }
 
+/// compile the root ignore file of a directory and, when the rules are
+/// enforced, every nested one, which would otherwise hide its directory
+fn validate_ignore_files(dir: &Path, ignore_file: &Path, enforce: bool) -> Result<()> {
+    load_ignore_rules(dir, dir, ignore_file)
+        .with_context(|| format!("invalid ignore file: {}", ignore_file.display()))?;
+    if enforce {
+        validate_nested_ignore_files(dir, ignore_file)?;
+    }
+    Ok(())
+}
+
+/// check that a directory exists
+fn validate_directory(dir: &Path, description: &str) -> Result<()> {
+    match fs::metadata(dir) {
+        Ok(metadata) => {
+            if !metadata.is_dir() {
+                anyhow::bail!("{description} is not a directory: {}", dir.display());
+            }
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            anyhow::bail!("{description} does not exist: {}", dir.display());
+        }
+        Err(err) => {
+            anyhow::bail!(
+                "failed to read {description} metadata: {} ({})",
+                dir.display(),
+                err
+            );
+        }
+    }
+    Ok(())
+}
+
+/// check that a mount path is a single plain segment such as `/builds`
+fn validate_mount_path(path: &str) -> Result<()> {
+    let valid = path
+        .strip_prefix('/')
+        .map(|segment| segment.strip_suffix('/').unwrap_or(segment))
+        .is_some_and(|segment| {
+            is_plain_segment(segment) && !matches!(segment, "__soop_static" | "__soop_share")
+        });
+    if !valid {
+        anyhow::bail!("mount path '{path}' must be a single segment such as /builds");
+    }
+    Ok(())
+}
+
+/// check that a base path is an absolute path of plain segments
+fn validate_base_path(base_path: &str) -> Result<()> {
+    let Some(segments) = base_path.strip_prefix('/') else {
+        anyhow::bail!("base_path '{base_path}' must start with '/'");
+    };
+
+    let segments = segments.trim_end_matches('/');
+    if segments.is_empty() {
+        return Ok(());
+    }
+
+    if !segments.split('/').all(is_plain_segment) {
+        anyhow::bail!("base_path '{base_path}' must be a plain path such as /files");
+    }
+
+    Ok(())
+}
+
+// a url path segment needing no escaping and no dot segment handling
+fn is_plain_segment(segment: &str) -> bool {
+    !matches!(segment, "" | "." | "..")
+        && segment
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !b"?#%{}*\\/".contains(&byte))
+}
+
+/// check that a listen entry is `unix:<path>` or `host:port` with a usable port
+fn validate_listen_entry(entry: &str) -> Result<()> {
+    if let Some(path) = entry.strip_prefix("unix:") {
+        if path.is_empty() {
+            anyhow::bail!("listen address '{entry}' is missing a socket path");
+        }
+        return Ok(());
+    }
+    if entry.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+
+    let Some((host, port)) = entry.rsplit_once(':') else {
+        anyhow::bail!("listen address '{entry}' is missing a port");
+    };
+    if host.is_empty() || host.contains(':') {
+        anyhow::bail!("listen address '{entry}' is invalid, write ipv6 literals as [addr]:port");
+    }
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(()),
+        _ => anyhow::bail!("listen address '{entry}' has an invalid port"),
+    }
+}
+
 /// load configuration from a file for testing purposes
 #[cfg(feature = "test-helpers")]
 #[allow(dead_code)]
@@ -197,6 +429,11 @@ mod tests {
             verbose: 0,
             quiet: 0,
             cors: vec![],
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            listen: vec![],
+            command: None,
         };
 
         let config = load_configuration(&cli).unwrap();
@@ -234,6 +471,11 @@ prepend_timestamp = false
             verbose: 0,
             quiet: 0,
             cors: vec![],
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            listen: vec![],
+            command: None,
         };
 
         let config = load_configuration(&cli).unwrap();
@@ -260,6 +502,11 @@ prepend_timestamp = false
             verbose: 0,
             quiet: 0,
             cors: vec![],
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            listen: vec![],
+            command: None,
         };
 
         let err = load_configuration(&cli).unwrap_err();
@@ -284,6 +531,11 @@ prepend_timestamp = false
             verbose: 0,
             quiet: 0,
             cors: vec![],
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            listen: vec![],
+            command: None,
         };
 
         let err = load_configuration(&cli).unwrap_err();
@@ -292,4 +544,17 @@ prepend_timestamp = false
             "unexpected error: {err}"
         );
     }
+
+    #[test]
+    fn test_listen_entry_validation() {
+        assert!(validate_listen_entry("127.0.0.1:8000").is_ok());
+        assert!(validate_listen_entry("[::]:8000").is_ok());
+        assert!(validate_listen_entry("localhost:8000").is_ok());
+        assert!(validate_listen_entry("localhost").is_err());
+        assert!(validate_listen_entry("::1:8000").is_err());
+        assert!(validate_listen_entry("localhost:0").is_err());
+        assert!(validate_listen_entry(":8000").is_err());
+        assert!(validate_listen_entry("unix:/run/soop3.sock").is_ok());
+        assert!(validate_listen_entry("unix:").is_err());
+    }
 }
@@ -1,6 +1,7 @@
 // configuration module public api
 
 pub mod loading;
+pub mod reload;
 pub mod types;
 
 pub use loading::load_configuration;
@@ -0,0 +1,218 @@
+// differences between a running and a reloaded configuration
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::types::AppConfig;
+
+/// settings read only while the listeners are set up, which a reload cannot change
+const RESTART_REQUIRED: &[&str] = &[
+    "server.host",
+    "server.port",
+    "server.listen",
+    "server.unix_socket_mode",
+    "server.unix_socket_owner",
+    "server.shutdown_timeout",
+    "server.trusted_proxies",
+    "server.proxy_protocol",
+    "tls",
+];
+
+/// one changed setting, keyed by its dotted path such as `server.cors_origins`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl ConfigChange {
+    /// whether the change only takes effect after a restart
+    pub fn requires_restart(&self) -> bool {
+        RESTART_REQUIRED.iter().any(|prefix| {
+            self.key
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    }
+}
+
+impl std::fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.key, self.old, self.new)
+    }
+}
+
+/// every setting that differs between two configurations, with secrets redacted
+pub fn config_changes(old: &AppConfig, new: &AppConfig) -> Vec<ConfigChange> {
+    let (old, new) = (flatten_config(old), flatten_config(new));
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| {
+            let show = |value: Option<&Value>| match value {
+                None | Some(Value::Null) => "(unset)".to_string(),
+                Some(_) if is_secret(key) => "(redacted)".to_string(),
+                Some(value) => redact_nested(value.clone()).to_string(),
+            };
+            ConfigChange {
+                key: key.clone(),
+                old: show(old.get(key)),
+                new: show(new.get(key)),
+            }
+        })
+        .collect()
+}
+
+// leaf settings by dotted path; lists are compared as a whole, so a changed
+// mount or vhost is reported as one change of its list
+fn flatten_config(config: &AppConfig) -> BTreeMap<String, Value> {
+    let mut settings = BTreeMap::new();
+    if let Ok(value) = serde_json::to_value(config) {
+        flatten_into(&mut settings, String::new(), value);
+    }
+    settings
+}
+
+fn flatten_into(settings: &mut BTreeMap<String, Value>, key: String, value: Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let key = if key.is_empty() {
+                    name
+                } else {
+                    format!("{key}.{name}")
+                };
+                flatten_into(settings, key, value);
+            }
+        }
+        value => {
+            settings.insert(key, value);
+        }
+    }
+}
+
+// hide secrets inside lists such as vhost credentials
+fn redact_nested(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(redact_nested).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = if is_secret(&name) && !value.is_null() {
+                        Value::String("(redacted)".to_string())
+                    } else {
+                        redact_nested(value)
+                    };
+                    (name, value)
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let name = key.rsplit('.').next().unwrap_or(key);
+    matches!(
+        name,
+        "password" | "password_hash" | "token_hash" | "share_secret"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changed_settings() {
+        let old = AppConfig::default();
+        let mut new = old.clone();
+        new.server.cors_origins = vec!["https://example.com".to_string()];
+        new.server.port = 9000;
+        new.upload.prevent_overwrite = false;
+
+        let changes = config_changes(&old, &new);
+        let described: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            described,
+            vec![
+                r#"server.cors_origins: [] -> ["https://example.com"]"#,
+                "server.port: 8000 -> 9000",
+                "upload.prevent_overwrite: true -> false",
+            ]
+        );
+        let restart: Vec<&str> = changes
+            .iter()
+            .filter(|change| change.requires_restart())
+            .map(|change| change.key.as_str())
+            .collect();
+        assert_eq!(restart, vec!["server.port"]);
+
+        assert!(config_changes(&old, &old.clone()).is_empty());
+    }
+
+    #[test]
+    fn redacts_passwords() {
+        let old = AppConfig::default();
+        let mut new = old.clone();
+        new.security.username = Some("admin".to_string());
+        new.security.password = Some("secret".to_string());
+
+        let described: Vec<String> = config_changes(&old, &new)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                "security.password: (unset) -> (redacted)",
+                r#"security.username: (unset) -> "admin""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_secrets_changed_inside_lists() {
+        let mut old = AppConfig {
+            vhosts: vec![crate::config::VhostConfig {
+                names: vec!["docs.local".to_string()],
+                default: false,
+                server: Default::default(),
+                security: Some(Default::default()),
+                upload: Default::default(),
+                mounts: Vec::new(),
+                access_rules: Vec::new(),
+            }],
+            ..Default::default()
+        };
+        let mut new = old.clone();
+        for (config, password) in [(&mut old, "old"), (&mut new, "new")] {
+            if let Some(security) = &mut config.vhosts[0].security {
+                security.password = Some(password.to_string());
+            }
+        }
+
+        let changes = config_changes(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].key, "vhost");
+        assert!(!changes[0].to_string().contains("new"));
+    }
+
+    #[test]
+    fn restart_prefixes_match_whole_keys() {
+        let change = |key: &str| ConfigChange {
+            key: key.to_string(),
+            old: String::new(),
+            new: String::new(),
+        };
+        assert!(change("tls.cert").requires_restart());
+        assert!(change("server.listen").requires_restart());
+        assert!(!change("server.public_dir").requires_restart());
+        assert!(!change("server.hostname").requires_restart());
+    }
+}
@@ -1,9 +1,13 @@
 // configuration type definitions
 
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use crate::utils::passwords::HashAlgorithm;
+
 /// command line interface definition
 #[derive(Parser, Debug, Clone)]
 #[command(name = "soop3", version = env!("CARGO_PKG_VERSION"))]
@@ -40,6 +44,100 @@ pub struct Cli {
     /// allowed CORS origins (repeatable, use "*" for all)
     #[arg(long)]
     pub cors: Vec<String>,
+
+    /// pem certificate chain to serve https with
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// pem private key for the tls certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// serve https with a certificate generated at startup
+    #[arg(long, conflicts_with_all = ["tls_cert", "tls_key"])]
+    pub tls_self_signed: bool,
+
+    /// address to listen on as host:port or unix:<path> (repeatable, overrides host and port)
+    #[arg(long)]
+    pub listen: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// maintenance commands run instead of the server
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// hash a password for a [[security.user]] entry or an htpasswd file
+    HashPassword {
+        /// hashing algorithm
+        #[arg(short, long, value_enum, default_value_t = HashAlgorithm::Argon2id)]
+        algorithm: HashAlgorithm,
+
+        /// print an htpasswd line for this user instead of the bare hash
+        #[arg(long)]
+        username: Option<String>,
+    },
+
+    /// print a signed link to a path, using the config's share_secret
+    Sign {
+        /// path to share, relative to the base path, such as /docs/report.pdf
+        path: String,
+
+        /// how long the link stays valid, such as 90m, 24h or 7d
+        #[arg(long, default_value = "24h")]
+        ttl: String,
+
+        /// http method the link allows, POST for uploads
+        #[arg(short, long, default_value = "GET")]
+        method: String,
+
+        /// also allow every path below this one
+        #[arg(long)]
+        prefix: bool,
+
+        /// url the server is reached at, such as https://files.example.com
+        #[arg(long)]
+        url: Option<String>,
+
+        /// sign for the virtual host with this name instead of the main site
+        #[arg(long)]
+        vhost: Option<String>,
+    },
+
+    /// manage bearer tokens for scripts and ci
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+}
+
+/// `soop3 token` subcommands
+#[derive(Subcommand, Debug, Clone)]
+pub enum TokenCommand {
+    /// generate a token and print it with its [[security.token]] entry
+    Create {
+        /// name the token is logged as and access rules refer to
+        name: String,
+
+        /// what the token may do, repeatable
+        #[arg(
+            short,
+            long = "scope",
+            value_name = "SCOPE",
+            value_enum,
+            required = true
+        )]
+        scopes: Vec<TokenScope>,
+
+        /// only accept the token under this path prefix, repeatable
+        #[arg(short, long = "path", value_name = "PATH")]
+        paths: Vec<String>,
+
+        /// expiry as an rfc 3339 timestamp such as 2027-01-01T00:00:00Z
+        #[arg(short, long)]
+        expires: Option<DateTime<Utc>>,
+    },
 }
 
 /// complete application configuration
@@ -49,10 +147,24 @@ pub struct AppConfig {
     pub security: SecurityConfig,
     pub listing: ListingConfig,
     pub upload: UploadConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// extra directories served under their own url paths
+    #[serde(default, rename = "mount")]
+    pub mounts: Vec<MountConfig>,
+    /// ordered rules granting users and groups access to url paths
+    #[serde(default, rename = "access_rule")]
+    pub access_rules: Vec<AccessRuleConfig>,
+    /// sites selected by the request's `Host` header
+    #[serde(default, rename = "vhost")]
+    pub vhosts: Vec<VhostConfig>,
 }
 
 /// server configuration section
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
@@ -61,21 +173,188 @@ pub struct ServerConfig {
     pub enable_upload: bool,
     #[serde(default)]
     pub cors_origins: Vec<String>,
+    /// serve `.br`/`.zst`/`.gz` siblings when the client accepts them
+    #[serde(default)]
+    pub precompressed: bool,
+    /// `host:port` or `unix:<path>` addresses to bind instead of `host` and `port`
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// permission bits for unix socket files, e.g. `0o660`
+    pub unix_socket_mode: Option<u32>,
+    /// `user`, `user:group` or `:group` owning unix socket files
+    pub unix_socket_owner: Option<String>,
+    /// seconds to let in-flight requests finish after SIGINT or SIGTERM
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// addresses and cidr blocks of proxies whose forwarding headers are believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// expect a proxy protocol v1 or v2 header on every tcp connection
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// url path prefix the server is mounted under, e.g. `/files`
+    pub base_path: Option<String>,
+}
+
+/// a directory served under its own url path, e.g. `/builds`; unset options
+/// fall back to the server wide settings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MountConfig {
+    /// single segment url path such as `/builds`
+    pub path: String,
+    /// directory served under `path`
+    pub dir: PathBuf,
+    pub enable_upload: Option<bool>,
+    pub ignore_file: Option<PathBuf>,
+    pub policy: Option<SecurityPolicy>,
+}
+
+/// a site served for the host names it lists; listener and proxy settings
+/// always come from the top level `[server]` section
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VhostConfig {
+    /// host names such as `docs.local`, or `*.docs.local` for every subdomain
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// serve requests whose host matches no virtual host
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// the top-level security settings when omitted
+    #[serde(default)]
+    pub security: Option<SecurityConfig>,
+    /// the top-level upload settings when omitted
+    #[serde(default)]
+    pub upload: Option<UploadConfig>,
+    #[serde(default, rename = "mount")]
+    pub mounts: Vec<MountConfig>,
+    #[serde(default, rename = "access_rule")]
+    pub access_rules: Vec<AccessRuleConfig>,
+}
+
+/// one `[[access_rule]]`; the first rule matching a request's path and method
+/// that applies to its user decides, requests no rule matches fall back to
+/// the security policy
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccessRuleConfig {
+    /// url path glob such as `/team/**`; `*` and `?` stay within a segment,
+    /// `/**` matches any number of segments including none
+    pub path: String,
+    /// limit the rule to these methods, all methods when empty; `GET` also
+    /// covers `HEAD`
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// users the rule applies to, `*` for every authenticated user; a rule
+    /// without users or groups applies to everyone, anonymous clients included
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// granted permissions, an empty list denies access
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+/// what an access rule grants: downloading files, uploading, or viewing
+/// directory listings
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write,
+    List,
 }
 
 /// security and authentication configuration
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct SecurityConfig {
+    /// a single plaintext user, kept for existing configs
     pub username: Option<String>,
     pub password: Option<String>,
     #[serde(default)]
     pub policy: SecurityPolicy,
+    /// users with hashed passwords
+    #[serde(default, rename = "user")]
+    pub users: Vec<UserConfig>,
+    /// apache style `user:hash` file, read at startup and on reload
+    pub htpasswd_file: Option<PathBuf>,
+    /// group names and their members, for access rules
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// bearer tokens for automation
+    #[serde(default, rename = "token")]
+    pub tokens: Vec<TokenConfig>,
+    /// key signing share links, see `soop3 sign`; links are disabled without it
+    pub share_secret: Option<String>,
+    /// lockouts after repeated failed logins
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// brute-force protection, counted per client ip and, by default, per user name
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// also count failures per user name from every client, which stops
+    /// guessing spread over many addresses; turn it off where anyone locking
+    /// a user out by guessing wrong on purpose is the greater risk
+    #[serde(default = "default_true")]
+    pub per_user: bool,
+    /// failed logins allowed before a lockout
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    /// seconds after the first failure before the count starts over
+    #[serde(default = "default_failure_window")]
+    pub failure_window: u64,
+    /// seconds of the first lockout, doubled by every further failure
+    #[serde(default = "default_lockout")]
+    pub lockout: u64,
+    /// longest lockout in seconds
+    #[serde(default = "default_max_lockout")]
+    pub max_lockout: u64,
+}
+
+/// one `[[security.user]]` entry
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UserConfig {
+    pub username: String,
+    /// argon2id, bcrypt or sha-crypt hash, see `soop3 hash-password`
+    pub password_hash: String,
+}
+
+/// one `[[security.token]]` entry, see `soop3 token create`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenConfig {
+    pub name: String,
+    /// `sha256:` and the hex digest of the token
+    pub token_hash: String,
+    #[serde(default)]
+    pub scopes: Vec<TokenScope>,
+    /// path prefixes the token is limited to, anywhere when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// what a bearer token may do: download and list, upload, or delete
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    Read,
+    Upload,
+    Delete,
 }
 
 /// directory listing configuration
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct ListingConfig {
     pub ignore_file: Option<PathBuf>,
+    /// also refuse downloads and uploads for paths matched by the ignore file
+    #[serde(default)]
+    pub enforce_ignore: bool,
 }
 
 /// file upload configuration
@@ -91,6 +370,33 @@ pub struct UploadConfig {
     pub create_directories: bool,
 }
 
+/// response compression configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionConfig {
+    /// off by default since encoded responses change etags and drop range support
+    #[serde(default)]
+    pub enabled: bool,
+    /// compression level passed to every algorithm (clamped to each one's range)
+    pub level: Option<i32>,
+    /// responses smaller than this many bytes are sent uncompressed
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: u16,
+}
+
+/// tls configuration section
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct TlsConfig {
+    /// pem certificate chain, leaf certificate first
+    pub cert: Option<PathBuf>,
+    /// pem private key matching the leaf certificate
+    pub key: Option<PathBuf>,
+    /// generate a throwaway certificate for the host and local addresses
+    #[serde(default)]
+    pub self_signed: bool,
+    /// plain http port that redirects every request to https
+    pub redirect_http_port: Option<u16>,
+}
+
 /// authentication policy options
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -125,6 +431,67 @@ impl AppConfig {
             .as_ref()
             .unwrap_or(&self.server.public_dir)
     }
+
+    /// the mount prefix without a trailing slash, empty when serving from `/`
+    pub fn base_path(&self) -> &str {
+        self.server
+            .base_path
+            .as_deref()
+            .map_or("", |base_path| base_path.trim_end_matches('/'))
+    }
+
+    /// the complete configuration of a virtual host's site, sharing the
+    /// process wide listener, proxy, listing, compression and tls settings
+    pub fn vhost_config(&self, vhost: &VhostConfig) -> AppConfig {
+        let base = &self.server;
+        AppConfig {
+            server: ServerConfig {
+                host: base.host.clone(),
+                port: base.port,
+                listen: base.listen.clone(),
+                unix_socket_mode: base.unix_socket_mode,
+                unix_socket_owner: base.unix_socket_owner.clone(),
+                shutdown_timeout: base.shutdown_timeout,
+                trusted_proxies: base.trusted_proxies.clone(),
+                proxy_protocol: base.proxy_protocol,
+                ..vhost.server.clone()
+            },
+            security: vhost
+                .security
+                .clone()
+                .unwrap_or_else(|| self.security.clone()),
+            upload: vhost.upload.clone().unwrap_or_else(|| self.upload.clone()),
+            mounts: vhost.mounts.clone(),
+            access_rules: vhost.access_rules.clone(),
+            vhosts: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
+impl VhostConfig {
+    /// the first host name, empty for a nameless default vhost, which like
+    /// the top-level site serves hosts no other vhost matches
+    pub fn canonical_name(&self) -> &str {
+        self.names.first().map_or("", String::as_str)
+    }
+
+    /// the host names for log and error messages
+    pub fn label(&self) -> String {
+        if self.names.is_empty() {
+            "default".to_string()
+        } else {
+            self.names.join(", ")
+        }
+    }
+}
+
+impl TlsConfig {
+    /// https is served with a self-signed certificate or once both the
+    /// certificate and key are configured
+    pub fn is_enabled(&self) -> bool {
+        self.self_signed || (self.cert.is_some() && self.key.is_some())
+    }
 }
 
 impl Default for ServerConfig {
@@ -136,6 +503,14 @@ impl Default for ServerConfig {
             upload_dir: None,
             enable_upload: false,
             cors_origins: Vec::new(),
+            precompressed: false,
+            listen: Vec::new(),
+            unix_socket_mode: None,
+            unix_socket_owner: None,
+            shutdown_timeout: default_shutdown_timeout(),
+            trusted_proxies: Vec::new(),
+            proxy_protocol: false,
+            base_path: None,
         }
     }
 }
@@ -151,11 +526,58 @@ impl Default for UploadConfig {
     }
 }
 
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: None,
+            min_size: default_compression_min_size(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            per_user: default_true(),
+            max_failures: default_max_failures(),
+            failure_window: default_failure_window(),
+            lockout: default_lockout(),
+            max_lockout: default_max_lockout(),
+        }
+    }
+}
+
 // default value functions for serde
 fn default_max_request_size() -> u64 {
     1024 * 1024 * 1024 // 1 GiB
 }
 
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_compression_min_size() -> u16 {
+    1024
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_failure_window() -> u64 {
+    15 * 60
+}
+
+fn default_lockout() -> u64 {
+    60
+}
+
+fn default_max_lockout() -> u64 {
+    60 * 60
+}
+
 fn default_true() -> bool {
     true
 }
@@ -1,5 +1,6 @@
 // soop3 library root for testing and modular access
 
+pub mod commands;
 pub mod config;
 pub mod server;
 pub mod utils;
@@ -5,6 +5,7 @@ use anyhow::Result;
 use clap::Parser;
 use tracing::{Level, info};
 
+mod commands;
 mod config;
 mod server;
 mod utils;
@@ -17,6 +18,11 @@ async fn main() -> Result<()> {
     // parse command line arguments
     let cli = Cli::parse();
 
+    // subcommands print their result to stdout, so run them before logging starts
+    if let Some(command) = &cli.command {
+        return commands::run(&cli, command);
+    }
+
     // initialize logging based on verbosity flags
     init_logging(cli.verbose, cli.quiet)?;
 
@@ -24,7 +30,7 @@ async fn main() -> Result<()> {
     let config = load_configuration(&cli)?;
 
     // start the http server
-    start_server(config).await
+    start_server(cli, config).await
 }
 
 /// initialize structured logging with tracing
@@ -7,58 +7,183 @@ use axum::{
     middleware,
     routing::{get, post},
 };
+use futures_util::future::{self, BoxFuture};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, info, warn};
+use tracing::{error, info, warn};
 
+#[cfg(unix)]
+use super::systemd;
 use super::{
+    fs::Mounts,
     handlers::{
         assets::serve_static_asset,
         files::{handle_request, handle_root_request},
+        share::{SHARE_PATH, create_share_link},
         upload::{handle_root_upload_request, handle_upload_request},
     },
+    listen,
     middleware::{
-        auth::authenticate_if_required, cors::handle_cors, security::add_security_headers,
+        auth::authenticate_if_required,
+        compression::{compression_layer, weaken_etag_when_encoded},
+        cors::handle_cors,
+        forwarded::{make_request_span, resolve_client},
+        security::add_security_headers,
     },
+    proxy::ProxyProtocolListener,
+    reload::{ReloadableApp, spawn_config_reloader},
+    shutdown::{serve_until_shutdown, shutdown_signal},
+    throttle::LoginThrottle,
+    tls::{self, CertificateResolver, TlsListener},
+    uploads::PartialUploads,
+    vhost::VirtualHosts,
 };
-use crate::config::AppConfig;
+use crate::config::{AppConfig, Cli, VhostConfig};
+use crate::utils::access::AccessRules;
+use crate::utils::cidr::TrustedProxies;
+use crate::utils::passwords::Users;
+use crate::utils::share::ShareLinks;
 
 /// shared application state
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub config: Arc<AppConfig>,
+    /// the root directory and every configured mount
+    pub mounts: Arc<Mounts>,
+    /// uploads still being written, cleaned up if they never complete
+    pub partial_uploads: Arc<PartialUploads>,
+    /// proxies whose forwarding headers are believed
+    pub trusted_proxies: Arc<TrustedProxies>,
+    /// users allowed to authenticate to this site
+    pub users: Arc<Users>,
+    /// per-path permissions of users and groups
+    pub access_rules: Arc<AccessRules>,
+    /// signs and checks share links
+    pub share_links: Arc<ShareLinks>,
+    /// failed logins per client and user, kept across reloads
+    pub login_throttle: Arc<LoginThrottle>,
 }
 
 impl AppState {
     pub fn new(config: AppConfig) -> Self {
+        let mounts = Mounts::new(&config);
+
+        // entries are validated at startup, so a failure here leaves no proxy trusted
+        let trusted_proxies =
+            TrustedProxies::new(&config.server.trusted_proxies).unwrap_or_default();
+
         Self {
+            mounts: Arc::new(mounts),
+            partial_uploads: Arc::default(),
+            trusted_proxies: Arc::new(trusted_proxies),
+            users: Arc::new(load_users(&config)),
+            access_rules: Arc::new(load_access_rules(&config)),
+            share_links: Arc::new(ShareLinks::new(config.security.share_secret.as_deref(), "")),
+            login_throttle: Arc::default(),
             config: Arc::new(config),
         }
     }
+
+    /// state for another configuration of this process, sharing upload
+    /// tracking, login throttling and the trusted proxies the listeners were
+    /// set up with
+    pub fn with_config(&self, config: AppConfig) -> Self {
+        Self {
+            mounts: Arc::new(Mounts::new(&config)),
+            partial_uploads: self.partial_uploads.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            users: Arc::new(load_users(&config)),
+            access_rules: Arc::new(load_access_rules(&config)),
+            share_links: Arc::new(ShareLinks::new(config.security.share_secret.as_deref(), "")),
+            login_throttle: self.login_throttle.clone(),
+            config: Arc::new(config),
+        }
+    }
+
+    /// state for a virtual host's site, with failed logins counted and share
+    /// links signed apart from the other sites
+    pub fn for_vhost(&self, vhost: &VhostConfig) -> Self {
+        let config = self.config.vhost_config(vhost);
+        let share_links = ShareLinks::new(
+            config.security.share_secret.as_deref(),
+            vhost.canonical_name(),
+        );
+        Self {
+            login_throttle: self.login_throttle.site(&vhost.label()),
+            share_links: Arc::new(share_links),
+            ..self.with_config(config)
+        }
+    }
+}
+
+// users are validated with the rest of the configuration; should the htpasswd
+// file break in between, nobody can authenticate rather than everybody
+fn load_users(config: &AppConfig) -> Users {
+    Users::load(&config.security).unwrap_or_else(|err| {
+        error!("failed to load users: {}", err);
+        Users::default()
+    })
+}
+
+// like the users, a broken rule list refuses every request instead of
+// falling back to the security policy
+fn load_access_rules(config: &AppConfig) -> AccessRules {
+    AccessRules::new(&config.access_rules).unwrap_or_else(|err| {
+        error!("failed to load access rules: {}", err);
+        AccessRules::deny_all()
+    })
 }
 
 /// create the axum application with all routes and middleware
-pub fn create_app(config: AppConfig) -> Router {
-    create_app_impl(config)
+pub fn create_app(app_state: AppState) -> Router {
+    create_app_impl(app_state)
 }
 
 /// create app for testing
 #[cfg(feature = "test-helpers")]
 #[allow(dead_code)]
 pub fn create_test_app(config: AppConfig) -> Router {
-    create_app_impl(config)
+    create_app_impl(AppState::new(config))
 }
 
 /// internal implementation for app creation
-fn create_app_impl(config: AppConfig) -> Router {
-    let app_state = AppState::new(config);
+fn create_app_impl(app_state: AppState) -> Router {
+    if app_state.config.vhosts.is_empty() {
+        return create_site(app_state);
+    }
+
+    // every virtual host is a complete site, picked by the request's host name
+    let mut hosts = VirtualHosts::default();
+    let mut default = None;
+    for vhost in &app_state.config.vhosts {
+        let site = create_site(app_state.for_vhost(vhost));
+        for name in &vhost.names {
+            // names are validated at startup
+            if let Ok(pattern) = name.parse() {
+                hosts.add(pattern, site.clone());
+            }
+        }
+        if vhost.default {
+            default = Some(site);
+        }
+    }
+
+    hosts.into_router(default.unwrap_or_else(|| create_site(app_state)))
+}
+
+/// the routes and middleware serving one site
+fn create_site(app_state: AppState) -> Router {
     let body_limit =
         usize::try_from(app_state.config.upload.max_request_size).unwrap_or(usize::MAX);
 
-    Router::new()
+    let routes = Router::new()
         // static asset routes
         .route("/__soop_static/{*path}", get(serve_static_asset))
+        .route(SHARE_PATH, post(create_share_link))
         // root route
         .route("/", get(handle_root_request))
         .route("/", post(handle_root_upload_request))
@@ -66,55 +191,113 @@ fn create_app_impl(config: AppConfig) -> Router {
         .route("/{*path}", post(handle_upload_request))
         // main file serving route
         .route("/{*path}", get(handle_request))
-        // middleware stack
+        // authentication sees paths relative to the base path to pick each mount's policy
         .layer(middleware::from_fn_with_state(
             app_state.clone(),
             authenticate_if_required,
-        ))
-        .layer(DefaultBodyLimit::max(body_limit))
+        ));
+
+    // mount everything under the base path; requests outside it fall through to a 404
+    let routes = match app_state.config.base_path() {
+        "" => routes,
+        base_path => Router::new().nest_service(base_path, routes.with_state(app_state.clone())),
+    };
+
+    let mut router = routes
+        // middleware stack
+        .layer(DefaultBodyLimit::max(body_limit));
+
+    if app_state.config.compression.enabled {
+        router = router
+            .layer(compression_layer(&app_state.config.compression))
+            .layer(middleware::from_fn(weaken_etag_when_encoded));
+    }
+
+    router
         .layer(middleware::from_fn_with_state(
             app_state.clone(),
             handle_cors,
         ))
         .layer(middleware::from_fn(add_security_headers))
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            resolve_client,
+        ))
         .with_state(app_state)
 }
 
-/// start the http server
-pub async fn start_server(config: AppConfig) -> Result<()> {
-    let app = create_app(config.clone());
+/// start the http server; `cli` is kept to reload the configuration it was loaded from
+pub async fn start_server(cli: Cli, config: AppConfig) -> Result<()> {
+    let app_state = AppState::new(config.clone());
+    let partial_uploads = app_state.partial_uploads.clone();
+    let trusted_proxies = app_state.trusted_proxies.clone();
+    let mounts = app_state.mounts.clone();
+    let app = ReloadableApp::new(create_app(app_state.clone()));
+    spawn_config_reloader(cli, app_state, app.clone());
+    let app = app.router();
 
-    // resolve hostname to socket address
-    let host_port = format!("{}:{}", config.server.host, config.server.port);
-    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(&host_port)
-        .await
-        .with_context(|| format!("failed to resolve hostname: {host_port}"))?
-        .collect();
+    // sockets passed in by systemd take the place of the configured listen addresses
+    #[cfg(unix)]
+    let inherited = systemd::inherited_listeners()?;
+    #[cfg(unix)]
+    let socket_activated = !inherited.is_empty();
+    #[cfg(not(unix))]
+    let socket_activated = false;
 
-    if addrs.is_empty() {
-        return Err(anyhow::anyhow!(
-            "hostname '{}' did not resolve to any addresses",
-            config.server.host
-        ));
-    }
+    // resolve every listen address, including all addresses of a hostname
+    let (tcp_entries, unix_paths) = if socket_activated {
+        (Vec::new(), Vec::new())
+    } else {
+        listen::split_listen_entries(&listen::listen_entries(&config.server))
+    };
+    let addrs = listen::resolve_listen_addrs(&tcp_entries).await?;
 
-    // use the first resolved address
-    let addr = addrs[0];
-
-    // log hostname resolution if not an IP address
-    if config.server.host.parse::<std::net::IpAddr>().is_err() {
-        debug!("resolved hostname '{}' to {}", config.server.host, addr);
-    }
+    let certificates = load_certificate_resolver(&config)?;
 
     // log startup information
+    let scheme = if certificates.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut endpoints: Vec<String> = tcp_entries
+        .iter()
+        .map(|entry| format!("{scheme}://{entry}"))
+        .chain(
+            unix_paths
+                .iter()
+                .map(|path| format!("unix:{}", path.display())),
+        )
+        .collect();
+    #[cfg(unix)]
+    endpoints.extend(inherited.endpoints(scheme));
     info!(
-        "starting soop3 v{} at http://{}:{}",
+        "starting soop3 v{} at {}",
         env!("CARGO_PKG_VERSION"),
-        config.server.host,
-        config.server.port
+        endpoints.join(", ")
     );
+    if let Some(resolver) = &certificates {
+        info!(
+            "tls certificate sha-256 fingerprint: {}",
+            resolver.fingerprint()
+        );
+    }
     info!("public dir: {}", config.server.public_dir.display());
+    for mount in mounts.iter() {
+        info!("mounted {} at {}", mount.dir.display(), mount.path);
+    }
+    if !config.base_path().is_empty() {
+        info!("serving under base path {}", config.base_path());
+    }
+    for vhost in &config.vhosts {
+        info!(
+            "virtual host {}: {}",
+            vhost.label(),
+            vhost.server.public_dir.display()
+        );
+    }
 
     if config.server.enable_upload {
         info!(
@@ -125,13 +308,213 @@ pub async fn start_server(config: AppConfig) -> Result<()> {
     }
 
     // start the server
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .context("failed to bind to address")?;
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut listeners = listen::bind_all(&addrs)?;
+    for addr in &addrs {
+        info!("server listening on {}", addr);
+    }
+    #[cfg(unix)]
+    if socket_activated {
+        info!("using sockets passed in by systemd");
+        listeners.extend(inherited.tcp);
+    }
+    #[cfg(unix)]
+    let notifier = systemd::Notifier::from_env().map(Arc::new);
 
-    info!("server listening on {}", addr);
+    let shutdown = CancellationToken::new();
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout);
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        let partial_uploads = partial_uploads.clone();
+        #[cfg(unix)]
+        let notifier = notifier.clone();
+        async move {
+            shutdown_signal().await;
+            #[cfg(unix)]
+            if let Some(notifier) = &notifier {
+                notifier.stopping();
+            }
+            info!(
+                "draining open connections for up to {}s",
+                drain_timeout.as_secs()
+            );
+            if !partial_uploads.is_empty() {
+                info!("waiting for {} uploads in progress", partial_uploads.len());
+            }
+            shutdown.cancel();
+        }
+    });
+
+    let mut servers: Vec<BoxFuture<'static, Result<()>>> = Vec::new();
+
+    // unix sockets sit behind a local proxy and always speak plain http
+    for path in unix_paths {
+        #[cfg(unix)]
+        {
+            let (listener, socket_file) = listen::bind_unix(
+                &path,
+                config.server.unix_socket_mode,
+                config.server.unix_socket_owner.as_deref(),
+            )
+            .await?;
+            info!("server listening on unix:{}", path.display());
+
+            let server =
+                serve_until_shutdown(listener, app.clone(), shutdown.clone(), drain_timeout);
+            servers.push(Box::pin(async move {
+                let result = server.await;
+                socket_file.remove();
+                result
+            }));
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!(
+            "unix socket listeners are not supported on this platform: {}",
+            path.display()
+        );
+    }
+    // inherited unix sockets belong to systemd and are left in place
+    #[cfg(unix)]
+    for listener in inherited.unix {
+        servers.push(Box::pin(serve_until_shutdown(
+            listener,
+            app.clone(),
+            shutdown.clone(),
+            drain_timeout,
+        )));
+    }
 
-    axum::serve(listener, app).await.context("server error")?;
+    let proxy_protocol = config.server.proxy_protocol.then_some(&trusted_proxies);
+    if proxy_protocol.is_some() {
+        info!("expecting proxy protocol headers on tcp connections");
+    }
+    match certificates {
+        None => {
+            for listener in listeners {
+                servers.push(serve_tcp(
+                    listener,
+                    proxy_protocol,
+                    None,
+                    app.clone(),
+                    shutdown.clone(),
+                    drain_timeout,
+                )?);
+            }
+        }
+        Some(resolver) => {
+            tls::spawn_certificate_reloader(resolver.clone());
+            let tls_config = tls::server_config(resolver)?;
+            let bound: Vec<SocketAddr> = listeners
+                .iter()
+                .filter_map(|listener| listener.local_addr().ok())
+                .collect();
+            for listener in listeners {
+                servers.push(serve_tcp(
+                    listener,
+                    proxy_protocol,
+                    Some(&tls_config),
+                    app.clone(),
+                    shutdown.clone(),
+                    drain_timeout,
+                )?);
+            }
+
+            if let Some(redirect_port) = config.tls.redirect_http_port {
+                // one redirect listener per address, pointing at that address's https port
+                let mut redirects: Vec<(SocketAddr, u16)> = Vec::new();
+                for addr in &bound {
+                    if !redirects.iter().any(|(seen, _)| seen.ip() == addr.ip()) {
+                        redirects.push((SocketAddr::new(addr.ip(), redirect_port), addr.port()));
+                    }
+                }
+
+                let redirect_addrs: Vec<SocketAddr> =
+                    redirects.iter().map(|(addr, _)| *addr).collect();
+                let redirect_listeners = listen::bind_all(&redirect_addrs)
+                    .context("failed to bind http redirect address")?;
+
+                for (listener, (addr, https_port)) in redirect_listeners.into_iter().zip(redirects)
+                {
+                    info!("redirecting http on {} to https", addr);
+                    servers.push(serve_tcp(
+                        listener,
+                        proxy_protocol,
+                        None,
+                        tls::redirect_router(https_port),
+                        shutdown.clone(),
+                        drain_timeout,
+                    )?);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(notifier) = &notifier {
+        notifier.ready();
+        systemd::spawn_watchdog(notifier.clone(), shutdown.clone());
+    }
+
+    let result = future::try_join_all(servers).await;
+    // stop the remaining listeners if one of them failed
+    shutdown.cancel();
+
+    // uploads cut off by the drain timeout never reach their final name
+    let removed = partial_uploads.remove_all();
+    if removed > 0 {
+        warn!("removed {} incomplete upload files", removed);
+    }
+    result?;
+    info!("server stopped");
 
     Ok(())
 }
+
+// serve a tcp listener, reading proxy protocol headers before any tls handshake
+fn serve_tcp(
+    listener: TcpListener,
+    proxy_protocol: Option<&Arc<TrustedProxies>>,
+    tls_config: Option<&Arc<rustls::ServerConfig>>,
+    app: Router,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    let server: BoxFuture<'static, Result<()>> = match (proxy_protocol, tls_config) {
+        (None, None) => Box::pin(serve_until_shutdown(listener, app, shutdown, drain_timeout)),
+        (None, Some(tls_config)) => {
+            let listener = TlsListener::new(listener, tls_config.clone())
+                .context("failed to start tls listener")?;
+            Box::pin(serve_until_shutdown(listener, app, shutdown, drain_timeout))
+        }
+        (Some(trusted_proxies), None) => {
+            let listener = ProxyProtocolListener::new(listener, trusted_proxies.clone())
+                .context("failed to start proxy protocol listener")?;
+            Box::pin(serve_until_shutdown(listener, app, shutdown, drain_timeout))
+        }
+        (Some(trusted_proxies), Some(tls_config)) => {
+            let listener = ProxyProtocolListener::new(listener, trusted_proxies.clone())
+                .context("failed to start proxy protocol listener")?;
+            let listener = TlsListener::new(listener, tls_config.clone())
+                .context("failed to start tls listener")?;
+            Box::pin(serve_until_shutdown(listener, app, shutdown, drain_timeout))
+        }
+    };
+    Ok(server)
+}
+
+// load configured certificates or generate a self-signed one when tls is enabled
+fn load_certificate_resolver(config: &AppConfig) -> Result<Option<Arc<CertificateResolver>>> {
+    let resolver = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert_path), Some(key_path)) => {
+            info!("loading tls certificate: {}", cert_path.display());
+            CertificateResolver::load(cert_path, key_path)?
+        }
+        _ if config.tls.self_signed => {
+            info!("generating self-signed tls certificate");
+            CertificateResolver::fixed(tls::self_signed_certificate(&config.server.host)?)
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Arc::new(resolver)))
+}
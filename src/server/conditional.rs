@@ -0,0 +1,235 @@
+// conditional request evaluation using etag and last-modified validators
+
+use axum::http::{HeaderMap, HeaderValue, Method, header};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// cache validators derived from file metadata
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+/// outcome of evaluating request preconditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+impl Validators {
+    /// build validators from file size and modification time
+    pub fn new(size: u64, modified: SystemTime) -> Self {
+        let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        Self {
+            etag: format!(
+                "\"{:x}-{:x}-{:x}\"",
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos(),
+                size
+            ),
+            // http dates only carry whole seconds
+            last_modified: UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        }
+    }
+
+    /// add etag and last-modified headers to a response
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(self.last_modified)) {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+    }
+}
+
+/// evaluate if-match, if-unmodified-since, if-none-match and if-modified-since
+/// in the order defined by rfc 9110 section 13.2.2
+pub fn evaluate_preconditions(
+    headers: &HeaderMap,
+    method: &Method,
+    validators: &Validators,
+) -> Precondition {
+    let is_read = method == Method::GET || method == Method::HEAD;
+
+    if let Some(if_match) = header_str(headers, header::IF_MATCH) {
+        if !etag_list_matches(if_match, &validators.etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = header_date(headers, header::IF_UNMODIFIED_SINCE)
+        && validators.last_modified > since
+    {
+        return Precondition::Failed;
+    }
+
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        if etag_list_matches(if_none_match, &validators.etag, false) {
+            return if is_read {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if is_read
+        && let Some(since) = header_date(headers, header::IF_MODIFIED_SINCE)
+        && validators.last_modified <= since
+    {
+        return Precondition::NotModified;
+    }
+
+    Precondition::Proceed
+}
+
+/// check whether a range request should be honoured according to if-range
+pub fn if_range_matches(headers: &HeaderMap, validators: &Validators) -> bool {
+    let Some(value) = header_str(headers, header::IF_RANGE) else {
+        return true;
+    };
+
+    let value = value.trim();
+    if value.starts_with('"') || value.starts_with("W/") {
+        // if-range requires a strong comparison, so weak tags never match
+        return strong_etag_eq(value, &validators.etag);
+    }
+
+    match httpdate::parse_http_date(value) {
+        Ok(date) => date == validators.last_modified,
+        Err(_) => false,
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    // invalid dates are ignored as if the header was absent
+    header_str(headers, name).and_then(|value| httpdate::parse_http_date(value.trim()).ok())
+}
+
+fn etag_list_matches(list: &str, current: &str, strong: bool) -> bool {
+    list.split(',').map(str::trim).any(|candidate| {
+        if candidate == "*" {
+            true
+        } else if strong {
+            strong_etag_eq(candidate, current)
+        } else {
+            weak_etag_eq(candidate, current)
+        }
+    })
+}
+
+fn strong_etag_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+fn weak_etag_eq(a: &str, b: &str) -> bool {
+    let a = a.strip_prefix("W/").unwrap_or(a);
+    let b = b.strip_prefix("W/").unwrap_or(b);
+    a == b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators::new(42, UNIX_EPOCH + Duration::new(1_700_000_000, 500))
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn etag_comparison() {
+        let v = validators();
+        let weak = format!("W/{}", v.etag);
+
+        assert!(etag_list_matches(&v.etag, &v.etag, true));
+        assert!(!etag_list_matches(&weak, &v.etag, true));
+        assert!(etag_list_matches(&weak, &v.etag, false));
+        assert!(etag_list_matches(
+            &format!("\"other\", {}", v.etag),
+            &v.etag,
+            false
+        ));
+        assert!(etag_list_matches("*", &v.etag, true));
+        assert!(!etag_list_matches("\"other\"", &v.etag, false));
+    }
+
+    #[test]
+    fn precondition_ordering() {
+        let v = validators();
+        let last_modified = httpdate::fmt_http_date(v.last_modified);
+
+        let h = headers(&[(header::IF_NONE_MATCH, &v.etag)]);
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::GET, &v),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::POST, &v),
+            Precondition::Failed
+        );
+
+        // if-none-match takes precedence over if-modified-since
+        let h = headers(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, &last_modified),
+        ]);
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::GET, &v),
+            Precondition::Proceed
+        );
+
+        let h = headers(&[(header::IF_MATCH, "\"other\"")]);
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::GET, &v),
+            Precondition::Failed
+        );
+
+        let h = headers(&[(header::IF_UNMODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT")]);
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::GET, &v),
+            Precondition::Failed
+        );
+
+        let h = headers(&[(header::IF_MODIFIED_SINCE, "not a date")]);
+        assert_eq!(
+            evaluate_preconditions(&h, &Method::GET, &v),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_range_validation() {
+        let v = validators();
+        let last_modified = httpdate::fmt_http_date(v.last_modified);
+
+        assert!(if_range_matches(&HeaderMap::new(), &v));
+        assert!(if_range_matches(
+            &headers(&[(header::IF_RANGE, &v.etag)]),
+            &v
+        ));
+        assert!(if_range_matches(
+            &headers(&[(header::IF_RANGE, &last_modified)]),
+            &v
+        ));
+        assert!(!if_range_matches(
+            &headers(&[(header::IF_RANGE, &format!("W/{}", v.etag))]),
+            &v
+        ));
+        assert!(!if_range_matches(
+            &headers(&[(header::IF_RANGE, "Thu, 01 Jan 1970 00:00:00 GMT")]),
+            &v
+        ));
+    }
+}
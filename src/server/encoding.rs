@@ -0,0 +1,102 @@
+// accept header q-values and encoding negotiation for precompressed sibling files
+
+use axum::http::{HeaderMap, header};
+
+/// content codings that may be served from precompressed siblings,
+/// in server preference order with their file extensions
+pub const PRECOMPRESSED_ENCODINGS: &[(&str, &str)] =
+    &[("br", "br"), ("zstd", "zst"), ("gzip", "gz")];
+
+/// the lowercased items of an `Accept`-style header with their q-values,
+/// which default to 1
+pub fn quality_values(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let name = params.next()?.trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((name, quality))
+        })
+        .collect()
+}
+
+/// list the precompressed encodings acceptable to the client, best first
+pub fn acceptable_encodings(headers: &HeaderMap) -> Vec<(&'static str, &'static str)> {
+    let Some(accept) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let preferences = quality_values(accept);
+
+    let quality_of = |coding: &str| {
+        let exact = preferences
+            .iter()
+            .find(|(name, _)| name == coding || (coding == "gzip" && name == "x-gzip"));
+        let wildcard = preferences.iter().find(|(name, _)| name == "*");
+        exact.or(wildcard).map(|(_, q)| *q).unwrap_or(0.0)
+    };
+
+    let mut accepted: Vec<_> = PRECOMPRESSED_ENCODINGS
+        .iter()
+        .enumerate()
+        .map(|(rank, &(coding, extension))| (rank, quality_of(coding), coding, extension))
+        .filter(|(_, quality, _, _)| *quality > 0.0)
+        .collect();
+
+    // highest quality wins, ties broken by server preference
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    accepted
+        .into_iter()
+        .map(|(_, _, coding, extension)| (coding, extension))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn codings(accept: &str) -> Vec<&'static str> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_str(accept).unwrap(),
+        );
+        acceptable_encodings(&headers)
+            .into_iter()
+            .map(|(coding, _)| coding)
+            .collect()
+    }
+
+    #[test]
+    fn parses_quality_values() {
+        assert_eq!(
+            quality_values("Text/HTML;level=1;q=0.5, application/json, ,*/*;q=x"),
+            vec![
+                ("text/html".to_string(), 0.5),
+                ("application/json".to_string(), 1.0),
+                ("*/*".to_string(), 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn negotiates_precompressed_encodings() {
+        assert_eq!(codings("gzip, deflate, br"), vec!["br", "gzip"]);
+        assert_eq!(codings("gzip;q=1.0, br;q=0.5"), vec!["gzip", "br"]);
+        assert_eq!(codings("*;q=0.1, br;q=0"), vec!["zstd", "gzip"]);
+        assert_eq!(codings("identity"), Vec::<&str>::new());
+        assert_eq!(codings("x-gzip"), vec!["gzip"]);
+        assert!(acceptable_encodings(&HeaderMap::new()).is_empty());
+    }
+}
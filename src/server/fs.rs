@@ -1,15 +1,21 @@
 // filesystem helpers for safe path resolution and directory reads
 
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, request::Parts},
+};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 use thiserror::Error;
 use tokio::fs::File;
 use tracing::warn;
 
+use crate::config::{AppConfig, SecurityPolicy};
 use crate::utils::{
     files::{DirectoryEntry, collect_directory_entries, get_mime_type},
-    ignore::filter_with_ignore_patterns,
-    paths::PathTraversalError,
-    paths::join_path_jailed,
+    ignore::IgnoreCache,
+    paths::{PathTraversalError, decode_request_path, join_decoded_path_jailed},
 };
 
 #[derive(Debug, Error)]
@@ -20,49 +26,238 @@ pub enum FsError {
     Io(#[from] std::io::Error),
 }
 
+/// the percent-decoded, normalised request path; the auth middleware works it
+/// out once so mount lookup, access rules and file serving all see the same path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestPath(String);
+
+impl RequestPath {
+    pub fn from_uri(path: &str) -> Result<Self, PathTraversalError> {
+        decode_request_path(path).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestPath {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(path) = parts.extensions.get::<Self>() {
+            return Ok(path.clone());
+        }
+        Self::from_uri(parts.uri.path()).map_err(|err| {
+            warn!(
+                "rejecting request with bad path: {} - {}",
+                parts.uri.path(),
+                err
+            );
+            StatusCode::BAD_REQUEST
+        })
+    }
+}
+
 pub struct FileMeta {
     pub file: File,
     pub size: u64,
+    pub modified: SystemTime,
     pub mime_type: String,
 }
 
+/// a directory tree served under a url path; the root mount serves
+/// `public_dir` at `/`
+#[derive(Debug)]
+pub struct Mount {
+    /// url path without a trailing slash, empty for the root mount
+    pub path: String,
+    pub dir: PathBuf,
+    pub upload_dir: PathBuf,
+    pub enable_upload: bool,
+    pub policy: SecurityPolicy,
+    /// compiled listing ignore rules, present when an ignore file is configured
+    pub ignore_rules: Option<Arc<IgnoreCache>>,
+}
+
+impl Mount {
+    /// the mount name shown as a virtual directory in the root listing
+    pub fn name(&self) -> &str {
+        self.path.trim_start_matches('/')
+    }
+}
+
+/// the root mount and every configured `[[mount]]`
+#[derive(Debug)]
+pub struct Mounts {
+    root: Arc<Mount>,
+    mounts: Vec<Arc<Mount>>,
+}
+
+impl Mounts {
+    pub fn new(config: &AppConfig) -> Self {
+        let root = Mount {
+            path: String::new(),
+            dir: config.server.public_dir.clone(),
+            upload_dir: config.upload_dir().clone(),
+            enable_upload: config.server.enable_upload,
+            policy: config.security.policy,
+            ignore_rules: config.listing.ignore_file.as_ref().map(|ignore_file| {
+                Arc::new(IgnoreCache::new(
+                    &config.server.public_dir,
+                    ignore_file,
+                    config.listing.enforce_ignore,
+                ))
+            }),
+        };
+
+        let mounts = config
+            .mounts
+            .iter()
+            .map(|mount| {
+                let ignore_file = mount
+                    .ignore_file
+                    .as_ref()
+                    .or(config.listing.ignore_file.as_ref());
+                Arc::new(Mount {
+                    path: format!("/{}", mount.path.trim_matches('/')),
+                    dir: mount.dir.clone(),
+                    upload_dir: mount.dir.clone(),
+                    enable_upload: mount.enable_upload.unwrap_or(config.server.enable_upload),
+                    policy: mount.policy.unwrap_or(config.security.policy),
+                    ignore_rules: ignore_file.map(|ignore_file| {
+                        Arc::new(IgnoreCache::new(
+                            &mount.dir,
+                            ignore_file,
+                            config.listing.enforce_ignore,
+                        ))
+                    }),
+                })
+            })
+            .collect();
+
+        Self {
+            root: Arc::new(root),
+            mounts,
+        }
+    }
+
+    /// the configured mounts, excluding the root
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Mount>> {
+        self.mounts.iter()
+    }
+
+    /// the mount serving the decoded `request_path` and the remaining path within
+    /// it, which keeps its leading slash and is empty for the bare mount path
+    pub fn resolve<'a>(&self, request_path: &'a str) -> (&Arc<Mount>, &'a str) {
+        self.mounts
+            .iter()
+            .find_map(|mount| {
+                let rest = request_path.strip_prefix(mount.path.as_str())?;
+                (rest.is_empty() || rest.starts_with('/')).then_some((mount, rest))
+            })
+            .unwrap_or((&self.root, request_path))
+    }
+
+    /// resolve a request path within the mount serving it, jailed to the mount directory
+    pub fn resolve_request_path(&self, request_path: &str) -> Result<ResolvedPath, FsError> {
+        let (mount, mount_path) = self.resolve(request_path);
+        Ok(ResolvedPath {
+            path: resolve_request_path(&mount.dir, mount_path)?,
+            mount: Arc::clone(mount),
+        })
+    }
+
+    /// every mount as a directory entry of the root listing
+    pub async fn virtual_entries(&self) -> Vec<DirectoryEntry> {
+        let mut entries = Vec::with_capacity(self.mounts.len());
+        for mount in &self.mounts {
+            let modified = tokio::fs::metadata(&mount.dir)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push(DirectoryEntry {
+                name: mount.name().to_string(),
+                size: 0,
+                modified,
+                is_dir: true,
+            });
+        }
+        entries
+    }
+}
+
+/// a request path resolved to a file or directory within its mount
+#[derive(Debug, Clone)]
+pub struct ResolvedPath {
+    pub mount: Arc<Mount>,
+    pub path: PathBuf,
+}
+
+/// jail a decoded request path to `public_dir`
 pub fn resolve_request_path(public_dir: &Path, request_path: &str) -> Result<PathBuf, FsError> {
-    let clean_path = request_path.strip_prefix('/').unwrap_or(request_path);
-    Ok(join_path_jailed(public_dir, clean_path)?)
+    Ok(join_decoded_path_jailed(public_dir, request_path)?)
 }
 
 pub async fn open_file_for_serving(file_path: &Path) -> Result<FileMeta, FsError> {
     let file = File::open(file_path).await?;
     let metadata = file.metadata().await?;
     let file_size = metadata.len();
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
     let mime_type = get_mime_type(file_path);
 
     Ok(FileMeta {
         file,
         size: file_size,
+        modified,
         mime_type,
     })
 }
 
-pub async fn collect_directory_entries_filtered(
-    dir_path: &Path,
+/// locate a precompressed sibling (e.g. `app.js.br`) for the first acceptable encoding
+/// siblings that resolve outside the served directory are ignored
+pub async fn find_precompressed_variant(
     public_dir: &Path,
-    ignore_file: Option<&PathBuf>,
-) -> Result<Vec<DirectoryEntry>, FsError> {
-    let entries = collect_directory_entries(dir_path).await?;
+    file_path: &Path,
+    encodings: &[(&'static str, &'static str)],
+) -> Option<(PathBuf, &'static str)> {
+    let file_name = file_path.file_name()?;
+    let canonical_base = public_dir.canonicalize().ok()?;
 
-    let Some(ignore_file) = ignore_file else {
-        return Ok(entries);
-    };
+    for &(encoding, extension) in encodings {
+        let mut sibling_name = file_name.to_os_string();
+        sibling_name.push(format!(".{extension}"));
+        let sibling = file_path.with_file_name(sibling_name);
 
-    match filter_with_ignore_patterns(&entries, public_dir, dir_path, Some(ignore_file)) {
-        Ok(filtered) => Ok(filtered),
-        Err(err) => {
+        let Ok(canonical) = tokio::fs::canonicalize(&sibling).await else {
+            continue;
+        };
+        if !canonical.starts_with(&canonical_base) {
             warn!(
-                "ignore file filtering failed: {}, continuing without filtering",
-                err
+                "ignoring precompressed sibling outside public dir: {}",
+                sibling.display()
             );
-            Ok(entries)
+            continue;
+        }
+        if tokio::fs::metadata(&canonical)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            return Some((canonical, encoding));
         }
     }
+
+    None
+}
+
+pub async fn collect_directory_entries_filtered(
+    dir_path: &Path,
+    ignore_rules: Option<&IgnoreCache>,
+) -> Result<Vec<DirectoryEntry>, FsError> {
+    let entries = collect_directory_entries(dir_path).await?;
+
+    Ok(match ignore_rules {
+        Some(ignore_rules) => ignore_rules.filter_entries(entries, dir_path),
+        None => entries,
+    })
 }
@@ -3,64 +3,108 @@
 use axum::{
     body::Body,
     extract::{OriginalUri, State},
-    http::{HeaderMap, Method, StatusCode, header},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
     response::Response,
 };
 use http_range_header::parse_range_header as parse_http_range;
 use std::io::ErrorKind;
-use std::path::{Path as StdPath, PathBuf};
+use std::ops::RangeInclusive;
+use std::path::Path as StdPath;
+use std::sync::Arc;
 use tokio::fs::{self as tokio_fs, File};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
 use tracing::{debug, error, info, instrument, warn};
 
 use super::assets::serve_embedded_favicon;
-use crate::server::{app::AppState, fs, listing};
+use crate::server::{
+    app::AppState,
+    conditional::{self, Precondition, Validators},
+    encoding,
+    fs::{self, Mount, RequestPath, ResolvedPath},
+    listing,
+    middleware::{compression::EncodedAtSource, forwarded::ClientInfo},
+    ranges::{self, ByteRangesBody},
+};
+use crate::utils::access::AccessPath;
+use crate::utils::files::get_mime_type;
+use crate::utils::paths::encode_path_segments;
 
 // handle root directory request
-#[instrument(skip(state, headers, uri))]
+#[instrument(skip(state, client, headers, uri, original_uri))]
 pub async fn handle_root_request(
     State(state): State<AppState>,
-    OriginalUri(uri): OriginalUri,
+    client: ClientInfo,
+    request_path: RequestPath,
+    uri: Uri,
+    OriginalUri(original_uri): OriginalUri,
     headers: HeaderMap,
     method: Method,
 ) -> Result<Response, StatusCode> {
-    handle_request_internal(state, uri.path().to_string(), headers, method).await
+    // under a base path both `/base` and `/base/` arrive here as `/`; an empty
+    // path sends the bare form through the trailing slash redirect
+    let path = if original_uri.path().ends_with('/') {
+        request_path.as_str()
+    } else {
+        ""
+    };
+
+    handle_request_internal(
+        state,
+        client,
+        path.to_string(),
+        uri.query().map(str::to_string),
+        headers,
+        method,
+    )
+    .await
 }
 
 // main request handler - routes to file or directory handling
-#[instrument(skip(state, headers, uri))]
+#[instrument(skip(state, client, headers, uri))]
 pub async fn handle_request(
     State(state): State<AppState>,
-    OriginalUri(uri): OriginalUri,
+    client: ClientInfo,
+    request_path: RequestPath,
+    uri: Uri,
     headers: HeaderMap,
     method: Method,
 ) -> Result<Response, StatusCode> {
-    handle_request_internal(state, uri.path().to_string(), headers, method).await
+    handle_request_internal(
+        state,
+        client,
+        request_path.as_str().to_string(),
+        uri.query().map(str::to_string),
+        headers,
+        method,
+    )
+    .await
 }
 
-// internal request handling logic
+// internal request handling logic; `file_path` is already percent-decoded
 async fn handle_request_internal(
     state: AppState,
+    client: ClientInfo,
     file_path: String,
+    query: Option<String>,
     headers: HeaderMap,
     method: Method,
 ) -> Result<Response, StatusCode> {
     info!("processing {} request", method.as_str());
 
-    // validate and resolve path securely
-    let resolved_path = match fs::resolve_request_path(&state.config.server.public_dir, &file_path)
-    {
-        Ok(path) => path,
+    // validate and resolve path securely within the mount serving it
+    let resolved = match state.mounts.resolve_request_path(&file_path) {
+        Ok(resolved) => resolved,
         Err(e) => {
             warn!("rejecting request with bad path: {} - {}", file_path, e);
             return Err(StatusCode::BAD_REQUEST);
         }
     };
 
+    let resolved_path = &resolved.path;
     debug!("resolved path: {}", resolved_path.display());
 
-    let metadata = match tokio_fs::metadata(&resolved_path).await {
+    let metadata = match tokio_fs::metadata(resolved_path).await {
         Ok(metadata) => Some(metadata),
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
         Err(err) => {
@@ -85,35 +129,126 @@ async fn handle_request_internal(
         return Err(StatusCode::NOT_FOUND);
     };
 
+    if is_hidden_by_ignore_rules(&state, &resolved.mount, resolved_path, metadata.is_dir()) {
+        warn!(
+            "refusing access to ignored path: {}",
+            resolved_path.display()
+        );
+        return Err(StatusCode::NOT_FOUND);
+    }
+
     if metadata.is_dir() {
-        handle_directory_request(state, resolved_path, file_path, headers, method).await
+        handle_directory_request(state, client, resolved, file_path, query, headers, method).await
     } else {
-        handle_file_request(resolved_path, headers, method).await
+        handle_file_request(&state, resolved, headers, method).await
     }
 }
 
 // handle requests for files with range support
 async fn handle_file_request(
-    file_path: PathBuf,
+    state: &AppState,
+    resolved: ResolvedPath,
     headers: HeaderMap,
     method: Method,
 ) -> Result<Response, StatusCode> {
+    let file_path = resolved.path;
     info!("serving file: {}", file_path.display());
     let is_head = method == Method::HEAD;
+    let precompressed_enabled = state.config.server.precompressed;
 
-    let file_meta = match fs::open_file_for_serving(&file_path).await {
+    let variant = if precompressed_enabled {
+        let encodings = encoding::acceptable_encodings(&headers);
+        fs::find_precompressed_variant(&resolved.mount.dir, &file_path, &encodings).await
+    } else {
+        None
+    };
+
+    let serve_path = match &variant {
+        Some((variant_path, encoding)) => {
+            info!(
+                "serving precompressed {} variant: {}",
+                encoding,
+                variant_path.display()
+            );
+            variant_path
+        }
+        None => &file_path,
+    };
+
+    let mut file_meta = match fs::open_file_for_serving(serve_path).await {
         Ok(meta) => meta,
         Err(err) => {
-            error!("failed to open file {}: {}", file_path.display(), err);
+            error!("failed to open file {}: {}", serve_path.display(), err);
             return Err(map_fs_error(&err));
         }
     };
-    let file = file_meta.file;
-    let file_size = file_meta.size;
-    let mime_type = file_meta.mime_type;
 
+    if variant.is_some() {
+        // the encoded sibling keeps the media type of the original file
+        file_meta.mime_type = get_mime_type(&file_path);
+    }
+
+    let validators = Validators::new(file_meta.size, file_meta.modified);
+
+    match conditional::evaluate_preconditions(&headers, &method, &validators) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            info!("not modified: {}", file_path.display());
+            return not_modified(&validators);
+        }
+        Precondition::Failed => {
+            warn!("precondition failed: {}", file_path.display());
+            return precondition_failed();
+        }
+    }
+
+    // a stale if-range validator means the client needs the full representation
+    let range_header = match headers.get(header::RANGE) {
+        Some(range_header) if conditional::if_range_matches(&headers, &validators) => {
+            Some(range_header)
+        }
+        Some(_) => {
+            info!("if-range validator did not match, ignoring range header");
+            None
+        }
+        None => None,
+    };
+
+    let mut response = serve_file_content(
+        file_meta.file,
+        file_meta.size,
+        file_meta.mime_type,
+        range_header,
+        is_head,
+    )
+    .await?;
+    validators.apply(response.headers_mut());
+
+    if let Some((_, encoding)) = variant {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        response.extensions_mut().insert(EncodedAtSource);
+    }
+    if precompressed_enabled {
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    Ok(response)
+}
+
+// serve the file body, honouring a range header when present
+async fn serve_file_content(
+    file: File,
+    file_size: u64,
+    mime_type: String,
+    range_header: Option<&HeaderValue>,
+    is_head: bool,
+) -> Result<Response, StatusCode> {
     // check for range header
-    if let Some(range_header) = headers.get(header::RANGE) {
+    if let Some(range_header) = range_header {
         let range_str = match range_header.to_str() {
             Ok(s) => s,
             Err(_) => {
@@ -124,18 +259,17 @@ async fn handle_file_request(
 
         match parse_http_range(range_str) {
             Ok(parsed_ranges) => {
-                match parsed_ranges.validate(file_size) {
-                    Ok(valid_ranges) => {
-                        if valid_ranges.is_empty() {
+                match ranges::satisfiable_ranges(&parsed_ranges, file_size) {
+                    Ok(valid_ranges) => match valid_ranges.as_slice() {
+                        [] => {
                             // no valid ranges, serve full file
                             if is_head {
                                 serve_full_file_head(file_size, mime_type)
                             } else {
                                 serve_full_file(file, file_size, mime_type).await
                             }
-                        } else {
-                            // serve first range (we don't support multipart ranges)
-                            let range = &valid_ranges[0];
+                        }
+                        [range] => {
                             let start = *range.start();
                             let end = *range.end();
                             info!(
@@ -148,7 +282,15 @@ async fn handle_file_request(
                                 serve_partial_file(file, start, end, file_size, mime_type).await
                             }
                         }
-                    }
+                        ranges => {
+                            info!(
+                                "serving multipart byteranges: {} ranges of {}",
+                                ranges.len(),
+                                file_size
+                            );
+                            serve_multipart_ranges(file, ranges, file_size, &mime_type, is_head)
+                        }
+                    },
                     Err(_) => {
                         warn!("range not satisfiable after validation");
                         range_not_satisfiable(file_size)
@@ -209,6 +351,35 @@ async fn serve_partial_file(
         })
 }
 
+// serve several ranges as a multipart/byteranges body
+fn serve_multipart_ranges(
+    file: File,
+    ranges: &[RangeInclusive<u64>],
+    file_size: u64,
+    mime_type: &str,
+    is_head: bool,
+) -> Result<Response, StatusCode> {
+    let byteranges = ByteRangesBody::new(ranges, file_size, mime_type);
+    let content_type = byteranges.content_type();
+    let content_length = byteranges.content_length();
+    let body = if is_head {
+        Body::empty()
+    } else {
+        Body::from_stream(byteranges.into_stream(file))
+    };
+
+    Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, content_length)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .body(body)
+        .map_err(|e| {
+            error!("failed to build multipart range response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
 fn serve_partial_file_head(
     start: u64,
     end: u64,
@@ -270,19 +441,29 @@ fn serve_full_file_head(file_size: u64, mime_type: String) -> Result<Response, S
 // handle requests for directories
 async fn handle_directory_request(
     state: AppState,
-    dir_path: PathBuf,
+    client: ClientInfo,
+    resolved: ResolvedPath,
     request_path: String,
+    query: Option<String>,
     headers: HeaderMap,
     method: Method,
 ) -> Result<Response, StatusCode> {
     let is_head = method == Method::HEAD;
+    let dir_path = &resolved.path;
+    // links and redirects go through any proxy prefix and the base path
+    let root = client.public_url(state.config.base_path());
 
     // ensure path ends with slash for directories
     if !request_path.ends_with('/') {
         info!("redirecting directory request to add trailing slash");
+        let request_path = encode_path_segments(&request_path);
+        let location = match &query {
+            Some(query) => format!("{root}{request_path}/?{query}"),
+            None => format!("{root}{request_path}/"),
+        };
         return Response::builder()
             .status(StatusCode::MOVED_PERMANENTLY)
-            .header(header::LOCATION, format!("{request_path}/"))
+            .header(header::LOCATION, location)
             .body(Body::empty())
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
     }
@@ -293,9 +474,15 @@ async fn handle_directory_request(
         let index_path = dir_path.join(index_file);
         match tokio_fs::metadata(&index_path).await {
             Ok(metadata) => {
-                if metadata.is_file() {
+                if metadata.is_file()
+                    && !is_hidden_by_ignore_rules(&state, &resolved.mount, &index_path, false)
+                {
                     info!("serving index file: {}", index_path.display());
-                    return handle_file_request(index_path, headers, method).await;
+                    let index = ResolvedPath {
+                        mount: Arc::clone(&resolved.mount),
+                        path: index_path,
+                    };
+                    return handle_file_request(&state, index, headers, method).await;
                 }
             }
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
@@ -312,43 +499,104 @@ async fn handle_directory_request(
 
     // generate directory listing
     info!("serving directory listing: {}", dir_path.display());
-    generate_directory_listing(&state, &dir_path, &request_path, is_head).await
+    let params = listing::ListingParams::from_query(query.as_deref());
+    generate_directory_listing(
+        &state,
+        &resolved,
+        &client,
+        &request_path,
+        &params,
+        &headers,
+        is_head,
+    )
+    .await
 }
 
-// generate html directory listing
+// generate html or json directory listing
 async fn generate_directory_listing(
     state: &AppState,
-    dir_path: &StdPath,
+    resolved: &ResolvedPath,
+    client: &ClientInfo,
     request_path: &str,
+    params: &listing::ListingParams,
+    headers: &HeaderMap,
     is_head: bool,
 ) -> Result<Response, StatusCode> {
+    let dir_path = &resolved.path;
+    let root = client.public_url(state.config.base_path());
+
     // collect directory entries
-    let mut entries = fs::collect_directory_entries_filtered(
-        dir_path,
-        &state.config.server.public_dir,
-        state.config.listing.ignore_file.as_ref(),
-    )
-    .await
-    .map_err(|err| {
-        error!("failed to read directory {}: {}", dir_path.display(), err);
-        map_fs_error(&err)
-    })?;
+    let mut entries =
+        fs::collect_directory_entries_filtered(dir_path, resolved.mount.ignore_rules.as_deref())
+            .await
+            .map_err(|err| {
+                error!("failed to read directory {}: {}", dir_path.display(), err);
+                map_fs_error(&err)
+            })?;
+
+    // mounts appear as directories of the root listing, shadowing real entries
+    if resolved.mount.path.is_empty() && request_path == "/" {
+        let mounts = state.mounts.virtual_entries().await;
+        entries.retain(|entry| !mounts.iter().any(|mount| mount.name == entry.name));
+        entries.extend(mounts);
+    }
+
+    // hide what the access rules would refuse this user
+    if !state.access_rules.is_empty() {
+        let dir = AccessPath::from_decoded(request_path);
+        entries.retain(|entry| {
+            dir.as_ref().is_some_and(|dir| {
+                state.access_rules.is_visible(
+                    &dir.child(&entry.name, entry.is_dir),
+                    client.user.as_deref(),
+                    &state.users,
+                )
+            })
+        });
+    }
 
-    listing::sort_entries(&mut entries);
-    let html = listing::build_listing_html(&entries, request_path);
+    listing::filter_entries(&mut entries, params.filter.as_deref());
+    listing::sort_entries(&mut entries, params.sort, params.order);
+
+    let (content_type, body) = if listing::wants_json(params, headers) {
+        let json = listing::build_listing_json(&entries, &root, request_path).map_err(|err| {
+            error!("failed to serialize directory listing: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        ("application/json", json)
+    } else {
+        (
+            "text/html; charset=utf-8",
+            listing::build_listing_html(&entries, &root, request_path, params),
+        )
+    };
 
     Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-        .header(header::CONTENT_LENGTH, html.len().to_string())
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body.len().to_string())
+        .header(header::VARY, "accept")
         .body(if is_head {
             Body::empty()
         } else {
-            Body::from(html)
+            Body::from(body)
         })
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+// ignored paths are only hidden from direct access when enforcement is enabled
+fn is_hidden_by_ignore_rules(
+    state: &AppState,
+    mount: &Mount,
+    path: &StdPath,
+    is_dir: bool,
+) -> bool {
+    match (&mount.ignore_rules, state.config.listing.enforce_ignore) {
+        (Some(ignore_rules), true) => ignore_rules.is_ignored(path, is_dir),
+        _ => false,
+    }
+}
+
 fn map_fs_error(err: &fs::FsError) -> StatusCode {
     match err {
         fs::FsError::InvalidPath(_) => StatusCode::BAD_REQUEST,
@@ -372,3 +620,19 @@ fn range_not_satisfiable(file_size: u64) -> Result<Response, StatusCode> {
         .body(Body::empty())
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
+
+fn not_modified(validators: &Validators) -> Result<Response, StatusCode> {
+    let mut response = Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .body(Body::empty())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    validators.apply(response.headers_mut());
+    Ok(response)
+}
+
+fn precondition_failed() -> Result<Response, StatusCode> {
+    Response::builder()
+        .status(StatusCode::PRECONDITION_FAILED)
+        .body(Body::empty())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
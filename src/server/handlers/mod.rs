@@ -2,4 +2,5 @@
 
 pub mod assets;
 pub mod files;
+pub mod share;
 pub mod upload;
@@ -0,0 +1,150 @@
+// minting signed share links for logged in users
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::Response,
+};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::server::{
+    app::AppState,
+    middleware::{auth::parse_bearer_token, forwarded::ClientInfo},
+};
+use crate::utils::{
+    access::{Access, AccessPath},
+    share::parse_duration,
+};
+
+/// the endpoint that mints share links, always behind authentication
+pub const SHARE_PATH: &str = "/__soop_share";
+
+/// query parameters of a mint request
+#[derive(Debug)]
+struct ShareParams {
+    path: Option<String>,
+    ttl: String,
+    method: Method,
+    prefix: bool,
+}
+
+impl ShareParams {
+    fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let mut params = Self {
+            path: None,
+            ttl: "24h".to_string(),
+            method: Method::GET,
+            prefix: false,
+        };
+
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "path" => params.path = Some(value.into_owned()),
+                "ttl" => params.ttl = value.into_owned(),
+                "method" => {
+                    params.method = Method::from_bytes(value.to_ascii_uppercase().as_bytes())
+                        .map_err(|_| format!("invalid method '{value}'"))?;
+                }
+                "prefix" => params.prefix = matches!(value.as_ref(), "" | "1" | "true"),
+                _ => {}
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+#[derive(Serialize)]
+struct ShareResponse {
+    url: String,
+    expires: String,
+}
+
+/// `POST /__soop_share?path=/docs/a.pdf&ttl=24h[&method=POST][&prefix=true]`
+/// answers with the signed url; users and tokens can only share what they may
+/// access themselves
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    if !state.share_links.is_enabled() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let user = client.user.as_deref().ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let bad_request = |reason: &dyn std::fmt::Display| {
+        warn!("rejecting share link request from {}: {}", user, reason);
+        StatusCode::BAD_REQUEST
+    };
+    let params = ShareParams::from_query(uri.query()).map_err(|err| bad_request(&err))?;
+    let path = params
+        .path
+        .as_deref()
+        .ok_or_else(|| bad_request(&"missing path"))?;
+    let target = AccessPath::from_uri(path)
+        .filter(|_| path.starts_with('/'))
+        .ok_or_else(|| bad_request(&format!("invalid path '{path}'")))?;
+    let ttl = parse_duration(&params.ttl).map_err(|err| bad_request(&err))?;
+
+    if matches!(
+        state
+            .access_rules
+            .evaluate(&params.method, &target, Some(user), &state.users),
+        Access::Deny | Access::Authenticate
+    ) {
+        warn!("access rules deny {} sharing {}", user, path);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // a token's path prefixes cover everything below the target once they
+    // cover the target itself
+    if let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_bearer_token)
+        && !state
+            .users
+            .verify_token(token)
+            .is_some_and(|(_, api_token)| api_token.covers(&params.method, &target))
+    {
+        warn!("token {} may not share {} {}", user, params.method, path);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let expires = chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+        .ok_or_else(|| bad_request(&"ttl is too long"))?;
+    let signed = state
+        .share_links
+        .sign(
+            path,
+            params.prefix,
+            &params.method,
+            Some(user),
+            expires.timestamp(),
+        )
+        .map_err(|err| bad_request(&err))?;
+    info!(
+        "{} shared {} {} until {}",
+        user, params.method, path, expires
+    );
+
+    let body = serde_json::to_string(&ShareResponse {
+        url: client.public_url(&format!("{}{}", state.config.base_path(), signed)),
+        expires: expires.to_rfc3339_opts(SecondsFormat::Secs, true),
+    })
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -2,47 +2,52 @@
 
 use axum::{
     body::Body,
-    extract::{Multipart, OriginalUri, State},
+    extract::{Multipart, State},
     http::StatusCode,
     response::Response,
 };
+use std::sync::Arc;
 use tracing::{error, info, instrument, warn};
 
 use crate::server::app::AppState;
+use crate::server::fs::RequestPath;
 use crate::server::uploads;
 
 /// handle file upload requests to root directory
 #[instrument(skip(state, multipart))]
 pub async fn handle_root_upload_request(
     State(state): State<AppState>,
-    OriginalUri(uri): OriginalUri,
+    request_path: RequestPath,
     multipart: Multipart,
 ) -> Result<Response, StatusCode> {
-    let upload_path = uri.path().trim_start_matches('/');
-    handle_upload_impl(state, upload_path, multipart).await
+    handle_upload_impl(state, request_path.as_str(), multipart).await
 }
 
 /// handle file upload requests with path
-#[instrument(skip(state, multipart, uri))]
+#[instrument(skip(state, multipart, request_path))]
 pub async fn handle_upload_request(
     State(state): State<AppState>,
-    OriginalUri(uri): OriginalUri,
+    request_path: RequestPath,
     multipart: Multipart,
 ) -> Result<Response, StatusCode> {
-    let upload_path = uri.path().trim_start_matches('/');
-    handle_upload_impl(state, upload_path, multipart).await
+    handle_upload_impl(state, request_path.as_str(), multipart).await
 }
 
-/// internal implementation for upload handling
+/// internal implementation for upload handling, given the decoded request path
 async fn handle_upload_impl(
     state: AppState,
-    upload_path: &str,
+    request_path: &str,
     mut multipart: Multipart,
 ) -> Result<Response, StatusCode> {
     info!("processing upload request");
 
+    // uploads land in the mount serving the request path
+    let (mount, upload_path) = state.mounts.resolve(request_path);
+    let mount = Arc::clone(mount);
+    let upload_path = upload_path.trim_start_matches('/');
+
     // verify uploads are enabled
-    if !state.config.server.enable_upload {
+    if !mount.enable_upload {
         warn!("upload attempt but uploads are disabled");
         return Err(StatusCode::FORBIDDEN);
     }
@@ -65,7 +70,7 @@ async fn handle_upload_impl(
         };
 
         // validate and process upload
-        let target_path = uploads::process_upload(&state.config, upload_path, filename, field)
+        let target_path = uploads::process_upload(&state, &mount, upload_path, filename, field)
             .await
             .map_err(|err| {
                 error!("upload failed: {}", err);
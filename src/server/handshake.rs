@@ -0,0 +1,150 @@
+// listeners that finish a per-connection handshake before serving
+
+use axum::serve::Listener;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{Semaphore, mpsc};
+use tracing::debug;
+
+const PENDING_CONNECTIONS: usize = 128;
+// handshakes in flight at once; further connections wait in the kernel backlog
+const MAX_PENDING_HANDSHAKES: usize = 1024;
+
+/// wraps a listener and runs a handshake, such as tls or a proxy protocol
+/// header, for every connection off the accept path so a slow client cannot
+/// hold up other connections; failed or timed out handshakes are dropped
+pub struct HandshakeListener<Io> {
+    incoming: mpsc::Receiver<(Io, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl<Io: Send + 'static> HandshakeListener<Io> {
+    /// accept from `listener`, handing each connection and its peer address to
+    /// `handshake`, which answers with the stream to serve and the client address
+    pub fn with_handshake<L, F, Fut>(
+        listener: L,
+        name: &'static str,
+        timeout: Duration,
+        handshake: F,
+    ) -> io::Result<Self>
+    where
+        L: Listener<Addr = SocketAddr>,
+        F: Fn(L::Io, SocketAddr) -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<(Io, SocketAddr)>> + Send + 'static,
+    {
+        Self::with_bounded_handshake(listener, name, timeout, MAX_PENDING_HANDSHAKES, handshake)
+    }
+
+    /// [`Self::with_handshake`] with at most `max_pending` handshakes in flight
+    fn with_bounded_handshake<L, F, Fut>(
+        mut listener: L,
+        name: &'static str,
+        timeout: Duration,
+        max_pending: usize,
+        handshake: F,
+    ) -> io::Result<Self>
+    where
+        L: Listener<Addr = SocketAddr>,
+        F: Fn(L::Io, SocketAddr) -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<(Io, SocketAddr)>> + Send + 'static,
+    {
+        let local_addr = listener.local_addr()?;
+        let (sender, incoming) = mpsc::channel(PENDING_CONNECTIONS);
+        let slots = Arc::new(Semaphore::new(max_pending));
+
+        tokio::spawn(async move {
+            loop {
+                // take a handshake slot before accepting, so clients that never
+                // finish their handshake cannot pile up tasks; stop accepting
+                // once the server has dropped this listener
+                let slot = tokio::select! {
+                    _ = sender.closed() => break,
+                    slot = slots.clone().acquire_owned() => slot,
+                };
+                let Ok(slot) = slot else { break };
+                let (stream, remote_addr) = tokio::select! {
+                    _ = sender.closed() => break,
+                    accepted = Listener::accept(&mut listener) => accepted,
+                };
+
+                let sender = sender.clone();
+                let handshake = handshake(stream, remote_addr);
+                tokio::spawn(async move {
+                    let _slot = slot;
+                    match tokio::time::timeout(timeout, handshake).await {
+                        Ok(Ok(connection)) => {
+                            let _ = sender.send(connection).await;
+                        }
+                        Ok(Err(err)) => debug!("{} with {} failed: {}", name, remote_addr, err),
+                        Err(_) => debug!("{} with {} timed out", name, remote_addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl<Io> Listener for HandshakeListener<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Io = Io;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(connection) => connection,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn bounds_pending_handshakes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let started = Arc::new(AtomicUsize::new(0));
+
+        let counter = started.clone();
+        let _listener = HandshakeListener::<TcpStream>::with_bounded_handshake(
+            listener,
+            "stalled handshake",
+            Duration::from_secs(60),
+            2,
+            move |stream, peer_addr| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    std::future::pending::<()>().await;
+                    Ok((stream, peer_addr))
+                }
+            },
+        )
+        .unwrap();
+
+        let mut clients = Vec::new();
+        for _ in 0..5 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,383 @@
+// listen address resolution and socket binding
+
+use anyhow::{Context, Result};
+#[cfg(unix)]
+use nix::unistd::{Group, User};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, warn};
+
+use crate::config::ServerConfig;
+
+const LISTEN_BACKLOG: i32 = 1024;
+const UNIX_PREFIX: &str = "unix:";
+
+/// configured listen entries, falling back to the host and port settings
+pub fn listen_entries(server: &ServerConfig) -> Vec<String> {
+    if server.listen.is_empty() {
+        vec![join_host_port(&server.host, server.port)]
+    } else {
+        server.listen.clone()
+    }
+}
+
+/// format a host and port, bracketing bare ipv6 literals
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// split listen entries into tcp `host:port` entries and unix socket paths
+pub fn split_listen_entries(entries: &[String]) -> (Vec<String>, Vec<PathBuf>) {
+    let mut tcp = Vec::new();
+    let mut unix = Vec::new();
+    for entry in entries {
+        match entry.strip_prefix(UNIX_PREFIX) {
+            Some(path) => unix.push(PathBuf::from(path)),
+            None => tcp.push(entry.clone()),
+        }
+    }
+    (tcp, unix)
+}
+
+/// resolve every listen entry, binding all addresses a hostname resolves to
+pub async fn resolve_listen_addrs(entries: &[String]) -> Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+
+    for entry in entries {
+        let resolved: Vec<SocketAddr> = tokio::net::lookup_host(entry.as_str())
+            .await
+            .with_context(|| format!("failed to resolve listen address: {entry}"))?
+            .collect();
+
+        if resolved.is_empty() {
+            anyhow::bail!("listen address '{entry}' did not resolve to any addresses");
+        }
+        if entry.parse::<SocketAddr>().is_err() {
+            debug!("resolved listen address '{}' to {:?}", entry, resolved);
+        }
+
+        for addr in resolved {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+
+    Ok(addrs)
+}
+
+/// bind every address, keeping ipv6 sockets v6-only when ipv4 addresses are
+/// bound as well so `[::]` and `0.0.0.0` can share a port
+pub fn bind_all(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>> {
+    let v6_only = addrs.iter().any(SocketAddr::is_ipv4);
+
+    addrs
+        .iter()
+        .map(|addr| bind_tcp(*addr, v6_only).with_context(|| format!("failed to bind to {addr}")))
+        .collect()
+}
+
+/// bind a single tcp listener
+pub fn bind_tcp(addr: SocketAddr, v6_only: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// a unix socket file this process bound, identified by device, inode and
+/// change time so it is only removed while it is still ours, even once a
+/// replacement has reused the inode
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixSocketFile {
+    path: PathBuf,
+    id: (u64, u64, i64, i64),
+}
+
+#[cfg(unix)]
+impl UnixSocketFile {
+    /// remove the socket file once its listener has stopped, unless another
+    /// process has replaced it in the meantime
+    pub fn remove(&self) {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if file_id(&metadata) == self.id => {}
+            Ok(_) => {
+                warn!(
+                    "leaving unix socket {} in place, it was replaced by another process",
+                    self.path.display()
+                );
+                return;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => return,
+            Err(err) => {
+                warn!(
+                    "failed to inspect unix socket {}: {}",
+                    self.path.display(),
+                    err
+                );
+                return;
+            }
+        }
+        if let Err(err) = fs::remove_file(&self.path)
+            && err.kind() != ErrorKind::NotFound
+        {
+            warn!(
+                "failed to remove unix socket {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> (u64, u64, i64, i64) {
+    (
+        metadata.dev(),
+        metadata.ino(),
+        metadata.ctime(),
+        metadata.ctime_nsec(),
+    )
+}
+
+/// bind a unix socket, replacing a stale socket file left behind by a previous run
+#[cfg(unix)]
+pub async fn bind_unix(
+    path: &Path,
+    mode: Option<u32>,
+    owner: Option<&str>,
+) -> Result<(UnixListener, UnixSocketFile)> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).await.is_ok() {
+                anyhow::bail!("unix socket is already in use: {}", path.display());
+            }
+            fs::remove_file(path).with_context(|| {
+                format!("failed to remove stale unix socket: {}", path.display())
+            })?;
+            debug!("removed stale unix socket: {}", path.display());
+        }
+        Ok(_) => anyhow::bail!("refusing to replace non-socket file: {}", path.display()),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("failed to inspect unix socket path: {}", path.display())
+            });
+        }
+    }
+
+    // bind inside a private directory and move the socket into place once its
+    // mode and owner are set, so it is never reachable with looser permissions
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("invalid unix socket path: {}", path.display()))?;
+    let staging_dir = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging_dir)
+        .with_context(|| {
+            format!(
+                "failed to create staging directory for unix socket: {}",
+                staging_dir.display()
+            )
+        })?;
+
+    let staged = staging_dir.join(file_name);
+    let result = bind_staged_unix(&staged, path, mode, owner);
+    if let Err(err) = fs::remove_file(&staged)
+        && err.kind() != ErrorKind::NotFound
+    {
+        warn!(
+            "failed to remove staged unix socket {}: {}",
+            staged.display(),
+            err
+        );
+    }
+    if let Err(err) = fs::remove_dir(&staging_dir) {
+        warn!(
+            "failed to remove unix socket staging directory {}: {}",
+            staging_dir.display(),
+            err
+        );
+    }
+    result
+}
+
+#[cfg(unix)]
+fn bind_staged_unix(
+    staged: &Path,
+    path: &Path,
+    mode: Option<u32>,
+    owner: Option<&str>,
+) -> Result<(UnixListener, UnixSocketFile)> {
+    let listener = UnixListener::bind(staged)
+        .with_context(|| format!("failed to bind unix socket: {}", path.display()))?;
+
+    if let Some(mode) = mode {
+        fs::set_permissions(staged, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set unix socket mode: {}", path.display()))?;
+    }
+    if let Some(owner) = owner {
+        let (uid, gid) = resolve_owner(owner)?;
+        std::os::unix::fs::chown(staged, uid, gid)
+            .with_context(|| format!("failed to set unix socket owner: {}", path.display()))?;
+    }
+    fs::rename(staged, path)
+        .with_context(|| format!("failed to move unix socket into place: {}", path.display()))?;
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("failed to inspect unix socket: {}", path.display()))?;
+
+    let file = UnixSocketFile {
+        path: path.to_path_buf(),
+        id: file_id(&metadata),
+    };
+    Ok((listener, file))
+}
+
+/// resolve `user`, `user:group` or `:group` to numeric ids through the
+/// system's account databases (including nss sources such as ldap),
+/// accepting numeric ids as-is
+#[cfg(unix)]
+fn resolve_owner(owner: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+
+    let uid = match user {
+        "" => None,
+        user => Some(match user.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => User::from_name(user)
+                .with_context(|| format!("failed to look up user: {user}"))?
+                .with_context(|| format!("unknown user: {user}"))?
+                .uid
+                .as_raw(),
+        }),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => Group::from_name(group)
+                .with_context(|| format!("failed to look up group: {group}"))?
+                .with_context(|| format!("unknown group: {group}"))?
+                .gid
+                .as_raw(),
+        }),
+    };
+
+    Ok((uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brackets_ipv6_hosts() {
+        assert_eq!(join_host_port("0.0.0.0", 80), "0.0.0.0:80");
+        assert_eq!(join_host_port("::1", 80), "[::1]:80");
+        assert_eq!(join_host_port("[::1]", 80), "[::1]:80");
+    }
+
+    #[tokio::test]
+    async fn binds_ipv4_and_ipv6_on_the_same_port() {
+        let v4 = bind_tcp("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let port = v4.local_addr().unwrap().port();
+
+        // loopback ipv6 may be unavailable in minimal containers
+        if let Ok(v6) = bind_tcp(SocketAddr::new("::1".parse().unwrap(), port), true) {
+            assert_eq!(v6.local_addr().unwrap().port(), port);
+        }
+
+        let addrs = resolve_listen_addrs(&[format!("127.0.0.1:{port}")])
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![v4.local_addr().unwrap()]);
+    }
+
+    #[test]
+    fn splits_unix_entries() {
+        let entries = vec!["unix:/run/soop3.sock".to_string(), "[::1]:80".to_string()];
+        let (tcp, unix) = split_listen_entries(&entries);
+        assert_eq!(tcp, vec!["[::1]:80"]);
+        assert_eq!(unix, vec![PathBuf::from("/run/soop3.sock")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn replaces_stale_unix_socket_only() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("soop3.sock");
+
+        let (listener, _) = bind_unix(&path, Some(0o600), Some("0")).await.unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // the staging directory is gone once the socket is in place
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // a live socket is never taken over
+        assert!(bind_unix(&path, None, None).await.is_err());
+
+        // a socket left behind without a listener is replaced
+        drop(listener);
+        let (_listener, socket) = bind_unix(&path, None, None).await.unwrap();
+
+        let file = temp_dir.path().join("regular");
+        fs::write(&file, "data").unwrap();
+        assert!(bind_unix(&file, None, None).await.is_err());
+        assert!(file.exists());
+
+        socket.remove();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn leaves_replaced_unix_sockets_alone() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("soop3.sock");
+
+        let (listener, socket) = bind_unix(&path, None, None).await.unwrap();
+        drop(listener);
+        // another instance took the path over before this one shut down
+        let (_listener, _) = bind_unix(&path, None, None).await.unwrap();
+
+        socket.remove();
+        assert!(path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_socket_owner() {
+        assert_eq!(resolve_owner("0").unwrap(), (Some(0), None));
+        assert_eq!(resolve_owner("root:0").unwrap(), (Some(0), Some(0)));
+        assert_eq!(resolve_owner(":0").unwrap(), (None, Some(0)));
+        assert!(resolve_owner("no-such-user-soop3").is_err());
+        assert!(resolve_owner(":no-such-group-soop3").is_err());
+    }
+}
@@ -1,19 +1,259 @@
-// directory listing html generation and sorting helpers
+// directory listing html/json generation and sorting helpers
 
+use axum::http::{HeaderMap, header};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::path::Path;
+use tracing::warn;
+
+use super::encoding::quality_values;
 use crate::utils::{
-    files::{DirectoryEntry, escape_html, format_file_size, format_timestamp},
+    files::{
+        DirectoryEntry, escape_html, format_file_size, format_timestamp, format_timestamp_rfc3339,
+        get_mime_type,
+    },
+    ignore::pattern_to_regex,
     paths::encode_path_segments,
 };
 
-pub fn sort_entries(entries: &mut [DirectoryEntry]) {
+/// column used to order directory listings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+/// direction used to order directory listings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+impl SortOrder {
+    fn as_str(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+/// query parameters understood by directory listings
+#[derive(Debug, Clone, Default)]
+pub struct ListingParams {
+    pub format: Option<String>,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    pub filter: Option<String>,
+}
+
+impl ListingParams {
+    /// parse listing parameters from a raw query string, ignoring unknown keys
+    pub fn from_query(query: Option<&str>) -> Self {
+        let mut params = Self::default();
+
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "format" => params.format = Some(value.to_ascii_lowercase()),
+                "sort" => {
+                    params.sort = match value.to_ascii_lowercase().as_str() {
+                        "size" => SortKey::Size,
+                        "mtime" => SortKey::Modified,
+                        "type" => SortKey::Type,
+                        _ => SortKey::Name,
+                    }
+                }
+                "order" => {
+                    params.order = if value.eq_ignore_ascii_case("desc") {
+                        SortOrder::Desc
+                    } else {
+                        SortOrder::Asc
+                    }
+                }
+                "filter" if !value.is_empty() => params.filter = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        params
+    }
+}
+
+/// json representation of a single listing entry
+#[derive(Debug, Serialize)]
+struct JsonEntry<'a> {
+    name: &'a str,
+    size: u64,
+    mtime: String,
+    is_dir: bool,
+    mime_type: Option<String>,
+    url: String,
+}
+
+/// json representation of a directory listing
+#[derive(Debug, Serialize)]
+struct JsonListing<'a> {
+    path: &'a str,
+    entries: Vec<JsonEntry<'a>>,
+}
+
+/// decide whether the client asked for a json listing via `?format=` or `Accept`
+pub fn wants_json(params: &ListingParams, headers: &HeaderMap) -> bool {
+    match params.format.as_deref() {
+        Some("json") => return true,
+        Some("html") => return false,
+        _ => {}
+    }
+
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let preferences = quality_values(accept);
+    let quality_of = |wanted: &str| {
+        preferences
+            .iter()
+            .filter(|(media, _)| media == wanted)
+            .map(|(_, quality)| *quality)
+            .fold(0.0f32, f32::max)
+    };
+
+    let json = quality_of("application/json");
+    json > 0.0 && json >= quality_of("text/html")
+}
+
+/// sort entries with directories first, then by the requested column
+pub fn sort_entries(entries: &mut [DirectoryEntry], sort: SortKey, order: SortOrder) {
     entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.cmp(&b.name),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => {
+            let ordering = match sort {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+                SortKey::Type => extension_of(&a.name).cmp(&extension_of(&b.name)),
+            }
+            .then_with(|| natural_cmp(&a.name, &b.name));
+
+            match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        }
     });
 }
 
-pub fn build_listing_html(entries: &[DirectoryEntry], request_path: &str) -> String {
+/// keep only entries whose name matches the `?filter=` glob
+pub fn filter_entries(entries: &mut Vec<DirectoryEntry>, filter: Option<&str>) {
+    let Some(filter) = filter else {
+        return;
+    };
+
+    match pattern_to_regex(filter) {
+        Ok(regex) => entries.retain(|entry| regex.is_match(&entry.name)),
+        Err(err) => warn!("ignoring invalid listing filter {:?}: {}", filter, err),
+    }
+}
+
+/// compare names so that embedded numbers sort by value (file2 < file10)
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_num = take_digits(&mut a_chars);
+                let b_num = take_digits(&mut b_chars);
+                let a_trimmed = a_num.trim_start_matches('0');
+                let b_trimmed = b_num.trim_start_matches('0');
+
+                let ordering = a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(ch) = chars.next_if(char::is_ascii_digit) {
+        digits.push(ch);
+    }
+    digits
+}
+
+fn extension_of(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// header cell linking to the listing sorted by this column, toggling the order
+fn sort_header(label: &str, key: SortKey, params: &ListingParams) -> String {
+    let (order, class) = if params.sort == key {
+        match params.order {
+            SortOrder::Asc => (SortOrder::Desc, " class=\"sorted asc\""),
+            SortOrder::Desc => (SortOrder::Asc, " class=\"sorted desc\""),
+        }
+    } else {
+        (SortOrder::Asc, "")
+    };
+
+    let mut href = format!("?sort={}&order={}", key.as_str(), order.as_str());
+    if let Some(filter) = &params.filter {
+        href.push_str("&filter=");
+        href.push_str(&utf8_percent_encode(filter, NON_ALPHANUMERIC).to_string());
+    }
+
+    format!(
+        "<th{class}><a href=\"{}\">{label}</a></th>",
+        escape_html(&href)
+    )
+}
+
+/// render the html listing; `root` is the url prefix the server is reached under
+pub fn build_listing_html(
+    entries: &[DirectoryEntry],
+    root: &str,
+    request_path: &str,
+    params: &ListingParams,
+) -> String {
     let mut html = String::new();
 
     // html document structure
@@ -25,20 +265,25 @@ pub fn build_listing_html(entries: &[DirectoryEntry], request_path: &str) -> Str
         "<meta name=\"generator\" content=\"soop3 v{}\">",
         env!("CARGO_PKG_VERSION")
     ));
-    html.push_str("<link rel=\"icon\" href=\"/__soop_static/icon.svg\">");
+    let root = escape_html(root);
+    html.push_str(&format!(
+        "<link rel=\"icon\" href=\"{root}/__soop_static/icon.svg\">"
+    ));
     html.push_str(&format!(
         "<title>soop3 | {}</title>",
         escape_html(request_path)
     ));
-    html.push_str("<link rel=\"stylesheet\" href=\"/__soop_static/style.css\">");
+    html.push_str(&format!(
+        "<link rel=\"stylesheet\" href=\"{root}/__soop_static/style.css\">"
+    ));
     html.push_str("</head><body>");
 
     // content structure
     html.push_str("<div class=\"wrapper\">");
     html.push_str("<main>");
-    html.push_str(
-        "<a href=\"/\"><img src=\"/__soop_static/icon.svg\" alt=\"logo\" class=\"logo-icon\"></a>",
-    );
+    html.push_str(&format!(
+        "<a href=\"{root}/\"><img src=\"{root}/__soop_static/icon.svg\" alt=\"logo\" class=\"logo-icon\"></a>",
+    ));
     html.push_str(&format!(
         "<h1 class=\"index-info\">Index of <code>{}</code></h1>",
         escape_html(request_path)
@@ -46,7 +291,11 @@ pub fn build_listing_html(entries: &[DirectoryEntry], request_path: &str) -> Str
 
     // file listing table
     html.push_str("<table class=\"list\">");
-    html.push_str("<tr><th>name</th><th>size</th><th>modified</th></tr>");
+    html.push_str("<tr>");
+    html.push_str(&sort_header("name", SortKey::Name, params));
+    html.push_str(&sort_header("size", SortKey::Size, params));
+    html.push_str(&sort_header("modified", SortKey::Modified, params));
+    html.push_str("</tr>");
 
     // parent directory link
     if request_path != "/" {
@@ -94,3 +343,99 @@ pub fn build_listing_html(entries: &[DirectoryEntry], request_path: &str) -> Str
 
     html
 }
+
+pub fn build_listing_json(
+    entries: &[DirectoryEntry],
+    root: &str,
+    request_path: &str,
+) -> Result<String, serde_json::Error> {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            let entry_path = if entry.is_dir {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            };
+
+            JsonEntry {
+                name: &entry.name,
+                size: entry.size,
+                mtime: format_timestamp_rfc3339(entry.modified),
+                is_dir: entry.is_dir,
+                mime_type: (!entry.is_dir).then(|| get_mime_type(Path::new(&entry.name))),
+                url: format!(
+                    "{root}{}{}",
+                    encode_path_segments(request_path),
+                    encode_path_segments(&entry_path)
+                ),
+            }
+        })
+        .collect();
+
+    serde_json::to_string(&JsonListing {
+        path: request_path,
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(name: &str, size: u64, age: u64, is_dir: bool) -> DirectoryEntry {
+        DirectoryEntry {
+            name: name.to_string(),
+            size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(age),
+            is_dir,
+        }
+    }
+
+    fn names(entries: &[DirectoryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn natural_name_ordering() {
+        let mut names = vec!["v1.10.0", "v1.2.0", "v1.9.1", "File", "file2", "file10"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["File", "file2", "file10", "v1.2.0", "v1.9.1", "v1.10.0"]
+        );
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    fn sorting_by_column_keeps_directories_first() {
+        let mut entries = vec![
+            entry("b.txt", 30, 1, false),
+            entry("a.log", 10, 3, false),
+            entry("dir", 0, 2, true),
+            entry("c.txt", 20, 2, false),
+        ];
+
+        sort_entries(&mut entries, SortKey::Size, SortOrder::Desc);
+        assert_eq!(names(&entries), vec!["dir", "b.txt", "c.txt", "a.log"]);
+
+        sort_entries(&mut entries, SortKey::Modified, SortOrder::Asc);
+        assert_eq!(names(&entries), vec!["dir", "b.txt", "c.txt", "a.log"]);
+
+        sort_entries(&mut entries, SortKey::Type, SortOrder::Asc);
+        assert_eq!(names(&entries), vec!["dir", "a.log", "b.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn parses_listing_query() {
+        let params = ListingParams::from_query(Some("sort=size&order=desc&filter=%2A.txt"));
+        assert_eq!(params.sort, SortKey::Size);
+        assert_eq!(params.order, SortOrder::Desc);
+        assert_eq!(params.filter.as_deref(), Some("*.txt"));
+
+        let params = ListingParams::from_query(Some("sort=bogus&order=up"));
+        assert_eq!(params.sort, SortKey::Name);
+        assert_eq!(params.order, SortOrder::Asc);
+    }
+}
@@ -1,78 +1,307 @@
-// http basic authentication middleware
+// http basic and bearer token authentication middleware
 
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::{HeaderValue, Method, StatusCode, header},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
     middleware::Next,
     response::Response,
 };
 use base64::prelude::*;
+use chrono::Utc;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, warn};
 
+use super::forwarded::ClientInfo;
 use crate::{
-    config::{SecurityConfig, SecurityPolicy},
-    server::app::AppState,
+    config::{RateLimitConfig, SecurityPolicy},
+    server::{app::AppState, fs::RequestPath, handlers::share::SHARE_PATH},
+    utils::{
+        access::{Access, AccessPath},
+        passwords::Users,
+        share::ShareCheck,
+    },
 };
 
-/// http basic authentication middleware
+/// http basic, bearer token, signed link and access rule middleware
 pub async fn authenticate_if_required(
     State(state): State<AppState>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    let needs_auth = determine_auth_requirement(&state.config.security, &request);
+    // decode the path once; mounts, access rules, signed links and the
+    // handlers all go by this one
+    let request_path = match RequestPath::from_uri(request.uri().path()) {
+        Ok(request_path) => request_path,
+        Err(err) => {
+            warn!(
+                "rejecting request with bad path: {} - {}",
+                request.uri().path(),
+                err
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+    request.extensions_mut().insert(request_path.clone());
+    let path = request_path.as_str();
+
+    // each mount may carry its own policy; minting share links always needs a user
+    let is_share_endpoint = path == SHARE_PATH;
+    let (mount, _) = state.mounts.resolve(path);
+    let needs_auth = is_share_endpoint || determine_auth_requirement(mount.policy, &request);
+
+    // access rules cover files and listings, not preflights or listing assets
+    let access_path = if state.access_rules.is_empty()
+        || request.method() == Method::OPTIONS
+        || is_share_endpoint
+        || path.starts_with("/__soop_static/")
+    {
+        None
+    } else {
+        AccessPath::from_decoded(path)
+    };
 
-    if !needs_auth {
+    if !needs_auth && access_path.is_none() {
         debug!("no authentication required for this request");
         return Ok(next.run(request).await);
     }
 
-    // check if auth is configured
-    let auth_available =
-        state.config.security.username.is_some() && state.config.security.password.is_some();
+    // a signed link stands in for credentials on the path and method it names
+    if state.share_links.is_enabled()
+        && !is_share_endpoint
+        && let Some(shared) = AccessPath::from_decoded(path)
+    {
+        let now = Utc::now().timestamp();
+        match state
+            .share_links
+            .verify(request.method(), &shared, request.uri().query(), now)
+        {
+            ShareCheck::Valid { by } => {
+                // a link never reaches further than the rules let the user who
+                // minted it, or anybody for links signed on the command line
+                if let Some(access_path) = &access_path
+                    && state.access_rules.evaluate(
+                        request.method(),
+                        access_path,
+                        by.as_deref(),
+                        &state.users,
+                    ) == Access::Deny
+                {
+                    warn!("access rules deny {} to the signer of the link", path);
+                    return Err(StatusCode::FORBIDDEN);
+                }
+                debug!("signed link grants {} {}", request.method(), path);
+                return Ok(next.run(request).await);
+            }
+            ShareCheck::Invalid(reason) => {
+                warn!("rejecting signed link for {}: {}", path, reason);
+                return Err(StatusCode::FORBIDDEN);
+            }
+            ShareCheck::NotShared => {}
+        }
+    }
+
+    // a client locked out after failed logins gets no further guesses; the
+    // attempt counts before the slow verification so concurrent guesses
+    // cannot all slip past the check
+    let rate_limit = &state.config.security.rate_limit;
+    let login_keys = login_keys(rate_limit, &request);
+    let lockout = if login_keys.is_empty() {
+        None
+    } else {
+        match state
+            .login_throttle
+            .begin_attempt(&login_keys, rate_limit, Instant::now())
+        {
+            Ok(lockout) => lockout,
+            Err(retry_after) => {
+                warn!("rejecting login attempt during lockout: {:?}", login_keys);
+                return Ok(too_many_requests(retry_after));
+            }
+        }
+    };
 
-    if !auth_available {
-        error!("authentication required but credentials not configured");
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let user = match authenticate(&state, request.method(), path, request.headers()).await {
+        Ok(user) => user,
+        Err(status) => {
+            // a request refused for anything but bad credentials is no failed login
+            state.login_throttle.refund(&login_keys, rate_limit);
+            return Err(status);
+        }
+    };
+
+    if user.is_some() {
+        state.login_throttle.record_success(&login_keys, rate_limit);
+    } else if let Some(lockout) = lockout {
+        warn!(
+            "locking out {:?} for {}s after repeated failed logins",
+            login_keys,
+            lockout.as_secs()
+        );
     }
 
-    // extract and validate credentials
-    let auth_header = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok());
+    let access = match &access_path {
+        Some(access_path) => state.access_rules.evaluate(
+            request.method(),
+            access_path,
+            user.as_deref(),
+            &state.users,
+        ),
+        None => Access::Unmatched,
+    };
+    let allowed = match access {
+        Access::Allow => true,
+        Access::Deny => {
+            warn!("access rules deny {} to {:?}", path, user);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Access::Authenticate => false,
+        Access::Unmatched if !needs_auth => true,
+        Access::Unmatched => {
+            // check if auth is configured
+            if state.users.is_empty() {
+                error!("authentication required but credentials not configured");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            user.is_some()
+        }
+    };
 
-    let auth_header = match auth_header {
-        Some(header) => header,
-        None => {
+    if !allowed {
+        let auth_header = request.headers().get(header::AUTHORIZATION);
+        if auth_header.is_none() {
             warn!("authentication required but no authorization header provided");
-            return Ok(unauthorized_response());
         }
+        let bearer = auth_header
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_bearer_token)
+            .is_some();
+        return Ok(unauthorized_response(bearer));
+    }
+
+    // handlers see who logged in, listings hide what the rules refuse them
+    if let Some(user) = user {
+        let mut client = request
+            .extensions()
+            .get::<ClientInfo>()
+            .cloned()
+            .unwrap_or_default();
+        client.user = Some(user);
+        request.extensions_mut().insert(client);
+    }
+    Ok(next.run(request).await)
+}
+
+/// the user named by the request's basic credentials or bearer token, if
+/// they are valid
+async fn authenticate(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Option<String>, StatusCode> {
+    // extract and validate credentials
+    let Some(auth_header) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return Ok(None);
     };
 
+    if let Some(token) = parse_bearer_token(auth_header) {
+        return authenticate_token(state, token, method, path);
+    }
+
     let credentials = match parse_basic_auth(auth_header) {
         Ok(creds) => creds,
         Err(e) => {
             warn!("failed to parse authorization header: {}", e);
-            return Ok(unauthorized_response());
+            return Ok(None);
         }
     };
 
-    if validate_credentials(&state.config.security, &credentials) {
-        debug!(
-            "authentication successful for user: {}",
-            credentials.username
-        );
-        Ok(next.run(request).await)
+    // password hashing is deliberately slow, keep it off the async workers
+    let users = state.users.clone();
+    let (username, verified) = tokio::task::spawn_blocking(move || {
+        let verified = validate_credentials(&users, &credentials);
+        (credentials.username, verified)
+    })
+    .await
+    .map_err(|err| {
+        error!("credential check failed: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if verified {
+        debug!("authentication successful for user: {}", username);
+        Ok(Some(username))
     } else {
-        warn!("authentication failed for user: {}", credentials.username);
-        Ok(unauthorized_response())
+        warn!("authentication failed for user: {}", username);
+        Ok(None)
+    }
+}
+
+/// check a bearer token; a valid token used outside its scopes or paths is
+/// refused outright rather than asked to log in again
+fn authenticate_token(
+    state: &AppState,
+    token: &str,
+    method: &Method,
+    path: &str,
+) -> Result<Option<String>, StatusCode> {
+    let Some((name, api_token)) = state.users.verify_token(token) else {
+        warn!("authentication failed for unknown bearer token");
+        return Ok(None);
+    };
+    if api_token.is_expired(Utc::now()) {
+        warn!("authentication failed for expired token: {}", name);
+        return Ok(None);
+    }
+    // minting a share link is checked against the path it shares instead
+    let covered = path == SHARE_PATH
+        || AccessPath::from_decoded(path).is_some_and(|path| api_token.covers(method, &path));
+    if !covered {
+        warn!("token {} does not cover {} {}", name, method, path);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    debug!("authentication successful for token: {}", name);
+    Ok(Some(name.to_string()))
+}
+
+/// what failed logins are counted against: the client ip and, for basic
+/// credentials unless `per_user` is off, the user name; nothing when the request
+/// carries no credentials
+fn login_keys(config: &RateLimitConfig, request: &Request) -> Vec<String> {
+    let Some(auth_header) = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return Vec::new();
+    };
+    if !config.enabled {
+        return Vec::new();
+    }
+
+    let mut keys = Vec::new();
+    if let Some(ip) = request
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client| client.ip)
+    {
+        keys.push(format!("ip:{ip}"));
     }
+    if config.per_user
+        && let Ok(credentials) = parse_basic_auth(auth_header)
+    {
+        keys.push(format!("user:{}", credentials.username));
+    }
+    keys
 }
 
 /// determine if authentication is required for this request
-fn determine_auth_requirement(security_config: &SecurityConfig, request: &Request) -> bool {
+fn determine_auth_requirement(policy: SecurityPolicy, request: &Request) -> bool {
     let method = request.method();
 
     if method == Method::OPTIONS {
@@ -81,7 +310,7 @@ fn determine_auth_requirement(security_config: &SecurityConfig, request: &Reques
 
     let is_download = method == Method::GET || method == Method::HEAD;
 
-    match security_config.policy {
+    match policy {
         SecurityPolicy::AuthenticateNone => false,
         SecurityPolicy::AuthenticateAll => true,
         SecurityPolicy::AuthenticateUpload => !is_download,
@@ -89,16 +318,40 @@ fn determine_auth_requirement(security_config: &SecurityConfig, request: &Reques
     }
 }
 
-fn unauthorized_response() -> Response {
+fn unauthorized_response(bearer: bool) -> Response {
+    let challenge = if bearer {
+        "Bearer realm=\"soop3\", error=\"invalid_token\""
+    } else {
+        "Basic realm=\"soop3\""
+    };
     let mut response = Response::new(Body::empty());
     *response.status_mut() = StatusCode::UNAUTHORIZED;
     response.headers_mut().insert(
         header::WWW_AUTHENTICATE,
-        HeaderValue::from_static("Basic realm=\"soop3\""),
+        HeaderValue::from_static(challenge),
     );
     response
 }
 
+fn too_many_requests(retry_after: Duration) -> Response {
+    // round up so clients never retry a moment too early
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// the token of a `Bearer <token>` authorization header
+pub fn parse_bearer_token(auth_header: &str) -> Option<&str> {
+    let mut parts = auth_header.split_whitespace();
+    let scheme = parts.next()?;
+    let token = parts.next()?;
+    (scheme.eq_ignore_ascii_case("bearer") && parts.next().is_none()).then_some(token)
+}
+
 /// parse http basic authentication header
 pub fn parse_basic_auth(auth_header: &str) -> Result<BasicCredentials, &'static str> {
     // header format: "Basic <base64-encoded-credentials>"
@@ -130,46 +383,9 @@ pub fn parse_basic_auth(auth_header: &str) -> Result<BasicCredentials, &'static
     })
 }
 
-/// validate credentials against configuration
-pub fn validate_credentials(
-    security_config: &SecurityConfig,
-    credentials: &BasicCredentials,
-) -> bool {
-    let expected_username = match &security_config.username {
-        Some(username) => username,
-        None => return false,
-    };
-
-    let expected_password = match &security_config.password {
-        Some(password) => password,
-        None => return false,
-    };
-
-    // constant-time comparison to prevent timing attacks
-    let user_ok = constant_time_eq(
-        credentials.username.as_bytes(),
-        expected_username.as_bytes(),
-    );
-    let pass_ok = constant_time_eq(
-        credentials.password.as_bytes(),
-        expected_password.as_bytes(),
-    );
-
-    user_ok & pass_ok
-}
-
-/// constant-time string comparison to prevent timing attacks
-pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
-    let max_len = a.len().max(b.len());
-    let mut result = (a.len() ^ b.len()) as u64;
-
-    for i in 0..max_len {
-        let x = *a.get(i).unwrap_or(&0);
-        let y = *b.get(i).unwrap_or(&0);
-        result |= (x ^ y) as u64;
-    }
-
-    result == 0
+/// validate credentials against the site's users
+pub fn validate_credentials(users: &Users, credentials: &BasicCredentials) -> bool {
+    users.verify(&credentials.username, &credentials.password)
 }
 
 /// basic authentication credentials
@@ -182,6 +398,7 @@ pub struct BasicCredentials {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::SecurityConfig;
 
     #[test]
     fn basic_auth_parsing() {
@@ -217,46 +434,47 @@ mod tests {
         assert!(parse_basic_auth("Basic dGVzdA==").is_err());
     }
 
+    #[test]
+    fn bearer_token_parsing() {
+        assert_eq!(parse_bearer_token("Bearer soop_abc"), Some("soop_abc"));
+        assert_eq!(parse_bearer_token("bearer  soop_abc"), Some("soop_abc"));
+        assert_eq!(parse_bearer_token("Bearer"), None);
+        assert_eq!(parse_bearer_token("Bearer a b"), None);
+        assert_eq!(parse_bearer_token("Basic dGVzdDp0ZXN0"), None);
+    }
+
     #[test]
     fn credential_validation() {
-        let security_config = SecurityConfig {
+        let users = Users::load(&SecurityConfig {
             username: Some("admin".to_string()),
             password: Some("secret".to_string()),
             policy: SecurityPolicy::AuthenticateAll,
-        };
+            ..Default::default()
+        })
+        .unwrap();
 
         let valid_creds = BasicCredentials {
             username: "admin".to_string(),
             password: "secret".to_string(),
         };
-        assert!(validate_credentials(&security_config, &valid_creds));
+        assert!(validate_credentials(&users, &valid_creds));
 
         let invalid_user = BasicCredentials {
             username: "wrong".to_string(),
             password: "secret".to_string(),
         };
-        assert!(!validate_credentials(&security_config, &invalid_user));
+        assert!(!validate_credentials(&users, &invalid_user));
 
         let invalid_pass = BasicCredentials {
             username: "admin".to_string(),
             password: "wrong".to_string(),
         };
-        assert!(!validate_credentials(&security_config, &invalid_pass));
+        assert!(!validate_credentials(&users, &invalid_pass));
 
         let empty_creds = BasicCredentials {
             username: "".to_string(),
             password: "".to_string(),
         };
-        assert!(!validate_credentials(&security_config, &empty_creds));
-    }
-
-    #[test]
-    fn constant_time_comparison() {
-        assert!(constant_time_eq(b"hello", b"hello"));
-        assert!(!constant_time_eq(b"hello", b"world"));
-        assert!(!constant_time_eq(b"hello", b"hell"));
-        assert!(!constant_time_eq(b"hell", b"hello"));
-        assert!(!constant_time_eq(b"", b"hello"));
-        assert!(constant_time_eq(b"", b""));
+        assert!(!validate_credentials(&users, &empty_creds));
     }
 }
@@ -0,0 +1,81 @@
+// response compression negotiated via accept-encoding
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{Extensions, HeaderMap, HeaderValue, Response, StatusCode, Version, header},
+    middleware::Next,
+};
+use tower_http::compression::{
+    CompressionLayer, CompressionLevel,
+    predicate::{And, Predicate, SizeAbove},
+};
+
+use crate::{config::CompressionConfig, utils::files::is_compressible_mime};
+
+type CompressionPredicate =
+    And<SizeAbove, fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool>;
+
+/// marker for responses whose body was already encoded on disk
+#[derive(Debug, Clone, Copy)]
+pub struct EncodedAtSource;
+
+/// build the compression layer from configuration
+pub fn compression_layer(config: &CompressionConfig) -> CompressionLayer<CompressionPredicate> {
+    let level = match config.level {
+        Some(level) => CompressionLevel::Precise(level),
+        None => CompressionLevel::Default,
+    };
+    let content_predicate: fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool =
+        should_compress;
+
+    CompressionLayer::new()
+        .gzip(true)
+        .br(true)
+        .zstd(true)
+        .quality(level)
+        .compress_when(SizeAbove::new(config.min_size).and(content_predicate))
+}
+
+/// skip partial content and formats that are already compressed
+fn should_compress(
+    status: StatusCode,
+    _version: Version,
+    headers: &HeaderMap,
+    _extensions: &Extensions,
+) -> bool {
+    if status == StatusCode::PARTIAL_CONTENT {
+        return false;
+    }
+
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_compressible_mime)
+}
+
+/// weaken strong etags on compressed responses so that they are never used
+/// to resume byte ranges of the identity representation
+pub async fn weaken_etag_when_encoded(request: Request, next: Next) -> Response<Body> {
+    let mut response = next.run(request).await;
+
+    // precompressed files carry their own representation-specific etag
+    if !response.headers().contains_key(header::CONTENT_ENCODING)
+        || response.extensions().get::<EncodedAtSource>().is_some()
+    {
+        return response;
+    }
+
+    let weakened = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .and_then(|etag| HeaderValue::from_str(&format!("W/{etag}")).ok());
+
+    if let Some(etag) = weakened {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+
+    response
+}
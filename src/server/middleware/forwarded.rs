@@ -0,0 +1,360 @@
+// client address and original url resolution behind trusted proxies
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{HeaderMap, HeaderName, request::Parts, uri::Authority},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use tracing::Span;
+
+use crate::server::app::AppState;
+use crate::server::proxy::PeerAddr;
+use crate::utils::cidr::TrustedProxies;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PREFIX: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+
+/// the client behind a request, as reported by trusted proxies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// the original client address, or the peer address when not proxied
+    pub ip: Option<IpAddr>,
+    /// `http` or `https` as requested by the client
+    pub proto: Option<String>,
+    /// host and port the client connected to
+    pub host: Option<String>,
+    /// path the proxy mounts this server under, without a trailing slash
+    pub prefix: Option<String>,
+    /// user who logged in with this request, set by the authentication middleware
+    pub user: Option<String>,
+}
+
+impl ClientInfo {
+    /// resolve the client for a connection from `peer`, believing forwarding
+    /// headers only when the peer is a trusted proxy
+    pub fn resolve(peer: Option<PeerAddr>, headers: &HeaderMap, trusted: &TrustedProxies) -> Self {
+        let peer_ip = match peer {
+            Some(PeerAddr::Tcp(addr)) => Some(addr.ip().to_canonical()),
+            _ => None,
+        };
+        // unix socket peers are local processes, trusted once any proxy is
+        let peer_trusted = match peer {
+            Some(PeerAddr::Tcp(addr)) => trusted.contains(addr.ip()),
+            Some(PeerAddr::Unix) => !trusted.is_empty(),
+            None => false,
+        };
+        if !peer_trusted {
+            return Self {
+                ip: peer_ip,
+                ..Self::default()
+            };
+        }
+
+        // everything left of the hop where the walk stops is client supplied,
+        // so scheme, host and prefix come from the values that hop's proxy added
+        let forwarded = header_values(headers, &axum::http::header::FORWARDED);
+        let (ip, depth, proto, host) = if forwarded.is_empty() {
+            let chain: Vec<_> = header_values(headers, &X_FORWARDED_FOR)
+                .iter()
+                .map(|node| parse_node(node))
+                .collect();
+            let (ip, depth) = walk_chain(peer_ip, &chain, trusted);
+            let proto = value_at_hop(header_values(headers, &X_FORWARDED_PROTO), depth);
+            let host = value_at_hop(header_values(headers, &X_FORWARDED_HOST), depth);
+            (ip, depth, proto, host)
+        } else {
+            let elements: Vec<ForwardedElement> = forwarded
+                .iter()
+                .map(|element| parse_forwarded(element))
+                .collect();
+            let chain: Vec<_> = elements.iter().map(|element| element.for_ip).collect();
+            let (ip, depth) = walk_chain(peer_ip, &chain, trusted);
+            let element = value_at_hop(elements, depth).unwrap_or_default();
+            (ip, depth, element.proto, element.host)
+        };
+
+        Self {
+            ip,
+            proto: proto
+                .map(|proto| proto.to_ascii_lowercase())
+                .filter(|proto| proto == "http" || proto == "https"),
+            host: host.filter(|host| is_valid_host(host)),
+            prefix: value_at_hop(header_values(headers, &X_FORWARDED_PREFIX), depth)
+                .and_then(|prefix| normalize_prefix(&prefix)),
+            user: None,
+        }
+    }
+
+    /// the url the client would use for a server-relative path, absolute when
+    /// the proxy reported both scheme and host
+    pub fn public_url(&self, path: &str) -> String {
+        let prefix = self.prefix.as_deref().unwrap_or("");
+        match (&self.proto, &self.host) {
+            (Some(proto), Some(host)) => format!("{proto}://{host}{prefix}{path}"),
+            _ => format!("{prefix}{path}"),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().cloned().unwrap_or_default())
+    }
+}
+
+/// record the resolved client on every request for handlers and logging
+pub async fn resolve_client(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response<Body> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<PeerAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    let client = ClientInfo::resolve(peer, request.headers(), &state.trusted_proxies);
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+/// request span carrying the resolved client address
+pub fn make_request_span(request: &Request) -> Span {
+    let client = request
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client| client.ip)
+        .map_or_else(|| "-".to_string(), |ip| ip.to_string());
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        client = %client,
+    )
+}
+
+// comma separated values across every instance of a header, in order
+fn header_values(headers: &HeaderMap, name: &HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+// one hop of an rfc 7239 `Forwarded` header
+#[derive(Debug, Default)]
+struct ForwardedElement {
+    for_ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn parse_forwarded(element: &str) -> ForwardedElement {
+    let mut parsed = ForwardedElement::default();
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => parsed.for_ip = parse_node(value),
+            "proto" => parsed.proto = Some(value.to_string()),
+            "host" => parsed.host = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    parsed
+}
+
+// `192.0.2.1`, `192.0.2.1:80`, `[2001:db8::1]:80` or `2001:db8::1`; obfuscated
+// and `unknown` nodes carry no address
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .map(|ip| ip.to_canonical())
+}
+
+// walk back from the nearest hop, stopping at the first address that is not
+// a trusted proxy; an address hidden by a proxy ends the walk at that proxy.
+// returns the client and how many hops from the end the walk took
+fn walk_chain(
+    peer_ip: Option<IpAddr>,
+    chain: &[Option<IpAddr>],
+    trusted: &TrustedProxies,
+) -> (Option<IpAddr>, usize) {
+    let mut client = peer_ip;
+    let mut depth = 0;
+    for hop in chain.iter().rev() {
+        depth += 1;
+        let Some(hop) = hop else {
+            break;
+        };
+        client = Some(*hop);
+        if !trusted.contains(*hop) {
+            break;
+        }
+    }
+    (client, depth)
+}
+
+// the value added at `depth` hops from the end; proxies that overwrite rather
+// than append leave fewer values, and then the first one is theirs
+fn value_at_hop<T>(mut values: Vec<T>, depth: usize) -> Option<T> {
+    let index = values.len().saturating_sub(depth.max(1));
+    (index < values.len()).then(|| values.swap_remove(index))
+}
+
+fn is_valid_host(host: &str) -> bool {
+    !host.contains('@') && host.parse::<Authority>().is_ok()
+}
+
+fn normalize_prefix(prefix: &str) -> Option<String> {
+    let prefix = prefix.trim_end_matches('/');
+    let valid = prefix.starts_with('/')
+        && !prefix.starts_with("//")
+        && prefix
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !matches!(byte, b'?' | b'#' | b'\\'));
+    valid.then(|| prefix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap()
+    }
+
+    fn peer(addr: &str) -> Option<PeerAddr> {
+        Some(PeerAddr::Tcp(addr.parse().unwrap()))
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let client = ClientInfo::resolve(peer("192.0.2.1:5000"), &headers, &trusted());
+        assert_eq!(client.ip, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(client.proto, None);
+
+        let client = ClientInfo::resolve(None, &headers, &trusted());
+        assert_eq!(client, ClientInfo::default());
+    }
+
+    #[test]
+    fn skips_trusted_hops_in_forwarded_for() {
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.7, 203.0.113.9"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-proto", "HTTPS"),
+            ("x-forwarded-host", "files.example.com"),
+            ("x-forwarded-prefix", "/files/"),
+        ]);
+        let client = ClientInfo::resolve(peer("10.0.0.1:5000"), &headers, &trusted());
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(client.proto.as_deref(), Some("https"));
+        assert_eq!(client.host.as_deref(), Some("files.example.com"));
+        assert_eq!(client.prefix.as_deref(), Some("/files"));
+        assert_eq!(
+            client.public_url("/docs/"),
+            "https://files.example.com/files/docs/"
+        );
+    }
+
+    #[test]
+    fn prefers_forwarded_header() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for="[2001:db8::7]:4711";proto=https;host=example.com, for=10.0.0.2"#,
+            ),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        let client = ClientInfo::resolve(peer("10.0.0.1:5000"), &headers, &trusted());
+        assert_eq!(client.ip, Some("2001:db8::7".parse().unwrap()));
+        assert_eq!(client.proto.as_deref(), Some("https"));
+        assert_eq!(client.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn ignores_client_supplied_hosts_ahead_of_the_proxy() {
+        let forwarded = headers(&[(
+            "forwarded",
+            "host=evil;proto=http, for=203.0.113.9;proto=https;host=files.example.com",
+        )]);
+        let client = ClientInfo::resolve(peer("10.0.0.1:5000"), &forwarded, &trusted());
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(client.proto.as_deref(), Some("https"));
+        assert_eq!(client.host.as_deref(), Some("files.example.com"));
+
+        let x_forwarded = headers(&[
+            ("x-forwarded-for", "198.51.100.7, 203.0.113.9"),
+            ("x-forwarded-host", "evil.example.com, files.example.com"),
+            ("x-forwarded-proto", "http, https"),
+            ("x-forwarded-prefix", "/evil, /files"),
+        ]);
+        let client = ClientInfo::resolve(peer("10.0.0.1:5000"), &x_forwarded, &trusted());
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(
+            client.public_url("/docs/"),
+            "https://files.example.com/files/docs/"
+        );
+    }
+
+    #[test]
+    fn rejects_unusable_forwarded_values() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                "for=unknown;proto=gopher;host=evil@example.com",
+            ),
+            ("x-forwarded-prefix", "//evil.example.com"),
+        ]);
+        let client = ClientInfo::resolve(peer("10.0.0.1:5000"), &headers, &trusted());
+        assert_eq!(client.ip, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(client.proto, None);
+        assert_eq!(client.host, None);
+        assert_eq!(client.prefix, None);
+        assert_eq!(client.public_url("/docs/"), "/docs/");
+    }
+
+    #[test]
+    fn trusts_unix_socket_peers_once_proxies_are_configured() {
+        let headers = headers(&[("x-forwarded-for", "203.0.113.9")]);
+        let client = ClientInfo::resolve(Some(PeerAddr::Unix), &headers, &trusted());
+        assert_eq!(client.ip, Some("203.0.113.9".parse().unwrap()));
+
+        let client =
+            ClientInfo::resolve(Some(PeerAddr::Unix), &headers, &TrustedProxies::default());
+        assert_eq!(client.ip, None);
+    }
+}
@@ -1,5 +1,7 @@
 // middleware module
 
 pub mod auth;
+pub mod compression;
 pub mod cors;
+pub mod forwarded;
 pub mod security;
@@ -1,10 +1,23 @@
 // server module public api
 
 pub mod app;
+pub mod conditional;
+pub mod encoding;
 pub mod fs;
 pub mod handlers;
+pub mod handshake;
+pub mod listen;
 pub mod listing;
 pub mod middleware;
+pub mod proxy;
+pub mod ranges;
+pub mod reload;
+pub mod shutdown;
+#[cfg(unix)]
+pub mod systemd;
+pub mod throttle;
+pub mod tls;
 pub mod uploads;
+pub mod vhost;
 
 pub use app::start_server;
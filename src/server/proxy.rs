@@ -0,0 +1,230 @@
+// connection peer addresses and haproxy proxy protocol support
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpStream};
+
+use super::handshake::HandshakeListener;
+use crate::utils::cidr::TrustedProxies;
+
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// the directly connected peer, recorded as connect info for every connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerAddr {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self::Tcp(*stream.remote_addr())
+    }
+}
+
+impl<Io> Connected<IncomingStream<'_, HandshakeListener<Io>>> for PeerAddr
+where
+    HandshakeListener<Io>: Listener<Addr = SocketAddr>,
+{
+    fn connect_info(stream: IncomingStream<'_, HandshakeListener<Io>>) -> Self {
+        Self::Tcp(*stream.remote_addr())
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, UnixListener>> for PeerAddr {
+    fn connect_info(_: IncomingStream<'_, UnixListener>) -> Self {
+        Self::Unix
+    }
+}
+
+/// tcp listener that reads a proxy protocol v1 or v2 header from every
+/// connection and reports the client address it carries; connections from
+/// peers outside the trusted proxies are closed
+pub type ProxyProtocolListener = HandshakeListener<TcpStream>;
+
+impl HandshakeListener<TcpStream> {
+    pub fn new(listener: TcpListener, trusted: Arc<TrustedProxies>) -> io::Result<Self> {
+        Self::with_handshake(
+            listener,
+            "proxy protocol header",
+            HEADER_TIMEOUT,
+            move |mut stream, peer_addr| {
+                let trusted = trusted.clone();
+                async move {
+                    if !trusted.contains(peer_addr.ip()) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "peer is not a trusted proxy",
+                        ));
+                    }
+                    let source = read_proxy_header(&mut stream).await?;
+                    Ok((stream, source.unwrap_or(peer_addr)))
+                }
+            },
+        )
+    }
+}
+
+/// consume a proxy protocol header, returning the source address it carries;
+/// `None` for health checks and unknown transports, which keep the peer address.
+/// the header is read without buffering past its end so the stream can be
+/// handed on untouched
+pub async fn read_proxy_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut start = [0u8; 8];
+    stream.read_exact(&mut start).await?;
+
+    if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("proxy protocol v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("invalid v1 header"))?;
+        parse_v1(line.trim_end())
+    } else if start == V2_SIGNATURE[..8] {
+        let mut rest = [0u8; 8];
+        stream.read_exact(&mut rest).await?;
+        if rest[..4] != V2_SIGNATURE[8..] {
+            return Err(invalid("invalid proxy protocol v2 signature"));
+        }
+        let length = u16::from_be_bytes([rest[6], rest[7]]) as usize;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).await?;
+        parse_v2(rest[4], rest[5], &payload)
+    } else {
+        Err(invalid("missing proxy protocol header"))
+    }
+}
+
+// `PROXY TCP4 <src> <dst> <sport> <dport>` or `PROXY UNKNOWN ...`
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("invalid v1 address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("invalid v1 port"))?;
+            if ip.is_ipv4() != (fields[1] == "TCP4") {
+                return Err(invalid("v1 address does not match its protocol"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed proxy protocol v1 header")),
+    }
+}
+
+// address blocks of the binary v2 format, which start with the source address
+fn parse_v2(version_command: u8, family: u8, payload: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported proxy protocol version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL connections come from the proxy itself, e.g. health checks
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unsupported proxy protocol v2 command")),
+    }
+
+    let addr = match family >> 4 {
+        1 => {
+            let block = payload
+                .get(..12)
+                .ok_or_else(|| invalid("short v2 address"))?;
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([block[8], block[9]]))
+        }
+        2 => {
+            let block = payload
+                .get(..36)
+                .ok_or_else(|| invalid("short v2 address"))?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&block[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([block[32], block[33]]))
+        }
+        // unspecified and unix socket sources carry no usable client address
+        _ => return Ok(None),
+    };
+
+    Ok(Some(addr))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(mut header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        read_proxy_header(&mut header).await
+    }
+
+    #[tokio::test]
+    async fn parses_v1_headers() {
+        let addr = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n")
+            .await
+            .unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+
+        let addr = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n")
+            .await
+            .unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+        assert!(parse(b"PROXY TCP4 2001:db8::1 ::1 1 2\r\n").await.is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stops_reading_at_end_of_header() {
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 1 2\r\nGET /";
+        read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(input, b"GET /");
+
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LENGTH));
+        assert!(parse(long.as_bytes()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn parses_v2_headers() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+        header.extend(56324u16.to_be_bytes());
+        header.extend(443u16.to_be_bytes());
+        header.extend(b"GET /");
+
+        let mut input = header.as_slice();
+        let addr = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(input, b"GET /");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([0x20, 0x00, 0, 0]);
+        assert_eq!(parse(&local).await.unwrap(), None);
+
+        let mut truncated = V2_SIGNATURE.to_vec();
+        truncated.extend([0x21, 0x21, 0, 4, 0, 0, 0, 0]);
+        assert!(parse(&truncated).await.is_err());
+    }
+}
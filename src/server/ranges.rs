@@ -0,0 +1,231 @@
+// byte range resolution and multipart/byteranges response bodies
+
+use axum::body::Bytes;
+use futures_util::stream::{self, Stream};
+use http_range_header::{EndPosition, ParsedRanges, RangeUnsatisfiableError, StartPosition};
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// requests asking for more ranges than this get the whole file
+const MAX_RANGES: usize = 32;
+
+/// validate parsed ranges against the file size and merge overlapping or adjacent ones;
+/// no ranges at all means the whole file should be served
+pub fn satisfiable_ranges(
+    parsed: &ParsedRanges,
+    file_size: u64,
+) -> Result<Vec<RangeInclusive<u64>>, RangeUnsatisfiableError> {
+    if parsed.ranges.len() > MAX_RANGES {
+        return Ok(Vec::new());
+    }
+
+    match parsed.validate(file_size) {
+        Ok(ranges) => Ok(coalesce_ranges(ranges)),
+        Err(RangeUnsatisfiableError::OverlappingRanges) => {
+            // overlapping ranges are legal in a request, so merge them instead of rejecting
+            let ranges = parsed
+                .ranges
+                .iter()
+                .map(|range| {
+                    let start = match range.start {
+                        StartPosition::Index(i) => i,
+                        StartPosition::FromLast(i) => file_size.saturating_sub(i),
+                    };
+                    let end = match range.end {
+                        EndPosition::Index(i) => i.min(file_size.saturating_sub(1)),
+                        EndPosition::LastByte => file_size.saturating_sub(1),
+                    };
+                    start..=end
+                })
+                .collect();
+            Ok(coalesce_ranges(ranges))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// sort ranges and merge any that overlap or touch
+pub fn coalesce_ranges(mut ranges: Vec<RangeInclusive<u64>>) -> Vec<RangeInclusive<u64>> {
+    ranges.sort_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                let end = (*last.end()).max(*range.end());
+                *last = *last.start()..=end;
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// layout of a multipart/byteranges body
+pub struct ByteRangesBody {
+    pub boundary: String,
+    parts: Vec<(RangeInclusive<u64>, Bytes)>,
+    trailer: Bytes,
+}
+
+impl ByteRangesBody {
+    pub fn new(ranges: &[RangeInclusive<u64>], file_size: u64, mime_type: &str) -> Self {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let boundary = format!("soop3-{unique:x}");
+
+        let parts = ranges
+            .iter()
+            .map(|range| {
+                let header = format!(
+                    "\r\n--{boundary}\r\nContent-Type: {mime_type}\r\nContent-Range: bytes {}-{}/{file_size}\r\n\r\n",
+                    range.start(),
+                    range.end()
+                );
+                (range.clone(), Bytes::from(header))
+            })
+            .collect();
+        let trailer = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+
+        Self {
+            boundary,
+            parts,
+            trailer,
+        }
+    }
+
+    /// content type header value including the boundary parameter
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// exact length of the encoded body
+    pub fn content_length(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(range, header)| header.len() as u64 + (range.end() - range.start() + 1))
+            .sum();
+        parts + self.trailer.len() as u64
+    }
+
+    /// stream the body, reading each range from the file as it is reached
+    pub fn into_stream(self, file: File) -> impl Stream<Item = std::io::Result<Bytes>> {
+        let state = StreamState {
+            file,
+            parts: self.parts.into_iter().collect(),
+            remaining: 0,
+            trailer: Some(self.trailer),
+        };
+
+        stream::unfold(state, |mut state| async move {
+            if state.remaining > 0 {
+                let chunk_len = state.remaining.min(CHUNK_SIZE as u64) as usize;
+                let mut buf = vec![0u8; chunk_len];
+                return match state.file.read(&mut buf).await {
+                    Ok(0) => {
+                        let err = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                        Some((Err(err), state.finish()))
+                    }
+                    Ok(read) => {
+                        buf.truncate(read);
+                        state.remaining -= read as u64;
+                        Some((Ok(Bytes::from(buf)), state))
+                    }
+                    Err(err) => Some((Err(err), state.finish())),
+                };
+            }
+
+            if let Some((range, header)) = state.parts.pop_front() {
+                if let Err(err) = state
+                    .file
+                    .seek(std::io::SeekFrom::Start(*range.start()))
+                    .await
+                {
+                    return Some((Err(err), state.finish()));
+                }
+                state.remaining = range.end() - range.start() + 1;
+                return Some((Ok(header), state));
+            }
+
+            state.trailer.take().map(|trailer| (Ok(trailer), state))
+        })
+    }
+}
+
+struct StreamState {
+    file: File,
+    parts: VecDeque<(RangeInclusive<u64>, Bytes)>,
+    remaining: u64,
+    trailer: Option<Bytes>,
+}
+
+impl StreamState {
+    // stop producing output after an error
+    fn finish(mut self) -> Self {
+        self.parts.clear();
+        self.remaining = 0;
+        self.trailer = None;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_range_header::parse_range_header;
+
+    #[test]
+    fn coalesces_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            coalesce_ranges(vec![500..=599, 0..=99, 50..=150, 151..=200]),
+            vec![0..=200, 500..=599]
+        );
+        assert_eq!(coalesce_ranges(vec![0..=10, 2..=3]), vec![0..=10]);
+        assert_eq!(coalesce_ranges(vec![0..=1, 3..=4]), vec![0..=1, 3..=4]);
+    }
+
+    #[test]
+    fn resolves_overlapping_requests() {
+        let parsed = parse_range_header("bytes=0-99,50-149,-10").unwrap();
+        assert_eq!(
+            satisfiable_ranges(&parsed, 1000).unwrap(),
+            vec![0..=149, 990..=999]
+        );
+
+        let parsed = parse_range_header("bytes=10-20").unwrap();
+        assert!(satisfiable_ranges(&parsed, 6).is_err());
+    }
+
+    #[test]
+    fn too_many_ranges_fall_back_to_the_whole_file() {
+        let header = |count: u64| {
+            let ranges: Vec<_> = (0..count).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+            format!("bytes={}", ranges.join(","))
+        };
+
+        let parsed = parse_range_header(&header(MAX_RANGES as u64)).unwrap();
+        assert_eq!(satisfiable_ranges(&parsed, 1000).unwrap().len(), MAX_RANGES);
+        let parsed = parse_range_header(&header(MAX_RANGES as u64 + 1)).unwrap();
+        assert!(satisfiable_ranges(&parsed, 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn byteranges_length_matches_layout() {
+        let body = ByteRangesBody::new(&[0..=1, 4..=5], 6, "text/plain");
+        let headers: u64 = body.parts.iter().map(|(_, h)| h.len() as u64).sum();
+        assert_eq!(
+            body.content_length(),
+            headers + 4 + body.trailer.len() as u64
+        );
+        assert!(body.content_type().ends_with(&body.boundary));
+    }
+}
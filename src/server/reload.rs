@@ -0,0 +1,78 @@
+// configuration hot reloading
+
+use axum::{Router, extract::Request};
+use std::sync::{Arc, PoisonError, RwLock};
+use tower::{ServiceExt, service_fn};
+use tracing::{error, info, warn};
+
+use super::app::{AppState, create_app};
+use crate::config::{Cli, load_configuration, reload::config_changes};
+use crate::utils::watch;
+
+/// the application router, replaced as a whole when the configuration is
+/// reloaded; requests already in flight finish on the router they started on
+#[derive(Debug, Clone)]
+pub struct ReloadableApp {
+    current: Arc<RwLock<Router>>,
+}
+
+impl ReloadableApp {
+    pub fn new(app: Router) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(app)),
+        }
+    }
+
+    pub fn replace(&self, app: Router) {
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = app;
+    }
+
+    /// a router handing every request to the current application
+    pub fn router(&self) -> Router {
+        let current = self.current.clone();
+        Router::new().fallback_service(service_fn(move |request: Request| {
+            let app = current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone();
+            app.oneshot(request)
+        }))
+    }
+}
+
+/// reload the configuration on SIGHUP and whenever the config file changes,
+/// keeping the running configuration when the new one fails validation
+pub fn spawn_config_reloader(cli: Cli, state: AppState, app: ReloadableApp) {
+    let files = cli.config_file.iter().cloned().collect();
+    let mut state = state;
+
+    watch::spawn_reloader("configuration", files, move || {
+        let config = match load_configuration(&cli) {
+            Ok(config) => config,
+            Err(err) => {
+                error!(
+                    "failed to reload configuration, keeping the current one: {:#}",
+                    err
+                );
+                return;
+            }
+        };
+
+        let changes = config_changes(&state.config, &config);
+        if changes.is_empty() {
+            info!("configuration unchanged");
+            return;
+        }
+        for change in &changes {
+            if change.requires_restart() {
+                warn!("changed {} (takes effect after a restart)", change);
+            } else {
+                info!("changed {}", change);
+            }
+        }
+
+        state = state.with_config(config);
+        app.replace(create_app(state.clone()));
+        info!("reloaded configuration with {} changes", changes.len());
+    });
+}
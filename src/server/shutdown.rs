@@ -0,0 +1,76 @@
+// graceful shutdown signalling and connection draining
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    extract::connect_info::Connected,
+    serve::{IncomingStream, Listener},
+};
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::proxy::PeerAddr;
+
+/// resolve once SIGINT or SIGTERM is received
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!("failed to listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                warn!("failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("received SIGINT, shutting down"),
+        _ = terminate => info!("received SIGTERM, shutting down"),
+    }
+}
+
+/// serve until `shutdown` is cancelled, then stop accepting connections and
+/// give in-flight requests up to `drain_timeout` to finish
+pub async fn serve_until_shutdown<L>(
+    listener: L,
+    app: Router,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) -> Result<()>
+where
+    L: Listener,
+    L::Addr: Debug,
+    PeerAddr: for<'a> Connected<IncomingStream<'a, L>>,
+{
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<PeerAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    let drain_deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(drain_timeout).await;
+    };
+
+    tokio::select! {
+        result = server => result.context("server error"),
+        _ = drain_deadline => {
+            warn!("drain timeout elapsed, closing remaining connections");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// systemd socket activation and service manager notifications
+
+use anyhow::{Context, Result};
+use listenfd::ListenFd;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// listeners passed in by the service manager through `LISTEN_FDS`
+#[derive(Default)]
+pub struct InheritedListeners {
+    pub tcp: Vec<TcpListener>,
+    pub unix: Vec<UnixListener>,
+}
+
+impl InheritedListeners {
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_empty() && self.unix.is_empty()
+    }
+
+    /// describe each inherited socket for the startup banner
+    pub fn endpoints(&self, scheme: &str) -> Vec<String> {
+        let tcp = self
+            .tcp
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .map(|addr| format!("{scheme}://{addr}"));
+        let unix = self
+            .unix
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .map(|addr| match addr.as_pathname() {
+                Some(path) => format!("unix:{}", path.display()),
+                None => "unix:(unnamed)".to_string(),
+            });
+        tcp.chain(unix).collect()
+    }
+}
+
+/// take the sockets systemd bound for this process, if it was socket activated
+pub fn inherited_listeners() -> Result<InheritedListeners> {
+    let mut fds = ListenFd::from_env();
+    let mut listeners = InheritedListeners::default();
+
+    for index in 0..fds.len() {
+        // a non-tcp socket is left in place, so fall back to a unix listener
+        if let Ok(Some(listener)) = fds.take_tcp_listener(index) {
+            listener.set_nonblocking(true)?;
+            listeners.tcp.push(TcpListener::from_std(listener)?);
+            continue;
+        }
+
+        let listener = fds
+            .take_unix_listener(index)
+            .with_context(|| format!("inherited socket {index} is not a stream listener"))?;
+        if let Some(listener) = listener {
+            listener.set_nonblocking(true)?;
+            listeners.unix.push(UnixListener::from_std(listener)?);
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// sends state changes to the service manager over `NOTIFY_SOCKET`
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: UnixSocketAddr,
+}
+
+impl Notifier {
+    /// connect to the socket named by `NOTIFY_SOCKET`, if one was set
+    pub fn from_env() -> Option<Self> {
+        let target = env::var_os("NOTIFY_SOCKET")?;
+        match Self::connect(&target) {
+            Ok(notifier) => Some(notifier),
+            Err(err) => {
+                warn!("failed to open notify socket {:?}: {}", target, err);
+                None
+            }
+        }
+    }
+
+    /// target a socket path, or an abstract socket name prefixed with `@`
+    pub fn connect(target: &OsStr) -> io::Result<Self> {
+        let addr = match target.as_bytes().strip_prefix(b"@") {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Some(name) => {
+                #[cfg(target_os = "android")]
+                use std::os::android::net::SocketAddrExt;
+                #[cfg(target_os = "linux")]
+                use std::os::linux::net::SocketAddrExt;
+                UnixSocketAddr::from_abstract_name(name)?
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "abstract notify sockets are not supported on this platform",
+                ));
+            }
+            None => UnixSocketAddr::from_pathname(Path::new(target))?,
+        };
+
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        })
+    }
+
+    /// send a newline separated list of `KEY=VALUE` assignments
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+
+    pub fn ready(&self) {
+        self.send_logged("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send_logged("STOPPING=1");
+    }
+
+    fn send_logged(&self, state: &str) {
+        match self.notify(state) {
+            Ok(()) => debug!("notified service manager: {}", state),
+            Err(err) => warn!("failed to notify service manager ({}): {}", state, err),
+        }
+    }
+}
+
+/// ping the watchdog at half the interval requested through `WATCHDOG_USEC`
+/// until `shutdown` is cancelled
+pub fn spawn_watchdog(notifier: Arc<Notifier>, shutdown: CancellationToken) {
+    let usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    let Some(interval) = watchdog_interval(usec.as_deref(), pid.as_deref(), std::process::id())
+    else {
+        return;
+    };
+    debug!("pinging service manager watchdog every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(err) = notifier.notify("WATCHDOG=1") {
+                        warn!("failed to ping service manager watchdog: {}", err);
+                    }
+                }
+                _ = shutdown.cancelled() => break,
+            }
+        }
+    });
+}
+
+// the watchdog applies to this process only when WATCHDOG_PID is unset or ours
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok() != Some(own_pid)
+    {
+        return None;
+    }
+
+    match usec?.parse::<u64>() {
+        Ok(usec) if usec > 0 => Some(Duration::from_micros(usec) / 2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_states_to_notify_socket() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::connect(path.as_os_str()).unwrap();
+        notifier.ready();
+        notifier.stopping();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sends_to_abstract_notify_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("soop3-notify-test-{}", std::process::id());
+        let receiver =
+            UnixDatagram::bind_addr(&UnixSocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+        let notifier = Notifier::connect(OsStr::new(&format!("@{name}"))).unwrap();
+        notifier.notify("WATCHDOG=1").unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+    }
+
+    #[test]
+    fn parses_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval(Some("10000000"), None, 42),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            watchdog_interval(Some("10000000"), Some("42"), 42),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(watchdog_interval(Some("10000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+}
@@ -0,0 +1,288 @@
+// lockouts after repeated failed logins
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+// entries are swept once the table grows past this size
+const MIN_SWEEP_SIZE: usize = 1024;
+
+/// failed logins per client ip (`ip:<addr>`) and, unless `per_user` is off, user
+/// name
+/// (`user:<name>`)
+///
+/// reaching `max_failures` locks a key out for `lockout` seconds, doubled by
+/// every further failure up to `max_lockout`; a successful login clears the
+/// user name's count
+#[derive(Debug, Default)]
+pub struct LoginThrottle {
+    state: Mutex<ThrottleState>,
+    /// the throttles of virtual hosts, by their host names
+    sites: Mutex<HashMap<String, Arc<LoginThrottle>>>,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    attempts: HashMap<String, Attempts>,
+    sweep_at: usize,
+}
+
+impl ThrottleState {
+    fn refund(&mut self, key: &str, config: &RateLimitConfig) {
+        let Some(attempts) = self.attempts.get_mut(key) else {
+            return;
+        };
+        if attempts.failures == config.max_failures {
+            attempts.locked_until = None;
+        }
+        attempts.failures = attempts.failures.saturating_sub(1);
+        if attempts.failures == 0 {
+            self.attempts.remove(key);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attempts {
+    failures: u32,
+    first_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    fn is_stale(&self, config: &RateLimitConfig, now: Instant) -> bool {
+        let window = Duration::from_secs(config.failure_window);
+        self.locked_until.is_none_or(|until| until <= now)
+            && now.saturating_duration_since(self.first_failure) >= window
+    }
+}
+
+impl LoginThrottle {
+    /// the throttle of a virtual host, so failed logins on one site never lock
+    /// clients out of another; it lives as long as this throttle, across reloads
+    pub fn site(&self, label: &str) -> Arc<LoginThrottle> {
+        self.sites
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(label.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// count a login attempt against every key before it is verified, so
+    /// concurrent guesses cannot slip past the lockout between check and count
+    ///
+    /// a locked out key refuses the attempt with its remaining lockout;
+    /// otherwise returns the lockout the attempt starts should it fail
+    pub fn begin_attempt(
+        &self,
+        keys: &[String],
+        config: &RateLimitConfig,
+        now: Instant,
+    ) -> Result<Option<Duration>, Duration> {
+        let mut state = self.lock();
+        if let Some(remaining) = keys
+            .iter()
+            .filter_map(|key| state.attempts.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+        {
+            return Err(remaining);
+        }
+
+        if state.attempts.len() >= state.sweep_at.max(MIN_SWEEP_SIZE) {
+            state
+                .attempts
+                .retain(|_, attempts| !attempts.is_stale(config, now));
+            state.sweep_at = state.attempts.len() * 2;
+        }
+
+        let mut lockout = None;
+        for key in keys {
+            let attempts = state
+                .attempts
+                .entry(key.clone())
+                .or_insert_with(|| Attempts {
+                    failures: 0,
+                    first_failure: now,
+                    locked_until: None,
+                });
+            if attempts.is_stale(config, now) {
+                *attempts = Attempts {
+                    failures: 0,
+                    first_failure: now,
+                    locked_until: None,
+                };
+            }
+
+            attempts.failures += 1;
+            if attempts.failures >= config.max_failures {
+                let duration = lockout_duration(attempts.failures - config.max_failures, config);
+                attempts.locked_until = Some(now + duration);
+                lockout = lockout.max(Some(duration));
+            }
+        }
+        Ok(lockout)
+    }
+
+    /// take back an attempt that turned out not to be a failed login,
+    /// lifting the lockout it started
+    pub fn refund(&self, keys: &[String], config: &RateLimitConfig) {
+        let mut state = self.lock();
+        for key in keys {
+            state.refund(key, config);
+        }
+    }
+
+    /// after a successful login forget the user name's failures; the client
+    /// ip only gets its attempt back, so guesses at other accounts from the
+    /// same address keep counting
+    pub fn record_success(&self, keys: &[String], config: &RateLimitConfig) {
+        let mut state = self.lock();
+        for key in keys {
+            if key.starts_with("user:") {
+                state.attempts.remove(key);
+            } else {
+                state.refund(key, config);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// exponential backoff: lockout, 2 * lockout, 4 * lockout, ... up to max_lockout
+fn lockout_duration(extra_failures: u32, config: &RateLimitConfig) -> Duration {
+    let factor = 1u64.checked_shl(extra_failures).unwrap_or(u64::MAX);
+    Duration::from_secs(
+        config
+            .lockout
+            .saturating_mul(factor)
+            .min(config.max_lockout.max(config.lockout)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            max_failures: 3,
+            failure_window: 60,
+            lockout: 10,
+            max_lockout: 35,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn locks_out_after_repeated_failures_with_backoff() {
+        let throttle = LoginThrottle::default();
+        let config = config();
+        let keys = ["ip:10.0.0.1".to_string(), "user:alice".to_string()];
+        let start = Instant::now();
+
+        assert_eq!(throttle.begin_attempt(&keys, &config, start), Ok(None));
+        assert_eq!(throttle.begin_attempt(&keys, &config, start), Ok(None));
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, start),
+            Ok(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            throttle.begin_attempt(&keys[1..], &config, start + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, later),
+            Ok(Some(Duration::from_secs(20)))
+        );
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, later),
+            Err(Duration::from_secs(20))
+        );
+        let later = later + Duration::from_secs(20);
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, later),
+            Ok(Some(Duration::from_secs(35)))
+        );
+
+        // other keys are unaffected
+        let bob = ["user:bob".to_string()];
+        assert_eq!(throttle.begin_attempt(&bob, &config, later), Ok(None));
+    }
+
+    #[test]
+    fn success_clears_the_user_but_not_the_address() {
+        let throttle = LoginThrottle::default();
+        let config = config();
+        let ip = "ip:10.0.0.1".to_string();
+        let now = Instant::now();
+
+        for user in ["user:root", "user:guest"] {
+            let keys = [ip.clone(), user.to_string()];
+            throttle.begin_attempt(&keys, &config, now).unwrap();
+        }
+        let keys = [ip.clone(), "user:alice".to_string()];
+        throttle.begin_attempt(&keys, &config, now).unwrap();
+        throttle.record_success(&keys, &config);
+
+        // the guesses at root and guest still count against the address
+        let keys = [ip, "user:alice".to_string()];
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, now),
+            Ok(Some(Duration::from_secs(10)))
+        );
+    }
+
+    #[test]
+    fn refunded_attempts_lift_their_lockout() {
+        let throttle = LoginThrottle::default();
+        let config = config();
+        let keys = ["ip:10.0.0.1".to_string()];
+        let now = Instant::now();
+
+        throttle.begin_attempt(&keys, &config, now).unwrap();
+        throttle.begin_attempt(&keys, &config, now).unwrap();
+        assert!(
+            throttle
+                .begin_attempt(&keys, &config, now)
+                .unwrap()
+                .is_some()
+        );
+        assert!(throttle.begin_attempt(&keys, &config, now).is_err());
+
+        throttle.refund(&keys, &config);
+        assert_eq!(
+            throttle.begin_attempt(&keys, &config, now),
+            Ok(Some(Duration::from_secs(10)))
+        );
+    }
+
+    #[test]
+    fn failures_expire_after_the_window() {
+        let throttle = LoginThrottle::default();
+        let config = config();
+        let keys = ["user:alice".to_string()];
+        let start = Instant::now();
+
+        throttle.begin_attempt(&keys, &config, start).unwrap();
+        throttle.begin_attempt(&keys, &config, start).unwrap();
+        let later = start + Duration::from_secs(60);
+        assert_eq!(throttle.begin_attempt(&keys, &config, later), Ok(None));
+        assert_eq!(throttle.begin_attempt(&keys, &config, later), Ok(None));
+        assert!(
+            throttle
+                .begin_attempt(&keys, &config, later)
+                .unwrap()
+                .is_some()
+        );
+    }
+}
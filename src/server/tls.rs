@@ -0,0 +1,310 @@
+// https termination with rustls and certificate hot reloading
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    http::{HeaderMap, StatusCode, Uri, header, uri::Authority},
+    response::{IntoResponse, Redirect, Response},
+    serve::Listener,
+};
+use rustls::{
+    ServerConfig,
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tracing::{debug, error, info, warn};
+
+use super::handshake::HandshakeListener;
+use crate::utils::watch;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// serves the current certificate and swaps it in place on reload,
+/// so new handshakes pick up renewed certificates while open connections continue
+#[derive(Debug)]
+pub struct CertificateResolver {
+    /// certificate and key paths, absent for generated certificates
+    files: Option<(PathBuf, PathBuf)>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let certified = load_certified_key(cert_path, key_path)?;
+
+        Ok(Self {
+            files: Some((cert_path.to_path_buf(), key_path.to_path_buf())),
+            current: RwLock::new(Arc::new(certified)),
+        })
+    }
+
+    /// serve a fixed in-memory certificate that is never reloaded
+    pub fn fixed(certified: CertifiedKey) -> Self {
+        Self {
+            files: None,
+            current: RwLock::new(Arc::new(certified)),
+        }
+    }
+
+    /// re-read the certificate and key, keeping the previous pair on failure
+    pub fn reload(&self) -> Result<()> {
+        let Some((cert_path, key_path)) = &self.files else {
+            return Ok(());
+        };
+
+        let certified = load_certified_key(cert_path, key_path)?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(certified);
+        Ok(())
+    }
+
+    /// sha-256 fingerprint of the leaf certificate currently being served
+    pub fn fingerprint(&self) -> String {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        current
+            .end_entity_cert()
+            .map(|cert| certificate_fingerprint(cert))
+            .unwrap_or_default()
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )
+    }
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// parse a pem certificate chain and private key into a signing pair
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read tls certificate: {}", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", cert_path.display());
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("failed to read tls private key: {}", key_path.display()))?;
+    let signing_key = crypto_provider()
+        .key_provider
+        .load_private_key(key)
+        .with_context(|| format!("unsupported tls private key: {}", key_path.display()))?;
+
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified.keys_match().with_context(|| {
+        format!(
+            "tls private key {} does not match certificate {}",
+            key_path.display(),
+            cert_path.display()
+        )
+    })?;
+
+    Ok(certified)
+}
+
+/// generate a self-signed certificate valid for `host`, localhost and every
+/// address of the local network interfaces
+pub fn self_signed_certificate(host: &str) -> Result<CertifiedKey> {
+    let mut names = vec!["localhost".to_string()];
+    let unspecified = host
+        .parse::<IpAddr>()
+        .is_ok_and(|addr| addr.is_unspecified());
+    if !host.is_empty() && !unspecified {
+        names.push(host.to_string());
+    }
+
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => names.extend(
+            interfaces
+                .iter()
+                .map(|interface| interface.ip().to_string()),
+        ),
+        Err(err) => warn!("failed to list network interfaces: {}", err),
+    }
+    for loopback in ["127.0.0.1", "::1"] {
+        names.push(loopback.to_string());
+    }
+    names.sort();
+    names.dedup();
+
+    let mut params = rcgen::CertificateParams::new(names.clone())
+        .context("invalid self-signed certificate names")?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "soop3 self-signed");
+
+    let key_pair = rcgen::KeyPair::generate().context("failed to generate tls key pair")?;
+    let cert = params
+        .self_signed(&key_pair)
+        .context("failed to generate self-signed certificate")?;
+    debug!("self-signed certificate names: {}", names.join(", "));
+
+    let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+    let signing_key = crypto_provider()
+        .key_provider
+        .load_private_key(key)
+        .context("failed to load generated tls key")?;
+
+    Ok(CertifiedKey::new(vec![cert.der().clone()], signing_key))
+}
+
+/// colon separated uppercase sha-256 digest of a der certificate
+pub fn certificate_fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// build the rustls server configuration around a reloadable resolver
+pub fn server_config(resolver: Arc<CertificateResolver>) -> Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .context("failed to configure tls protocol versions")?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// reload the certificate on SIGHUP and whenever the files change on disk
+pub fn spawn_certificate_reloader(resolver: Arc<CertificateResolver>) {
+    let Some((cert_path, key_path)) = resolver.files.clone() else {
+        return;
+    };
+
+    watch::spawn_reloader(
+        "tls certificate",
+        vec![cert_path, key_path],
+        move || match resolver.reload() {
+            Ok(()) => info!(
+                "reloaded tls certificate, sha-256 fingerprint: {}",
+                resolver.fingerprint()
+            ),
+            Err(err) => error!(
+                "failed to reload tls certificate, keeping the current one: {:#}",
+                err
+            ),
+        },
+    );
+}
+
+/// tcp listener that performs tls handshakes off the accept path
+pub type TlsListener<Io = TcpStream> = HandshakeListener<TlsStream<Io>>;
+
+impl<Io> HandshakeListener<TlsStream<Io>>
+where
+    Io: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new<L>(listener: L, config: Arc<ServerConfig>) -> io::Result<Self>
+    where
+        L: Listener<Io = Io, Addr = SocketAddr>,
+    {
+        let acceptor = TlsAcceptor::from(config);
+        Self::with_handshake(
+            listener,
+            "tls handshake",
+            HANDSHAKE_TIMEOUT,
+            move |stream, remote_addr| {
+                let accept = acceptor.accept(stream);
+                async move { Ok((accept.await?, remote_addr)) }
+            },
+        )
+    }
+}
+
+/// router for the plain http listener that sends every request to https
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        https_redirect(&headers, &uri, https_port)
+    })
+}
+
+fn https_redirect(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(authority) = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let host = authority.host();
+    let target_authority = if https_port == 443 {
+        host.to_string()
+    } else {
+        format!("{host}:{https_port}")
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    Redirect::permanent(&format!("https://{target_authority}{path}")).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn redirect_location(host: &str, uri: &str, https_port: u16) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_str(host).unwrap());
+        let response = https_redirect(&headers, &uri.parse().unwrap(), https_port);
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn self_signed_certificate_covers_host() {
+        let certified = self_signed_certificate("files.lan").unwrap();
+        certified.keys_match().unwrap();
+
+        let resolver = CertificateResolver::fixed(certified);
+        let fingerprint = resolver.fingerprint();
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        assert!(resolver.reload().is_ok());
+        assert_eq!(resolver.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn redirects_to_https_authority() {
+        assert_eq!(
+            redirect_location("example.com:8080", "/docs/a.txt?x=1", 8443),
+            "https://example.com:8443/docs/a.txt?x=1"
+        );
+        assert_eq!(
+            redirect_location("example.com", "/", 443),
+            "https://example.com/"
+        );
+        assert_eq!(
+            redirect_location("[::1]:80", "/file", 443),
+            "https://[::1]/file"
+        );
+
+        let response = https_redirect(&HeaderMap::new(), &Uri::from_static("/"), 443);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
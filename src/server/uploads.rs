@@ -1,8 +1,10 @@
 // upload processing and streaming helpers
 
+use std::collections::HashSet;
 use std::ffi::OsString;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use axum::{
@@ -14,8 +16,9 @@ use thiserror::Error;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 
-use crate::config::AppConfig;
-use crate::utils::paths::join_path_jailed;
+use crate::server::app::AppState;
+use crate::server::fs::Mount;
+use crate::utils::paths::join_decoded_path_jailed;
 
 const MAX_FILENAME_BYTES: usize = 255;
 
@@ -33,6 +36,8 @@ pub enum UploadError {
     InvalidBase,
     #[error("file already exists")]
     Conflict,
+    #[error("upload target is ignored")]
+    IgnoredTarget,
     #[error("payload too large")]
     PayloadTooLarge,
     #[error("io error: {0}")]
@@ -50,6 +55,7 @@ impl UploadError {
             UploadError::MissingDirectory => StatusCode::NOT_FOUND,
             UploadError::InvalidBase => StatusCode::INTERNAL_SERVER_ERROR,
             UploadError::Conflict => StatusCode::CONFLICT,
+            UploadError::IgnoredTarget => StatusCode::FORBIDDEN,
             UploadError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             UploadError::Io(err) => match err.kind() {
                 std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
@@ -64,29 +70,33 @@ impl UploadError {
     }
 }
 
+/// store one multipart file under `upload_path`, relative to the mount's upload directory
 pub async fn process_upload(
-    config: &AppConfig,
+    state: &AppState,
+    mount: &Mount,
     upload_path: &str,
     original_filename: String,
     field: Field<'_>,
 ) -> Result<PathBuf, UploadError> {
-    ensure_upload_base_dir(config).await?;
+    let config = &state.config;
+    ensure_upload_base_dir(&mount.upload_dir, config.upload.create_directories).await?;
     let sanitized_filename = sanitize_filename(&original_filename)?;
-    let encoded_filename = escape_percent_for_join(&sanitized_filename);
 
     // determine target filename - combine upload path with multipart filename
     let base_filename = if upload_path.is_empty() {
-        encoded_filename.clone()
+        sanitized_filename.clone()
     } else {
         // for directory paths, append the multipart filename
         let normalized_path = upload_path.trim_end_matches('/');
         if normalized_path.is_empty() {
-            encoded_filename.clone()
+            sanitized_filename.clone()
         } else {
-            format!("{normalized_path}/{encoded_filename}")
+            format!("{normalized_path}/{sanitized_filename}")
         }
     };
 
+    let requested_path = join_decoded_path_jailed(&mount.upload_dir, &base_filename)?;
+
     let (filename, filename_for_validation) = if config.upload.prepend_timestamp {
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
         (
@@ -100,7 +110,19 @@ pub async fn process_upload(
     validate_final_component(&filename_for_validation)?;
 
     // validate target path is within upload directory
-    let target_path = join_path_jailed(config.upload_dir(), &filename)?;
+    let target_path = join_decoded_path_jailed(&mount.upload_dir, &filename)?;
+
+    // refuse to create files that the ignore rules hide from clients, and
+    // ignore files that would rewrite the rules; both the name the client asked
+    // for and the timestamped one it is stored under are checked
+    if let Some(ignore_rules) = &mount.ignore_rules
+        && [&requested_path, &target_path].into_iter().any(|path| {
+            ignore_rules.is_ignore_file(path)
+                || (config.listing.enforce_ignore && ignore_rules.is_ignored(path, false))
+        })
+    {
+        return Err(UploadError::IgnoredTarget);
+    }
 
     // ensure parent directory exists
     if let Some(parent) = target_path.parent() {
@@ -124,6 +146,7 @@ pub async fn process_upload(
     // write file atomically with streaming
     write_multipart_field_streaming(
         field,
+        &state.partial_uploads,
         &target_path,
         config.upload.max_request_size,
         config.upload.prevent_overwrite,
@@ -133,9 +156,10 @@ pub async fn process_upload(
     Ok(target_path)
 }
 
-async fn ensure_upload_base_dir(config: &AppConfig) -> Result<(), UploadError> {
-    let upload_base = config.upload_dir();
-
+async fn ensure_upload_base_dir(
+    upload_base: &Path,
+    create_directories: bool,
+) -> Result<(), UploadError> {
     match fs::metadata(upload_base).await {
         Ok(metadata) => {
             if !metadata.is_dir() {
@@ -144,7 +168,7 @@ async fn ensure_upload_base_dir(config: &AppConfig) -> Result<(), UploadError> {
             Ok(())
         }
         Err(err) if err.kind() == ErrorKind::NotFound => {
-            if config.upload.create_directories {
+            if create_directories {
                 fs::create_dir_all(upload_base).await?;
                 Ok(())
             } else {
@@ -188,16 +212,9 @@ fn validate_final_component(path: &str) -> Result<(), UploadError> {
     Ok(())
 }
 
-fn escape_percent_for_join(value: &str) -> String {
-    if value.contains('%') {
-        value.replace('%', "%25")
-    } else {
-        value.to_string()
-    }
-}
-
 async fn write_multipart_field_streaming(
     mut field: Field<'_>,
+    partial_uploads: &Arc<PartialUploads>,
     target_path: &Path,
     max_bytes: u64,
     prevent_overwrite: bool,
@@ -216,48 +233,98 @@ async fn write_multipart_field_streaming(
             Err(err) => return Err(UploadError::Io(err)),
         };
 
-        let result = write_field_to_file(&mut field, &mut file, max_bytes).await;
-        if result.is_err() {
-            let _ = fs::remove_file(target_path).await;
-        }
-        return result;
+        // the target itself is partial until every byte has been written
+        let partial = partial_uploads.track(target_path);
+        let written = write_field_to_file(&mut field, &mut file, max_bytes).await?;
+        partial.complete();
+        return Ok(written);
     }
 
     let temp_path = temp_path_for_target(target_path);
+    let partial = partial_uploads.track(&temp_path);
     let mut file = fs::File::create(&temp_path).await?;
 
-    let written = match write_field_to_file(&mut field, &mut file, max_bytes).await {
-        Ok(written) => written,
-        Err(err) => {
-            let _ = fs::remove_file(&temp_path).await;
-            return Err(err);
-        }
-    };
+    let written = write_field_to_file(&mut field, &mut file, max_bytes).await?;
 
     drop(file);
 
     if let Err(err) = fs::rename(&temp_path, target_path).await {
-        match err.kind() {
-            ErrorKind::AlreadyExists => {
-                if let Err(remove_err) = fs::remove_file(target_path).await {
-                    let _ = fs::remove_file(&temp_path).await;
-                    return Err(UploadError::Io(remove_err));
-                }
-                if let Err(rename_err) = fs::rename(&temp_path, target_path).await {
-                    let _ = fs::remove_file(&temp_path).await;
-                    return Err(UploadError::Io(rename_err));
-                }
-            }
-            _ => {
-                let _ = fs::remove_file(&temp_path).await;
-                return Err(UploadError::Io(err));
-            }
+        if err.kind() != ErrorKind::AlreadyExists {
+            return Err(UploadError::Io(err));
         }
+        fs::remove_file(target_path).await?;
+        fs::rename(&temp_path, target_path).await?;
     }
+    partial.complete();
 
     Ok(written)
 }
 
+/// files of uploads that are still being written
+///
+/// each file is removed if its upload is abandoned, whether the request fails,
+/// the client disconnects or the server shuts down before it completes
+#[derive(Debug, Default)]
+pub struct PartialUploads {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+impl PartialUploads {
+    fn track(self: &Arc<Self>, path: &Path) -> PartialUpload {
+        self.lock().insert(path.to_path_buf());
+        PartialUpload {
+            registry: Arc::clone(self),
+            path: path.to_path_buf(),
+            completed: false,
+        }
+    }
+
+    /// number of uploads currently in progress
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// delete the files of every upload still in progress, returning how many were removed
+    pub fn remove_all(&self) -> usize {
+        let paths: Vec<PathBuf> = self.lock().drain().collect();
+        paths
+            .iter()
+            .filter(|path| std::fs::remove_file(path).is_ok())
+            .count()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashSet<PathBuf>> {
+        self.paths.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// guard for one partial upload file, deleting it on drop unless completed
+struct PartialUpload {
+    registry: Arc<PartialUploads>,
+    path: PathBuf,
+    completed: bool,
+}
+
+impl PartialUpload {
+    fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        let tracked = self.registry.lock().remove(&self.path);
+        // an untracked path was already cleaned up by remove_all
+        if tracked && !self.completed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
 fn temp_path_for_target(target_path: &Path) -> PathBuf {
     let unique = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -0,0 +1,61 @@
+// name based virtual host routing
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{HeaderMap, Uri, header},
+};
+use tower::{ServiceExt, service_fn};
+use tracing::debug;
+
+use crate::utils::hosts::{HostPattern, normalize_host};
+
+/// the site of every virtual host, picked by the request's host name
+#[derive(Debug, Clone, Default)]
+pub struct VirtualHosts {
+    /// most specific patterns first
+    sites: Vec<(HostPattern, Router)>,
+}
+
+impl VirtualHosts {
+    pub fn add(&mut self, pattern: HostPattern, site: Router) {
+        let position = self
+            .sites
+            .partition_point(|(existing, _)| existing.specificity() >= pattern.specificity());
+        self.sites.insert(position, (pattern, site));
+    }
+
+    /// the site serving `host`, if any pattern matches it
+    pub fn site_for(&self, host: &str) -> Option<&Router> {
+        self.sites
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host))
+            .map(|(_, site)| site)
+    }
+
+    /// a router sending each request to its virtual host, or to `default`
+    /// when no name matches
+    pub fn into_router(self, default: Router) -> Router {
+        Router::new().fallback_service(service_fn(move |request: Request| {
+            let host = request_host(request.headers(), request.uri());
+            let site = host
+                .as_deref()
+                .and_then(|host| self.site_for(host))
+                .unwrap_or_else(|| {
+                    debug!("no virtual host for {:?}, using the default", host);
+                    &default
+                })
+                .clone();
+            site.oneshot(request)
+        }))
+    }
+}
+
+// the `Host` header, or the uri authority of http/2 requests
+fn request_host(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| uri.authority().map(|authority| authority.as_str()))
+        .and_then(normalize_host)
+}
@@ -0,0 +1,448 @@
+// per-path access rules for users and groups
+
+use axum::http::Method;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use thiserror::Error;
+
+use super::passwords::Users;
+use crate::config::{AccessRuleConfig, Permission};
+
+#[derive(Error, Debug)]
+pub enum AccessRuleError {
+    #[error("invalid access rule path '{0}', expected a glob starting with '/'")]
+    InvalidPath(String),
+    #[error("invalid method '{0}'")]
+    InvalidMethod(String),
+    #[error("unknown user '{0}'")]
+    UnknownUser(String),
+    #[error("unknown group '{0}'")]
+    UnknownGroup(String),
+}
+
+/// how the access rules answer a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allow,
+    Deny,
+    /// a rule for other users matches, the client has to log in
+    Authenticate,
+    /// no rule matches the path, the security policy decides
+    Unmatched,
+}
+
+/// a decoded url path as the rules see it, such as `/team/notes.txt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPath {
+    path: String,
+    is_dir: bool,
+}
+
+impl AccessPath {
+    /// the path of a request uri; a trailing slash marks a directory
+    /// listing, `..` segments are refused since they could step around a rule
+    pub fn from_uri(path: &str) -> Option<Self> {
+        Self::from_decoded(&percent_decode_str(path).decode_utf8().ok()?)
+    }
+
+    /// a path that has already been percent-decoded
+    pub fn from_decoded(decoded: &str) -> Option<Self> {
+        let mut segments = Vec::new();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment => segments.push(segment),
+            }
+        }
+
+        Some(Self {
+            path: format!("/{}", segments.join("/")),
+            is_dir: segments.is_empty() || decoded.ends_with('/'),
+        })
+    }
+
+    /// the normalized path without a trailing slash
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    /// the path of a directory entry
+    pub fn child(&self, name: &str, is_dir: bool) -> Self {
+        Self {
+            path: format!("{}/{}", self.path.trim_end_matches('/'), name),
+            is_dir,
+        }
+    }
+
+    /// whether the path is `prefix` or lies below it, segment by segment
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        let prefix = prefix.trim_end_matches('/');
+        self.path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    fn permission(&self, method: &Method) -> Permission {
+        match *method {
+            Method::GET | Method::HEAD if self.is_dir => Permission::List,
+            Method::GET | Method::HEAD => Permission::Read,
+            _ => Permission::Write,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AccessRule {
+    path: Regex,
+    methods: Vec<Method>,
+    users: Vec<String>,
+    groups: Vec<String>,
+    permissions: Vec<Permission>,
+}
+
+impl AccessRule {
+    // a HEAD request is a GET without the body, so GET rules cover it too
+    fn matches(&self, method: &Method, path: &AccessPath) -> bool {
+        let method_matches = self.methods.is_empty()
+            || self.methods.contains(method)
+            || (*method == Method::HEAD && self.methods.contains(&Method::GET));
+        method_matches && self.path.is_match(&path.path)
+    }
+
+    fn applies_to(&self, user: Option<&str>, users: &Users) -> bool {
+        if self.users.is_empty() && self.groups.is_empty() {
+            return true;
+        }
+        let Some(user) = user else {
+            return false;
+        };
+        self.users.iter().any(|name| name == "*" || name == user)
+            || self.groups.iter().any(|group| users.in_group(user, group))
+    }
+}
+
+/// the `[[access_rule]]` list of a site, in configuration order
+#[derive(Debug, Clone, Default)]
+pub struct AccessRules {
+    rules: Vec<AccessRule>,
+}
+
+impl AccessRules {
+    pub fn new(configs: &[AccessRuleConfig]) -> Result<Self, AccessRuleError> {
+        let mut rules = Vec::with_capacity(configs.len());
+        for config in configs {
+            let path = glob_to_regex(&config.path)
+                .ok_or_else(|| AccessRuleError::InvalidPath(config.path.clone()))?;
+            let methods = config
+                .methods
+                .iter()
+                .map(|method| {
+                    Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                        .map_err(|_| AccessRuleError::InvalidMethod(method.clone()))
+                })
+                .collect::<Result<_, _>>()?;
+
+            rules.push(AccessRule {
+                path,
+                methods,
+                users: config.users.clone(),
+                groups: config.groups.clone(),
+                permissions: config.permissions.clone(),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    /// a single rule refusing everything, for when the configured rules
+    /// cannot be compiled
+    pub fn deny_all() -> Self {
+        Self {
+            rules: vec![AccessRule {
+                path: Regex::new("").expect("empty regex is valid"),
+                methods: Vec::new(),
+                users: Vec::new(),
+                groups: Vec::new(),
+                permissions: Vec::new(),
+            }],
+        }
+    }
+
+    /// check that the users and groups the rules name exist, catching typos
+    /// that would otherwise silently lock people out
+    pub fn check_names(&self, users: &Users) -> Result<(), AccessRuleError> {
+        for rule in &self.rules {
+            if let Some(user) = rule
+                .users
+                .iter()
+                .find(|user| *user != "*" && !users.contains(user))
+            {
+                return Err(AccessRuleError::UnknownUser(user.clone()));
+            }
+            if let Some(group) = rule.groups.iter().find(|group| !users.has_group(group)) {
+                return Err(AccessRuleError::UnknownGroup(group.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// decide a request by the first matching rule that applies to `user`;
+    /// anonymous clients are asked to log in when a rule for somebody else
+    /// would grant what they asked for
+    pub fn evaluate(
+        &self,
+        method: &Method,
+        path: &AccessPath,
+        user: Option<&str>,
+        users: &Users,
+    ) -> Access {
+        let permission = path.permission(method);
+        let mut matched = false;
+        let mut granted_to_others = false;
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(method, path)) {
+            matched = true;
+            let grants = rule.permissions.contains(&permission);
+            if rule.applies_to(user, users) {
+                return match (grants, user) {
+                    (true, _) => Access::Allow,
+                    (false, None) if granted_to_others => Access::Authenticate,
+                    (false, _) => Access::Deny,
+                };
+            }
+            granted_to_others |= grants;
+        }
+
+        match (matched, user) {
+            (false, _) => Access::Unmatched,
+            (true, None) => Access::Authenticate,
+            (true, Some(_)) => Access::Deny,
+        }
+    }
+
+    /// whether a listing shows `path` to `user`
+    pub fn is_visible(&self, path: &AccessPath, user: Option<&str>, users: &Users) -> bool {
+        matches!(
+            self.evaluate(&Method::GET, path, user, users),
+            Access::Allow | Access::Unmatched
+        )
+    }
+}
+
+// `*` and `?` match within a segment, `/**` any number of whole segments;
+// trailing slashes are ignored so `/incoming/` names the directory itself
+fn glob_to_regex(glob: &str) -> Option<Regex> {
+    if !glob.starts_with('/') {
+        return None;
+    }
+    let glob = match glob.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    };
+
+    let mut pattern = String::from("^");
+    let mut rest = glob;
+    while let Some(ch) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("/**")
+            && (after.is_empty() || after.starts_with('/'))
+        {
+            pattern.push_str("(?:/.*)?");
+            rest = after;
+            continue;
+        }
+        match ch {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            ch => pattern.push_str(&regex::escape(ch.encode_utf8(&mut [0; 4]))),
+        }
+        rest = &rest[ch.len_utf8()..];
+    }
+    pattern.push('$');
+
+    Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SecurityConfig;
+
+    fn path(uri: &str) -> AccessPath {
+        AccessPath::from_uri(uri).unwrap()
+    }
+
+    fn rule(
+        glob: &str,
+        users: &[&str],
+        groups: &[&str],
+        permissions: &[Permission],
+    ) -> AccessRuleConfig {
+        AccessRuleConfig {
+            path: glob.to_string(),
+            methods: Vec::new(),
+            users: users.iter().map(ToString::to_string).collect(),
+            groups: groups.iter().map(ToString::to_string).collect(),
+            permissions: permissions.to_vec(),
+        }
+    }
+
+    fn team() -> Users {
+        let mut security = SecurityConfig {
+            username: Some("ci".to_string()),
+            password: Some("ci".to_string()),
+            ..Default::default()
+        };
+        security.users = ["alice", "bob"]
+            .into_iter()
+            .map(|name| crate::config::UserConfig {
+                username: name.to_string(),
+                password_hash: "$6$rounds=1000$salt$hash".to_string(),
+            })
+            .collect();
+        security
+            .groups
+            .insert("dev".to_string(), vec!["alice".to_string()]);
+        Users::load(&security).unwrap()
+    }
+
+    #[test]
+    fn globs_match_segments() {
+        let glob = |glob: &str| glob_to_regex(glob).unwrap();
+        assert!(glob("/public/**").is_match("/public"));
+        assert!(glob("/public/**").is_match("/public/a/b.txt"));
+        assert!(!glob("/public/**").is_match("/publicity"));
+        assert!(glob("/**").is_match("/"));
+        assert!(glob("/docs/*.md").is_match("/docs/readme.md"));
+        assert!(!glob("/docs/*.md").is_match("/docs/a/readme.md"));
+        assert!(glob("/a/**/b").is_match("/a/b"));
+        assert!(glob("/a/**/b").is_match("/a/x/y/b"));
+        assert!(glob("/incoming/").is_match("/incoming"));
+        assert!(!glob("/incoming/").is_match("/incoming/file"));
+        assert!(glob("/v?.txt").is_match("/v1.txt"));
+        assert!(glob_to_regex("public/**").is_none());
+    }
+
+    #[test]
+    fn normalizes_request_paths() {
+        assert_eq!(path("/team%20docs/a.txt").path, "/team docs/a.txt");
+        assert_eq!(path("//team/./a.txt").path, "/team/a.txt");
+        assert!(path("/team/").is_dir);
+        assert!(path("/").is_dir);
+        assert!(!path("/team").is_dir);
+        assert!(AccessPath::from_uri("/public/../team/a.txt").is_none());
+        assert!(AccessPath::from_uri("/public/%2e%2e/team").is_none());
+        assert_eq!(path("/").child("team", true).path, "/team");
+        assert_eq!(path("/team/").child("a.txt", false).path, "/team/a.txt");
+        assert!(path("/builds/a.tar").starts_with("/builds/"));
+        assert!(path("/builds").starts_with("/builds"));
+        assert!(path("/a.txt").starts_with("/"));
+        assert!(!path("/buildsx/a.tar").starts_with("/builds"));
+    }
+
+    #[test]
+    fn first_applicable_rule_decides() {
+        use Permission::*;
+        let users = team();
+        let rules = AccessRules::new(&[
+            rule("/public/**", &[], &[], &[Read, List]),
+            rule("/team/**", &[], &["dev"], &[Read, List, Write]),
+            rule("/incoming/", &["ci"], &[], &[Write]),
+            rule("/incoming/", &["*"], &[], &[List]),
+        ])
+        .unwrap();
+        rules.check_names(&users).unwrap();
+        let get = |uri: &str, user| rules.evaluate(&Method::GET, &path(uri), user, &users);
+        let post = |uri: &str, user| rules.evaluate(&Method::POST, &path(uri), user, &users);
+
+        assert_eq!(get("/public/a.txt", None), Access::Allow);
+        assert_eq!(get("/public/", None), Access::Allow);
+        assert_eq!(post("/public/", Some("alice")), Access::Deny);
+
+        assert_eq!(get("/team/a.txt", None), Access::Authenticate);
+        assert_eq!(get("/team/a.txt", Some("alice")), Access::Allow);
+        assert_eq!(post("/team/", Some("alice")), Access::Allow);
+        assert_eq!(get("/team/a.txt", Some("bob")), Access::Deny);
+
+        assert_eq!(post("/incoming/", Some("ci")), Access::Allow);
+        assert_eq!(post("/incoming/", None), Access::Authenticate);
+        assert_eq!(post("/incoming/", Some("bob")), Access::Deny);
+        assert_eq!(get("/incoming/", Some("ci")), Access::Deny);
+        assert_eq!(get("/incoming/", Some("bob")), Access::Allow);
+
+        assert_eq!(get("/other.txt", None), Access::Unmatched);
+        assert!(rules.is_visible(&path("/other.txt"), None, &users));
+        assert!(!rules.is_visible(&path("/team/"), None, &users));
+        assert!(rules.is_visible(&path("/team/"), Some("alice"), &users));
+    }
+
+    #[test]
+    fn methods_limit_rules() {
+        let users = team();
+        let mut config = rule("/public/**", &[], &[], &[Permission::Read]);
+        config.methods = vec!["get".to_string(), "HEAD".to_string()];
+        let rules = AccessRules::new(&[config]).unwrap();
+        assert_eq!(
+            rules.evaluate(&Method::HEAD, &path("/public/a"), None, &users),
+            Access::Allow
+        );
+        assert_eq!(
+            rules.evaluate(&Method::POST, &path("/public/"), None, &users),
+            Access::Unmatched
+        );
+
+        let deny_all = AccessRules::deny_all();
+        assert_eq!(
+            deny_all.evaluate(&Method::GET, &path("/"), Some("alice"), &users),
+            Access::Deny
+        );
+    }
+
+    #[test]
+    fn get_rules_cover_head() {
+        let users = team();
+        let mut config = rule("/team/**", &[], &[], &[]);
+        config.methods = vec!["GET".to_string()];
+        let rules = AccessRules::new(&[config]).unwrap();
+        for method in [Method::GET, Method::HEAD] {
+            assert_eq!(
+                rules.evaluate(&method, &path("/team/a"), None, &users),
+                Access::Deny,
+                "{method}"
+            );
+        }
+        assert_eq!(
+            rules.evaluate(&Method::POST, &path("/team/a"), None, &users),
+            Access::Unmatched
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        let users = team();
+        let check = |config: AccessRuleConfig| {
+            AccessRules::new(&[config]).and_then(|rules| rules.check_names(&users))
+        };
+        assert!(matches!(
+            check(rule("/a", &["mallory"], &[], &[])),
+            Err(AccessRuleError::UnknownUser(_))
+        ));
+        assert!(matches!(
+            check(rule("/a", &[], &["ops"], &[])),
+            Err(AccessRuleError::UnknownGroup(_))
+        ));
+        assert!(matches!(
+            check(rule("a/**", &[], &[], &[])),
+            Err(AccessRuleError::InvalidPath(_))
+        ));
+        let mut config = rule("/a", &[], &[], &[]);
+        config.methods = vec!["NOT A METHOD".to_string()];
+        assert!(matches!(
+            check(config),
+            Err(AccessRuleError::InvalidMethod(_))
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+// ip network matching for trusted proxy lists
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CidrError {
+    #[error("invalid address in '{0}'")]
+    InvalidAddress(String),
+
+    #[error("invalid prefix length in '{0}'")]
+    InvalidPrefix(String),
+}
+
+/// an address block such as `10.0.0.0/8` or `fd00::/8`; a bare address
+/// matches only itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(net).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+// compare the leading `prefix_len` bits of two addresses `width` bits wide
+fn prefix_matches(net: u128, ip: u128, width: u8, prefix_len: u8) -> bool {
+    let host_bits = u32::from(width - prefix_len);
+    net.checked_shr(host_bits).unwrap_or(0) == ip.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (value, None),
+        };
+
+        let addr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map_err(|_| CidrError::InvalidAddress(value.to_string()))?
+            .to_canonical();
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            None => width,
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= width)
+                .ok_or_else(|| CidrError::InvalidPrefix(value.to_string()))?,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// the proxies whose forwarding headers and proxy protocol headers are believed
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    pub fn new(entries: &[String]) -> Result<Self, CidrError> {
+        let networks = entries
+            .iter()
+            .map(|entry| entry.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { networks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_address_blocks() {
+        let network: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(network.contains(ip("10.1.2.3")));
+        assert!(!network.contains(ip("11.0.0.1")));
+        assert!(network.contains(ip("::ffff:10.0.0.1")));
+
+        let network: Cidr = "fd00::/8".parse().unwrap();
+        assert!(network.contains(ip("fd12::1")));
+        assert!(!network.contains(ip("fe80::1")));
+        assert!(!network.contains(ip("10.0.0.1")));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn bare_addresses_match_exactly() {
+        let proxies = TrustedProxies::new(&["127.0.0.1".to_string(), "[::1]".to_string()]).unwrap();
+        assert!(proxies.contains(ip("127.0.0.1")));
+        assert!(proxies.contains(ip("::1")));
+        assert!(!proxies.contains(ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("proxy.internal".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+}
@@ -1,6 +1,6 @@
 // file operations and formatting utilities
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use std::path::Path;
 use std::time::SystemTime;
 use tokio::fs;
@@ -45,6 +45,12 @@ pub fn format_timestamp(timestamp: SystemTime) -> String {
     datetime.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
+/// format a timestamp as rfc3339 in utc for machine-readable output
+pub fn format_timestamp_rfc3339(timestamp: SystemTime) -> String {
+    let datetime: DateTime<Utc> = timestamp.into();
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
 /// collect directory entries asynchronously
 pub async fn collect_directory_entries(
     dir_path: &Path,
@@ -95,6 +101,48 @@ pub fn get_mime_type(file_path: &Path) -> String {
         .to_string()
 }
 
+/// check whether a mime type benefits from on-the-fly compression
+/// formats that are already compressed are skipped
+pub fn is_compressible_mime(mime_type: &str) -> bool {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if essence == "image/svg+xml" {
+        return true;
+    }
+
+    if essence.starts_with("image/")
+        || essence.starts_with("audio/")
+        || essence.starts_with("video/")
+    {
+        return false;
+    }
+
+    !matches!(
+        essence.as_str(),
+        "application/zip"
+            | "application/gzip"
+            | "application/x-gzip"
+            | "application/x-bzip2"
+            | "application/x-xz"
+            | "application/x-7z-compressed"
+            | "application/x-rar-compressed"
+            | "application/vnd.rar"
+            | "application/zstd"
+            | "application/x-zstd"
+            | "application/x-brotli"
+            | "application/java-archive"
+            | "application/epub+zip"
+            | "application/pdf"
+            | "font/woff"
+            | "font/woff2"
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -125,4 +173,15 @@ mod tests {
         assert_eq!(get_mime_type(Path::new("file.png")), "image/png");
         assert_eq!(get_mime_type(Path::new("file.jpg")), "image/jpeg");
     }
+
+    #[test]
+    fn test_compressible_mime_detection() {
+        assert!(is_compressible_mime("text/html; charset=utf-8"));
+        assert!(is_compressible_mime("application/json"));
+        assert!(is_compressible_mime("image/svg+xml"));
+        assert!(!is_compressible_mime("image/png"));
+        assert!(!is_compressible_mime("video/mp4"));
+        assert!(!is_compressible_mime(&get_mime_type(Path::new("a.zip"))));
+        assert!(!is_compressible_mime(&get_mime_type(Path::new("a.tar.gz"))));
+    }
 }
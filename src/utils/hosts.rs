@@ -0,0 +1,128 @@
+// host name patterns for virtual hosts
+
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum HostPatternError {
+    #[error("invalid host name '{0}'")]
+    InvalidName(String),
+}
+
+/// an exact host name such as `docs.local`, or `*.docs.local` matching every
+/// subdomain but not `docs.local` itself
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HostPattern {
+    Exact(String),
+    Wildcard(String),
+}
+
+impl HostPattern {
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(name) => host == name,
+            Self::Wildcard(suffix) => host
+                .strip_suffix(suffix.as_str())
+                .is_some_and(|label| label.len() > 1 && label.ends_with('.')),
+        }
+    }
+
+    /// how closely the pattern matches; exact names beat wildcards and longer
+    /// wildcard suffixes beat shorter ones
+    pub fn specificity(&self) -> usize {
+        match self {
+            Self::Exact(_) => usize::MAX,
+            Self::Wildcard(suffix) => suffix.len(),
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = HostPatternError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || HostPatternError::InvalidName(value.to_string());
+        let name = normalize_host(value).ok_or_else(invalid)?;
+
+        match name.strip_prefix("*.") {
+            Some(suffix) if is_host_name(suffix) => Ok(Self::Wildcard(suffix.to_string())),
+            Some(_) => Err(invalid()),
+            None if is_host_name(&name) => Ok(Self::Exact(name)),
+            None => Err(invalid()),
+        }
+    }
+}
+
+/// the host name of a `Host` header value, lowercased and without port or
+/// trailing dot
+pub fn normalize_host(host: &str) -> Option<String> {
+    let host = host.trim();
+    let name = if let Some(rest) = host.strip_prefix('[') {
+        // bracketed ipv6 literal, optionally followed by a port
+        let (literal, _) = rest.split_once(']')?;
+        literal
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    (!name.is_empty()).then_some(name)
+}
+
+fn is_host_name(name: &str) -> bool {
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b':'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(value: &str) -> HostPattern {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_names() {
+        assert!(pattern("docs.local").matches("docs.local"));
+        assert!(!pattern("docs.local").matches("www.docs.local"));
+
+        let wildcard = pattern("*.docs.local");
+        assert!(wildcard.matches("www.docs.local"));
+        assert!(wildcard.matches("a.b.docs.local"));
+        assert!(!wildcard.matches("docs.local"));
+        assert!(!wildcard.matches("xdocs.local"));
+
+        assert!(pattern("*.docs.local").specificity() < pattern("docs.local").specificity());
+        assert!(pattern("*.local").specificity() < pattern("*.docs.local").specificity());
+    }
+
+    #[test]
+    fn normalizes_host_headers() {
+        assert_eq!(
+            normalize_host("Docs.Local:8000").as_deref(),
+            Some("docs.local")
+        );
+        assert_eq!(normalize_host("docs.local.").as_deref(), Some("docs.local"));
+        assert_eq!(normalize_host("[::1]:8000").as_deref(), Some("::1"));
+        assert_eq!(normalize_host("").as_deref(), None);
+        assert_eq!(
+            pattern("DOCS.local"),
+            HostPattern::Exact("docs.local".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!("*".parse::<HostPattern>().is_err());
+        assert!("docs.*.local".parse::<HostPattern>().is_err());
+        assert!("docs..local".parse::<HostPattern>().is_err());
+        assert!("docs local".parse::<HostPattern>().is_err());
+        assert!("".parse::<HostPattern>().is_err());
+    }
+}
@@ -1,10 +1,17 @@
-// ignore file pattern matching utilities
+// ignore file pattern matching utilities (gitignore semantics)
 
 use anyhow::{Context, Result};
-use regex::Regex;
+use regex::{Regex, RegexSet};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use tracing::warn;
+use std::sync::{Arc, PoisonError, RwLock};
+use tracing::{error, warn};
+
+use super::watch::{FileStamp, file_stamps};
+
+// bound on cached directories before the cache is reset
+const MAX_CACHED_DIRECTORIES: usize = 4096;
 
 /// convert a simple glob pattern to a regex string
 /// supports * (any chars) and ? (single char) like soop2
@@ -36,96 +43,520 @@ pub fn pattern_to_regex(pattern: &str) -> Result<Regex> {
         .with_context(|| format!("failed to compile regex from pattern: {pattern}"))
 }
 
-/// read ignore patterns from a file
-pub fn read_ignore_patterns(ignore_file: &Path) -> Result<Vec<Regex>> {
-    let content = fs::read_to_string(ignore_file)
-        .with_context(|| format!("failed to read ignore file: {}", ignore_file.display()))?;
+/// a single parsed line from a gitignore-style file
+#[derive(Debug, Clone)]
+pub struct IgnorePattern {
+    /// regex source matching paths relative to the public root
+    source: String,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnorePattern {
+    /// parse one gitignore line, returning none for blank lines and comments
+    /// `base` is the directory of the ignore file relative to the public root
+    pub fn parse(line: &str, base: &str) -> Option<Self> {
+        let line = trim_unescaped_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // a leading backslash escapes a literal '#' or '!'
+        let line = match line.strip_prefix('\\') {
+            Some(rest) if rest.starts_with('#') || rest.starts_with('!') => rest,
+            _ => line,
+        };
+
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        // patterns with a slash anywhere but the end are relative to the ignore file
+        let anchored = line.contains('/');
+        let glob = line.strip_prefix('/').unwrap_or(line);
+
+        let mut source = String::from("^");
+        let base = base.trim_matches('/');
+        if !base.is_empty() {
+            source.push_str(&regex::escape(base));
+            source.push('/');
+        }
+        if !anchored {
+            source.push_str("(?:.*/)?");
+        }
+        source.push_str(&glob_to_regex(glob));
+        source.push('$');
+
+        Some(Self {
+            source,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+/// ignore patterns compiled into a single regex set
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    set: RegexSet,
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreRules {
+    /// compile parsed patterns, keeping their order for last-match-wins evaluation
+    pub fn new(patterns: Vec<IgnorePattern>) -> Result<Self> {
+        let set = RegexSet::new(patterns.iter().map(|pattern| pattern.source.as_str()))
+            .context("failed to compile ignore patterns")?;
+        Ok(Self { set, patterns })
+    }
+
+    /// rules that ignore nothing
+    pub fn empty() -> Self {
+        Self {
+            set: RegexSet::empty(),
+            patterns: Vec::new(),
+        }
+    }
+
+    /// rules that ignore every path
+    pub fn everything() -> Self {
+        let pattern = IgnorePattern {
+            source: "^.*$".to_string(),
+            negated: false,
+            dir_only: false,
+        };
+        Self::new(vec![pattern]).expect("catch-all pattern compiles")
+    }
+
+    fn last_match_ignores(&self, rel_path: &str, is_dir: bool) -> bool {
+        self.set
+            .matches(rel_path)
+            .iter()
+            .rev()
+            .map(|index| &self.patterns[index])
+            .find(|pattern| is_dir || !pattern.dir_only)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// translate a gitignore glob into an unanchored regex fragment
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let at_end = i + 2 == chars.len();
+                let before_slash = chars.get(i + 2) == Some(&'/');
+
+                if at_start && before_slash {
+                    // "**/" matches zero or more leading directories
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else if at_start && at_end {
+                    // trailing "/**" matches everything inside
+                    regex.push_str(".*");
+                    i += 2;
+                } else {
+                    regex.push_str("[^/]*");
+                    i += 2;
+                }
+            }
+            '*' => {
+                regex.push_str("[^/]*");
+                i += 1;
+            }
+            '?' => {
+                regex.push_str("[^/]");
+                i += 1;
+            }
+            '[' => match parse_char_class(&chars[i..]) {
+                Some((class, consumed)) => {
+                    regex.push_str(&class);
+                    i += consumed;
+                }
+                None => {
+                    regex.push_str("\\[");
+                    i += 1;
+                }
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            ch => {
+                regex.push_str(&regex::escape(&ch.to_string()));
+                i += 1;
+            }
+        }
+    }
+
+    regex
+}
+
+/// translate a bracket expression, returning the regex class and chars consumed
+fn parse_char_class(chars: &[char]) -> Option<(String, usize)> {
+    let mut i = 1;
+    let mut class = String::from("[");
+
+    if matches!(chars.get(i), Some('!') | Some('^')) {
+        class.push('^');
+        i += 1;
+    }
 
-    let patterns: Result<Vec<Regex>> = content
+    let first = i;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == ']' && i > first {
+            class.push(']');
+            return Some((class, i + 1));
+        }
+
+        if ch == '\\' && i + 1 < chars.len() {
+            i += 1;
+            class.push('\\');
+            class.push(chars[i]);
+        } else if ch == '-' || ch.is_alphanumeric() {
+            class.push(ch);
+        } else {
+            class.push('\\');
+            class.push(ch);
+        }
+        i += 1;
+    }
+
+    None
+}
+
+fn trim_unescaped_trailing_spaces(line: &str) -> &str {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut end = line.len();
+    while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// parse the contents of an ignore file located at `base` (relative to the public root)
+pub fn parse_ignore_patterns(content: &str, base: &str) -> Vec<IgnorePattern> {
+    content
         .lines()
-        .filter(|line| !line.trim().is_empty()) // skip empty lines like soop2
-        .map(|line| pattern_to_regex(line.trim()))
-        .collect();
+        .filter_map(|line| IgnorePattern::parse(line, base))
+        .collect()
+}
 
-    patterns.with_context(|| format!("failed to parse patterns from: {}", ignore_file.display()))
+/// read ignore patterns from a file located at `base` relative to the public root
+pub fn read_ignore_patterns(ignore_file: &Path, base: &str) -> Result<Vec<IgnorePattern>> {
+    let content = fs::read_to_string(ignore_file)
+        .with_context(|| format!("failed to read ignore file: {}", ignore_file.display()))?;
+
+    Ok(parse_ignore_patterns(&content, base))
 }
 
-/// check if a relative path matches any of the ignore patterns
-pub fn is_path_ignored(rel_path: &str, patterns: &[Regex]) -> bool {
-    patterns.iter().any(|pattern| pattern.is_match(rel_path))
+/// check if a relative path is ignored, applying the last matching pattern
+/// a path inside an ignored directory stays ignored, like git
+pub fn is_path_ignored(rel_path: &str, is_dir: bool, rules: &IgnoreRules) -> bool {
+    let rel_path = rel_path.trim_matches('/');
+
+    for (index, _) in rel_path.match_indices('/') {
+        if rules.last_match_ignores(&rel_path[..index], true) {
+            return true;
+        }
+    }
+
+    rules.last_match_ignores(rel_path, is_dir)
 }
 
-/// filter directory entries using ignore patterns
-pub fn filter_with_ignore_patterns(
-    entries: &[super::files::DirectoryEntry],
+/// load the root ignore file plus any same-named ignore files found in each
+/// directory between the public root and `dir_path`
+pub fn load_ignore_rules(
     base_dir: &Path,
     dir_path: &Path,
-    ignore_file: Option<&PathBuf>,
-) -> Result<Vec<super::files::DirectoryEntry>> {
+    ignore_file: &Path,
+) -> Result<IgnoreRules> {
     let canonical_base = base_dir
         .canonicalize()
         .unwrap_or_else(|_| base_dir.to_path_buf());
+    let sources = ignore_sources(base_dir, &canonical_base, dir_path, ignore_file);
 
-    // if no ignore file specified, return all entries
-    let ignore_file = match ignore_file {
-        Some(file) => file,
-        None => return Ok(entries.to_vec()),
-    };
+    compile_ignore_sources(&sources, &canonical_base)
+}
 
+// list the ignore files that apply to `dir_path`, root file first
+fn ignore_sources(
+    base_dir: &Path,
+    canonical_base: &Path,
+    dir_path: &Path,
+    ignore_file: &Path,
+) -> Vec<PathBuf> {
     // resolve ignore file path relative to base directory
-    let ignore_path = if ignore_file.is_absolute() {
-        ignore_file.clone()
+    let root_path = if ignore_file.is_absolute() {
+        ignore_file.to_path_buf()
     } else {
         base_dir.join(ignore_file)
     };
+    let canonical_root = canonical_base.join(ignore_file);
+    let mut sources = vec![root_path];
+
+    // nested ignore files share the configured file name
+    let Some(file_name) = ignore_file.file_name() else {
+        return sources;
+    };
+    let Ok(relative_dir) = dir_path.strip_prefix(canonical_base) else {
+        return sources;
+    };
+
+    let mut current = canonical_base.to_path_buf();
+    let mut nested_dirs = vec![current.clone()];
+    for component in relative_dir.components() {
+        current.push(component);
+        nested_dirs.push(current.clone());
+    }
 
-    // if ignore file doesn't exist, return all entries (silent like soop2)
-    match fs::metadata(&ignore_path) {
-        Ok(_) => {}
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            return Ok(entries.to_vec());
+    for dir in nested_dirs {
+        let candidate = dir.join(file_name);
+        if candidate != canonical_root {
+            sources.push(candidate);
         }
+    }
+
+    sources
+}
+
+/// compile every nested ignore file below `base_dir` on its own, so one that is
+/// broken surfaces before enforced rules hide its directory
+pub fn validate_nested_ignore_files(base_dir: &Path, ignore_file: &Path) -> Result<()> {
+    let Some(file_name) = ignore_file.file_name() else {
+        return Ok(());
+    };
+    let canonical_base = base_dir
+        .canonicalize()
+        .with_context(|| format!("failed to resolve directory: {}", base_dir.display()))?;
+
+    let mut dirs = vec![canonical_base.clone()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("failed to scan {} for ignore files: {}", dir.display(), err);
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if entry.file_name() == file_name {
+                let path = entry.path();
+                if let Some(patterns) = read_optional_ignore_file(&path, &canonical_base)? {
+                    IgnoreRules::new(patterns)
+                        .with_context(|| format!("invalid ignore file: {}", path.display()))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn compile_ignore_sources(sources: &[PathBuf], canonical_base: &Path) -> Result<IgnoreRules> {
+    let mut patterns = Vec::new();
+    for source in sources {
+        // missing ignore files contribute no rules (silent like soop2)
+        if let Some(file_patterns) = read_optional_ignore_file(source, canonical_base)? {
+            patterns.extend(file_patterns);
+        }
+    }
+
+    IgnoreRules::new(patterns)
+}
+
+/// read an ignore file if it exists, with patterns relative to its own directory
+fn read_optional_ignore_file(
+    ignore_path: &Path,
+    canonical_base: &Path,
+) -> Result<Option<Vec<IgnorePattern>>> {
+    match fs::metadata(ignore_path) {
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(_) => {
+            warn!(
+                "ignore file is not a regular file: {}",
+                ignore_path.display()
+            );
+            return Ok(None);
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
         Err(err) => {
             warn!(
                 "failed to read ignore file metadata for {}: {}",
                 ignore_path.display(),
                 err
             );
-            return Ok(entries.to_vec());
+            return Ok(None);
         }
     }
 
-    // read ignore patterns
-    let patterns = match read_ignore_patterns(&ignore_path) {
-        Ok(patterns) => patterns,
-        Err(err) => {
-            warn!(
-                "failed to read ignore patterns from {}: {}",
-                ignore_path.display(),
-                err
-            );
-            return Ok(entries.to_vec());
+    let canonical = ignore_path
+        .canonicalize()
+        .unwrap_or_else(|_| ignore_path.to_path_buf());
+    let base = canonical
+        .parent()
+        .and_then(|parent| parent.strip_prefix(canonical_base).ok())
+        .map(|rel| rel.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+
+    read_ignore_patterns(ignore_path, &base).map(Some)
+}
+
+#[derive(Debug)]
+struct CachedRules {
+    stamps: Vec<FileStamp>,
+    rules: Arc<IgnoreRules>,
+}
+
+/// compiled ignore rules per directory, recompiled only when one of the
+/// contributing ignore files changes
+#[derive(Debug)]
+pub struct IgnoreCache {
+    base_dir: PathBuf,
+    canonical_base: PathBuf,
+    ignore_file: PathBuf,
+    /// whether the rules guard direct access, which makes broken rules and the
+    /// ignore files themselves hidden
+    enforce: bool,
+    entries: RwLock<HashMap<PathBuf, CachedRules>>,
+}
+
+impl IgnoreCache {
+    pub fn new(base_dir: &Path, ignore_file: &Path, enforce: bool) -> Self {
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            canonical_base: base_dir
+                .canonicalize()
+                .unwrap_or_else(|_| base_dir.to_path_buf()),
+            ignore_file: ignore_file.to_path_buf(),
+            enforce,
+            entries: RwLock::new(HashMap::new()),
         }
-    };
+    }
 
-    // filter entries
-    let filtered: Vec<_> = entries
-        .iter()
-        .filter(|entry| {
-            // create relative path from base_dir like soop2 does
-            let entry_path = dir_path.join(&entry.name);
-            let rel_path = match entry_path.strip_prefix(&canonical_base) {
-                Ok(path) => path.to_string_lossy().to_string(),
-                Err(_) => entry.name.clone(), // fallback to just the name
-            };
+    /// whether `path` is named like the ignore file, at the root or nested
+    pub fn is_ignore_file(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|name| Some(name) == self.ignore_file.file_name())
+    }
 
-            // check if path should be ignored
-            !is_path_ignored(&rel_path, &patterns)
-        })
-        .cloned()
-        .collect();
+    /// rules applying to entries of `dir_path`, loading them on first use or change
+    pub fn rules_for(&self, dir_path: &Path) -> Arc<IgnoreRules> {
+        let sources = ignore_sources(
+            &self.base_dir,
+            &self.canonical_base,
+            dir_path,
+            &self.ignore_file,
+        );
+        let stamps = file_stamps(&sources);
+
+        if let Some(cached) = self
+            .entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(dir_path)
+            && cached.stamps == stamps
+        {
+            return cached.rules.clone();
+        }
+
+        // broken rules are reported once per change; listings fail open, but
+        // enforced rules hide everything rather than what they were meant to
+        let rules = match compile_ignore_sources(&sources, &self.canonical_base) {
+            Ok(rules) => Arc::new(rules),
+            Err(err) => {
+                error!(
+                    "failed to load ignore rules for {}: {:#}",
+                    dir_path.display(),
+                    err
+                );
+                Arc::new(if self.enforce {
+                    IgnoreRules::everything()
+                } else {
+                    IgnoreRules::empty()
+                })
+            }
+        };
 
-    Ok(filtered)
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= MAX_CACHED_DIRECTORIES {
+            entries.clear();
+        }
+        entries.insert(
+            dir_path.to_path_buf(),
+            CachedRules {
+                stamps,
+                rules: rules.clone(),
+            },
+        );
+
+        rules
+    }
+
+    /// check whether a path inside the public directory is ignored
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.canonical_base) else {
+            return false;
+        };
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        if self.enforce && self.is_ignore_file(path) {
+            return true;
+        }
+
+        let parent = path.parent().unwrap_or(&self.canonical_base);
+        is_path_ignored(
+            &relative.to_string_lossy().replace('\\', "/"),
+            is_dir,
+            &self.rules_for(parent),
+        )
+    }
+
+    /// drop directory entries that are ignored
+    pub fn filter_entries(
+        &self,
+        entries: Vec<super::files::DirectoryEntry>,
+        dir_path: &Path,
+    ) -> Vec<super::files::DirectoryEntry> {
+        let rules = self.rules_for(dir_path);
+
+        entries
+            .into_iter()
+            .filter(|entry| {
+                // create relative path from base_dir like soop2 does
+                let entry_path = dir_path.join(&entry.name);
+                let rel_path = match entry_path.strip_prefix(&self.canonical_base) {
+                    Ok(path) => path.to_string_lossy().replace('\\', "/"),
+                    Err(_) => entry.name.clone(), // fallback to just the name
+                };
+
+                let hidden_ignore_file =
+                    self.enforce && !entry.is_dir && self.is_ignore_file(&entry_path);
+                !hidden_ignore_file && !is_path_ignored(&rel_path, entry.is_dir, &rules)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -169,13 +600,14 @@ mod tests {
 
         fs::write(&ignore_file, "*.log\ntemp*\n\nbuild\n").unwrap();
 
-        let patterns = read_ignore_patterns(&ignore_file).unwrap();
+        let patterns = read_ignore_patterns(&ignore_file, "").unwrap();
         assert_eq!(patterns.len(), 3); // empty line should be skipped
 
-        assert!(is_path_ignored("debug.log", &patterns));
-        assert!(is_path_ignored("temp123", &patterns));
-        assert!(is_path_ignored("build", &patterns));
-        assert!(!is_path_ignored("source.rs", &patterns));
+        let rules = IgnoreRules::new(patterns).unwrap();
+        assert!(is_path_ignored("debug.log", false, &rules));
+        assert!(is_path_ignored("temp123", false, &rules));
+        assert!(is_path_ignored("build", true, &rules));
+        assert!(!is_path_ignored("source.rs", false, &rules));
     }
 
     #[test]
@@ -216,13 +648,8 @@ mod tests {
             },
         ];
 
-        let filtered = filter_with_ignore_patterns(
-            &entries,
-            base_dir,
-            dir_path,
-            Some(&PathBuf::from(".gitignore")),
-        )
-        .unwrap();
+        let cache = IgnoreCache::new(base_dir, Path::new(".gitignore"), false);
+        let filtered = cache.filter_entries(entries, &dir_path.canonicalize().unwrap());
 
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].name, "source.rs");
@@ -252,16 +679,164 @@ mod tests {
             },
         ];
 
-        let filtered = filter_with_ignore_patterns(
-            &entries,
-            base_dir,
-            dir_path,
-            Some(&PathBuf::from("ignore_dir")),
-        )
-        .unwrap();
+        let cache = IgnoreCache::new(base_dir, Path::new("ignore_dir"), false);
+        let filtered = cache.filter_entries(entries.clone(), dir_path);
 
         assert_eq!(filtered.len(), entries.len());
         assert_eq!(filtered[0].name, "source.rs");
         assert_eq!(filtered[1].name, "debug.log");
     }
+
+    fn ignored(content: &str, rel_path: &str, is_dir: bool) -> bool {
+        let rules = IgnoreRules::new(parse_ignore_patterns(content, "")).unwrap();
+        is_path_ignored(rel_path, is_dir, &rules)
+    }
+
+    #[test]
+    fn test_gitignore_semantics() {
+        // comments, escapes and trailing spaces
+        assert!(!ignored("# comment\n", "# comment", false));
+        assert!(ignored("\\#literal\n", "#literal", false));
+        assert!(ignored("spaced   \n", "spaced", false));
+
+        // unanchored patterns match at any depth, anchored ones from the root
+        assert!(ignored("*.log\n", "a/b/debug.log", false));
+        assert!(ignored("/root.txt\n", "root.txt", false));
+        assert!(!ignored("/root.txt\n", "sub/root.txt", false));
+        assert!(!ignored("docs/*.md\n", "other/docs/readme.md", false));
+        assert!(!ignored("*.txt\n", "dir/sub", true));
+        assert!(!ignored("a*\n", "b/c", false));
+
+        // single star does not cross directories, double star does
+        assert!(!ignored("docs/*.md\n", "docs/nested/readme.md", false));
+        assert!(ignored(
+            "docs/**/*.md\n",
+            "docs/nested/deep/readme.md",
+            false
+        ));
+        assert!(ignored("docs/**/*.md\n", "docs/readme.md", false));
+        assert!(ignored("**/cache\n", "x/y/cache", true));
+        assert!(ignored("vendor/**\n", "vendor/lib/file.rs", false));
+
+        // directory-only patterns and their contents
+        assert!(ignored("build/\n", "build", true));
+        assert!(!ignored("build/\n", "build", false));
+        assert!(ignored("build/\n", "build/output.bin", false));
+
+        // negation re-includes unless a parent directory is excluded
+        assert!(!ignored("*.log\n!keep.log\n", "keep.log", false));
+        assert!(ignored("*.log\n!keep.log\n", "drop.log", false));
+        assert!(ignored("logs/\n!logs/keep.log\n", "logs/keep.log", false));
+        assert!(ignored("\\!important\n", "!important", false));
+
+        // character classes
+        assert!(ignored("file[0-9].txt\n", "file7.txt", false));
+        assert!(!ignored("file[!0-9].txt\n", "file7.txt", false));
+        assert!(ignored("file[!0-9].txt\n", "filex.txt", false));
+        assert!(ignored("weird[.txt\n", "weird[.txt", false));
+    }
+
+    #[test]
+    fn test_nested_ignore_files_are_relative_to_their_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(base_dir.join("sub/deeper")).unwrap();
+        fs::write(base_dir.join(".gitignore"), "*.tmp\n").unwrap();
+        fs::write(base_dir.join("sub/.gitignore"), "/local.txt\n!keep.tmp\n").unwrap();
+
+        let rules = load_ignore_rules(
+            &base_dir,
+            &base_dir.join("sub/deeper"),
+            Path::new(".gitignore"),
+        )
+        .unwrap();
+
+        assert!(is_path_ignored("sub/local.txt", false, &rules));
+        assert!(!is_path_ignored("local.txt", false, &rules));
+        assert!(!is_path_ignored("sub/deeper/local.txt", false, &rules));
+        assert!(is_path_ignored("sub/deeper/x.tmp", false, &rules));
+        assert!(!is_path_ignored("sub/keep.tmp", false, &rules));
+        assert!(is_path_ignored("keep.tmp", false, &rules));
+    }
+
+    #[test]
+    fn test_cache_reloads_changed_ignore_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().canonicalize().unwrap();
+        let ignore_file = base_dir.join(".gitignore");
+
+        fs::write(&ignore_file, "*.log\n").unwrap();
+        let cache = IgnoreCache::new(&base_dir, Path::new(".gitignore"), false);
+
+        let first = cache.rules_for(&base_dir);
+        assert!(Arc::ptr_eq(&first, &cache.rules_for(&base_dir)));
+        assert!(cache.is_ignored(&base_dir.join("debug.log"), false));
+
+        // a different size is enough to invalidate even within the same mtime tick
+        fs::write(&ignore_file, "*.tmp\n*.bak\n").unwrap();
+        assert!(!Arc::ptr_eq(&first, &cache.rules_for(&base_dir)));
+        assert!(!cache.is_ignored(&base_dir.join("debug.log"), false));
+        assert!(cache.is_ignored(&base_dir.join("old.bak"), false));
+
+        // creating a nested ignore file is picked up too
+        fs::create_dir(base_dir.join("sub")).unwrap();
+        assert!(!cache.is_ignored(&base_dir.join("sub/notes.txt"), false));
+        fs::write(base_dir.join("sub/.gitignore"), "notes.txt\n").unwrap();
+        assert!(cache.is_ignored(&base_dir.join("sub/notes.txt"), false));
+    }
+
+    #[test]
+    fn test_invalid_patterns_fail_to_compile() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().canonicalize().unwrap();
+        fs::write(base_dir.join(".gitignore"), "*.pem\n").unwrap();
+        fs::create_dir(base_dir.join("sub")).unwrap();
+        fs::write(base_dir.join("sub/.gitignore"), "file[z-a]\n").unwrap();
+
+        let result = load_ignore_rules(&base_dir, &base_dir.join("sub"), Path::new(".gitignore"));
+        assert!(result.is_err());
+
+        // enforced rules fail closed, hiding everything the broken file governs
+        let cache = IgnoreCache::new(&base_dir, Path::new(".gitignore"), true);
+        assert!(cache.is_ignored(&base_dir.join("sub/key.pem"), false));
+        assert!(cache.is_ignored(&base_dir.join("sub/notes.txt"), false));
+        assert!(!cache.is_ignored(&base_dir.join("notes.txt"), false));
+
+        // listings alone fall back to showing the entries
+        let cache = IgnoreCache::new(&base_dir, Path::new(".gitignore"), false);
+        assert!(!cache.is_ignored(&base_dir.join("sub/notes.txt"), false));
+    }
+
+    #[test]
+    fn test_validation_finds_broken_nested_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path();
+        fs::create_dir_all(base_dir.join("a/b")).unwrap();
+        fs::write(base_dir.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(base_dir.join("a/.gitignore"), "*.tmp\n").unwrap();
+        assert!(validate_nested_ignore_files(base_dir, Path::new(".gitignore")).is_ok());
+
+        fs::write(base_dir.join("a/b/.gitignore"), "file[z-a]\n").unwrap();
+        let err = validate_nested_ignore_files(base_dir, Path::new(".gitignore")).unwrap_err();
+        assert!(format!("{err:#}").contains("a/b/.gitignore"));
+    }
+
+    #[test]
+    fn test_enforced_rules_hide_ignore_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(base_dir.join("sub")).unwrap();
+        fs::write(base_dir.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(base_dir.join("sub/.gitignore"), "*.tmp\n").unwrap();
+
+        let cache = IgnoreCache::new(&base_dir, Path::new(".gitignore"), true);
+        assert!(cache.is_ignored(&base_dir.join(".gitignore"), false));
+        assert!(cache.is_ignored(&base_dir.join("sub/.gitignore"), false));
+        assert!(!cache.is_ignored(&base_dir.join("sub/notes.txt"), false));
+
+        let cache = IgnoreCache::new(&base_dir, Path::new(".gitignore"), false);
+        assert!(!cache.is_ignored(&base_dir.join("sub/.gitignore"), false));
+        assert!(cache.is_ignore_file(&base_dir.join("sub/.gitignore")));
+    }
 }
@@ -1,5 +1,12 @@
 // utility functions module
 
+pub mod access;
+pub mod cidr;
 pub mod files;
+pub mod hosts;
 pub mod ignore;
+pub mod passwords;
 pub mod paths;
+pub mod share;
+pub mod tokens;
+pub mod watch;
@@ -0,0 +1,521 @@
+// password hashes and the users allowed to authenticate
+
+use argon2::{
+    Argon2, PasswordHasher, PasswordVerifier,
+    password_hash::{PasswordHash as PhcHash, SaltString, rand_core::OsRng},
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use thiserror::Error;
+
+use super::tokens::{ApiToken, TokenError, token_digest};
+use crate::config::SecurityConfig;
+
+#[derive(Error, Debug)]
+pub enum PasswordError {
+    #[error("unsupported password hash '{0}', expected argon2, bcrypt or sha-crypt")]
+    UnsupportedHash(String),
+    #[error("invalid {algorithm} hash: {reason}")]
+    InvalidHash {
+        algorithm: &'static str,
+        reason: String,
+    },
+    #[error("failed to hash password: {0}")]
+    Hashing(String),
+    #[error("failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}:{line}: expected 'user:hash'", path.display())]
+    InvalidLine { path: PathBuf, line: usize },
+    #[error("{}:{line}: user '{user}': {source}", path.display())]
+    InvalidEntry {
+        path: PathBuf,
+        line: usize,
+        user: String,
+        source: Box<PasswordError>,
+    },
+    #[error("user '{user}': {source}")]
+    InvalidUser {
+        user: String,
+        source: Box<PasswordError>,
+    },
+    #[error("invalid user name '{0}'")]
+    InvalidName(String),
+    #[error("user '{0}' is defined more than once")]
+    DuplicateUser(String),
+    #[error("token '{name}': {source}")]
+    InvalidToken { name: String, source: TokenError },
+    #[error("group '{group}' lists unknown user '{user}'")]
+    UnknownMember { group: String, user: String },
+}
+
+/// algorithms `hash-password` can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    Argon2id,
+    Bcrypt,
+    Sha512Crypt,
+}
+
+/// a password hash in modular crypt format: `$argon2id$`, `$2b$` (and the
+/// `$2a$`/`$2y$` variants htpasswd writes), `$5$` or `$6$`
+#[derive(Clone, PartialEq, Eq)]
+pub enum PasswordHash {
+    Argon2(String),
+    Bcrypt(String),
+    Sha256Crypt(String),
+    Sha512Crypt(String),
+}
+
+impl PasswordHash {
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Argon2(hash) => PhcHash::new(hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            }),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Self::Sha256Crypt(hash) => sha_crypt::sha256_check(password, hash).is_ok(),
+            Self::Sha512Crypt(hash) => sha_crypt::sha512_check(password, hash).is_ok(),
+        }
+    }
+}
+
+// hashes are credentials too, keep them out of debug logs
+impl fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let algorithm = match self {
+            Self::Argon2(_) => "Argon2",
+            Self::Bcrypt(_) => "Bcrypt",
+            Self::Sha256Crypt(_) => "Sha256Crypt",
+            Self::Sha512Crypt(_) => "Sha512Crypt",
+        };
+        write!(f, "{algorithm}(..)")
+    }
+}
+
+impl FromStr for PasswordHash {
+    type Err = PasswordError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |algorithm, reason: &dyn fmt::Display| PasswordError::InvalidHash {
+            algorithm,
+            reason: reason.to_string(),
+        };
+        let hash = value.to_string();
+
+        if value.starts_with("$argon2") {
+            let parsed = PhcHash::new(value).map_err(|err| invalid("argon2", &err))?;
+            if parsed.hash.is_none() {
+                return Err(invalid("argon2", &"missing hash output"));
+            }
+            return Ok(Self::Argon2(hash));
+        }
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| value.starts_with(prefix))
+        {
+            // $2b$<cost>$<22 character salt><31 character hash>
+            if value.len() != 60 || value.as_bytes()[6] != b'$' {
+                return Err(invalid("bcrypt", &"expected 60 characters"));
+            }
+            return Ok(Self::Bcrypt(hash));
+        }
+        if let Some(rest) = value.strip_prefix("$5$") {
+            check_sha_crypt(rest).map_err(|reason| invalid("sha256-crypt", &reason))?;
+            return Ok(Self::Sha256Crypt(hash));
+        }
+        if let Some(rest) = value.strip_prefix("$6$") {
+            check_sha_crypt(rest).map_err(|reason| invalid("sha512-crypt", &reason))?;
+            return Ok(Self::Sha512Crypt(hash));
+        }
+
+        // keep the value itself out of the message, it may be a plaintext password
+        let scheme = value
+            .split('$')
+            .nth(1)
+            .filter(|_| value.starts_with('$'))
+            .map_or_else(|| "(no scheme)".to_string(), |id| format!("${id}$"));
+        Err(PasswordError::UnsupportedHash(scheme))
+    }
+}
+
+// `[rounds=<n>$]<salt>$<hash>`
+fn check_sha_crypt(rest: &str) -> Result<(), &'static str> {
+    let rest = match rest.strip_prefix("rounds=") {
+        Some(rounds) => {
+            let (rounds, rest) = rounds.split_once('$').ok_or("missing salt")?;
+            rounds.parse::<u32>().map_err(|_| "invalid rounds")?;
+            rest
+        }
+        None => rest,
+    };
+    match rest.split_once('$') {
+        Some((_, hash)) if !hash.is_empty() && !hash.contains('$') => Ok(()),
+        _ => Err("expected '<salt>$<hash>'"),
+    }
+}
+
+/// hash a password for a `[[security.user]]` entry or an htpasswd file
+pub fn hash_password(password: &str, algorithm: HashAlgorithm) -> Result<String, PasswordError> {
+    match algorithm {
+        HashAlgorithm::Argon2id => {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| PasswordError::Hashing(err.to_string()))
+        }
+        HashAlgorithm::Bcrypt => bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|err| PasswordError::Hashing(err.to_string())),
+        HashAlgorithm::Sha512Crypt => {
+            sha_crypt::sha512_simple(password, &sha_crypt::Sha512Params::default())
+                .map_err(|err| PasswordError::Hashing(format!("{err:?}")))
+        }
+    }
+}
+
+/// read an apache style htpasswd file of `user:hash` lines
+pub fn load_htpasswd(path: &Path) -> Result<Vec<(String, PasswordHash)>, PasswordError> {
+    let contents = fs::read_to_string(path).map_err(|source| PasswordError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (user, hash) = line
+            .split_once(':')
+            .filter(|(user, _)| !user.is_empty())
+            .ok_or_else(|| PasswordError::InvalidLine {
+                path: path.to_path_buf(),
+                line: index + 1,
+            })?;
+        let hash = hash.parse().map_err(|err| PasswordError::InvalidEntry {
+            path: path.to_path_buf(),
+            line: index + 1,
+            user: user.to_string(),
+            source: Box::new(err),
+        })?;
+        entries.push((user.to_string(), hash));
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone)]
+enum Secret {
+    /// the legacy `username`/`password` pair
+    Plain(String),
+    Hashed(PasswordHash),
+    /// a `[[security.token]]` entry, only accepted as a bearer token
+    Token(ApiToken),
+}
+
+/// every user of a site: the plaintext `username`/`password` pair, the
+/// `[[security.user]]` entries, the lines of the `htpasswd_file` and the
+/// `[[security.token]]` entries, which share the user namespace
+#[derive(Debug, Clone, Default)]
+pub struct Users {
+    users: HashMap<String, Secret>,
+    /// members of each `[security.groups]` entry
+    groups: HashMap<String, HashSet<String>>,
+}
+
+impl Users {
+    pub fn load(security: &SecurityConfig) -> Result<Self, PasswordError> {
+        let mut users = Self::default();
+
+        if let (Some(username), Some(password)) = (&security.username, &security.password) {
+            users.insert(username, Secret::Plain(password.clone()))?;
+        }
+        for user in &security.users {
+            let hash = user
+                .password_hash
+                .parse()
+                .map_err(|err| PasswordError::InvalidUser {
+                    user: user.username.clone(),
+                    source: Box::new(err),
+                })?;
+            users.insert(&user.username, Secret::Hashed(hash))?;
+        }
+        if let Some(htpasswd_file) = &security.htpasswd_file {
+            for (username, hash) in load_htpasswd(htpasswd_file)? {
+                users.insert(&username, Secret::Hashed(hash))?;
+            }
+        }
+        for token in &security.tokens {
+            let api_token =
+                ApiToken::from_config(token).map_err(|source| PasswordError::InvalidToken {
+                    name: token.name.clone(),
+                    source,
+                })?;
+            users.insert(&token.name, Secret::Token(api_token))?;
+        }
+        for (group, members) in &security.groups {
+            if let Some(user) = members.iter().find(|user| !users.contains(user)) {
+                return Err(PasswordError::UnknownMember {
+                    group: group.clone(),
+                    user: user.clone(),
+                });
+            }
+            users
+                .groups
+                .insert(group.clone(), members.iter().cloned().collect());
+        }
+
+        Ok(users)
+    }
+
+    fn insert(&mut self, username: &str, secret: Secret) -> Result<(), PasswordError> {
+        if username.is_empty() || username.contains(':') {
+            return Err(PasswordError::InvalidName(username.to_string()));
+        }
+        if self.users.insert(username.to_string(), secret).is_some() {
+            return Err(PasswordError::DuplicateUser(username.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.groups.contains_key(group)
+    }
+
+    pub fn in_group(&self, username: &str, group: &str) -> bool {
+        self.groups
+            .get(group)
+            .is_some_and(|members| members.contains(username))
+    }
+
+    /// check a password; unknown users still pay for a hash check so response
+    /// times don't reveal which names exist
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        static UNKNOWN_USER: LazyLock<Option<PasswordHash>> = LazyLock::new(|| {
+            hash_password("", HashAlgorithm::Argon2id)
+                .ok()
+                .and_then(|hash| hash.parse().ok())
+        });
+
+        match self.users.get(username) {
+            Some(Secret::Plain(expected)) => {
+                constant_time_eq(password.as_bytes(), expected.as_bytes())
+            }
+            Some(Secret::Hashed(hash)) => hash.verify(password),
+            Some(Secret::Token(_)) | None => {
+                if let Some(hash) = UNKNOWN_USER.as_ref() {
+                    hash.verify(password);
+                }
+                false
+            }
+        }
+    }
+
+    /// the name and entry of a bearer token
+    pub fn verify_token(&self, token: &str) -> Option<(&str, &ApiToken)> {
+        let digest = token_digest(token);
+        self.users.iter().find_map(|(name, secret)| match secret {
+            Secret::Token(api_token) if api_token.matches(&digest) => {
+                Some((name.as_str(), api_token))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// constant-time string comparison to prevent timing attacks
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let max_len = a.len().max(b.len());
+    let mut result = (a.len() ^ b.len()) as u64;
+
+    for i in 0..max_len {
+        let x = *a.get(i).unwrap_or(&0);
+        let y = *b.get(i).unwrap_or(&0);
+        result |= (x ^ y) as u64;
+    }
+
+    result == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TokenConfig, TokenScope, UserConfig};
+    use crate::utils::tokens::hash_token;
+    use tempfile::TempDir;
+
+    // cheap parameters keep the tests fast; all verify "secret"
+    const ARGON2: &str = "$argon2id$v=19$m=256,t=1,p=1$uicJpmCY9To0IJO4LRFVZw$xqgtcQBUkwCC7WHyaBeSLu4Lh3LzKsgZc1RVEI9au7A";
+    const BCRYPT: &str = "$2y$04$EoghMZk4uSDfdUv29XJXwOzR81UMFmKlknhNb/j13bk4CkcNpnOIO";
+    const SHA512: &str = "$6$rounds=1000$vlHTkmH5Rg4bh/CL$Rh35cLKKrNh/PROvQGyDGE4mMD3ZVTx7Ts5El3NBP2d/YuudtSdL6tqaS6Jk.EUn4I1nL/1tHvP1ZSH/HrvEP.";
+
+    #[test]
+    fn parses_supported_hashes() {
+        assert!(matches!(ARGON2.parse(), Ok(PasswordHash::Argon2(_))));
+        assert!(matches!(BCRYPT.parse(), Ok(PasswordHash::Bcrypt(_))));
+        assert!(matches!(SHA512.parse(), Ok(PasswordHash::Sha512Crypt(_))));
+
+        assert!(matches!(
+            "plaintext".parse::<PasswordHash>(),
+            Err(PasswordError::UnsupportedHash(scheme)) if scheme == "(no scheme)"
+        ));
+        assert!(matches!(
+            "$apr1$salt$hash".parse::<PasswordHash>(),
+            Err(PasswordError::UnsupportedHash(scheme)) if scheme == "$apr1$"
+        ));
+        assert!("$2y$04$short".parse::<PasswordHash>().is_err());
+        assert!("$6$salt".parse::<PasswordHash>().is_err());
+        assert!(
+            "$argon2id$v=19$m=256,t=1,p=1"
+                .parse::<PasswordHash>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verifies_passwords() {
+        for hash in [ARGON2, BCRYPT, SHA512] {
+            let hash: PasswordHash = hash.parse().unwrap();
+            assert!(hash.verify("secret"), "{hash:?}");
+            assert!(!hash.verify("wrong"), "{hash:?}");
+        }
+    }
+
+    #[test]
+    fn hashes_round_trip() {
+        for algorithm in [HashAlgorithm::Bcrypt, HashAlgorithm::Sha512Crypt] {
+            let hash: PasswordHash = hash_password("secret", algorithm).unwrap().parse().unwrap();
+            assert!(hash.verify("secret"));
+            assert!(!hash.verify("secret2"));
+        }
+        let hash = hash_password("secret", HashAlgorithm::Argon2id).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+    }
+
+    #[test]
+    fn loads_users_from_every_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let htpasswd = temp_dir.path().join("htpasswd");
+        fs::write(
+            &htpasswd,
+            format!("# team\ncarol:{BCRYPT}\n\ndave:{SHA512}\n"),
+        )
+        .unwrap();
+
+        let security = SecurityConfig {
+            username: Some("admin".to_string()),
+            password: Some("plain".to_string()),
+            users: vec![UserConfig {
+                username: "alice".to_string(),
+                password_hash: ARGON2.to_string(),
+            }],
+            htpasswd_file: Some(htpasswd.clone()),
+            ..Default::default()
+        };
+        let users = Users::load(&security).unwrap();
+        assert!(users.verify("admin", "plain"));
+        assert!(users.verify("alice", "secret"));
+        assert!(users.verify("carol", "secret"));
+        assert!(users.verify("dave", "secret"));
+        assert!(!users.verify("alice", "plain"));
+        assert!(!users.verify("nobody", "secret"));
+
+        assert!(!users.in_group("alice", "dev"));
+
+        let mut grouped = security.clone();
+        grouped.groups.insert(
+            "dev".to_string(),
+            vec!["alice".to_string(), "carol".to_string()],
+        );
+        let users = Users::load(&grouped).unwrap();
+        assert!(users.has_group("dev"));
+        assert!(users.in_group("carol", "dev"));
+        assert!(!users.in_group("dave", "dev"));
+        grouped
+            .groups
+            .insert("ops".to_string(), vec!["eve".to_string()]);
+        assert!(matches!(
+            Users::load(&grouped),
+            Err(PasswordError::UnknownMember { user, .. }) if user == "eve"
+        ));
+
+        fs::write(&htpasswd, format!("alice:{BCRYPT}\n")).unwrap();
+        assert!(matches!(
+            Users::load(&security),
+            Err(PasswordError::DuplicateUser(user)) if user == "alice"
+        ));
+
+        fs::write(&htpasswd, "carol:{SHA}fEqNCco3Yq9h5ZUglD3CZJT4lBs=\n").unwrap();
+        let err = Users::load(&security).unwrap_err().to_string();
+        assert!(err.contains(":1: user 'carol'"), "{err}");
+
+        fs::write(&htpasswd, "no separator\n").unwrap();
+        assert!(matches!(
+            Users::load(&security),
+            Err(PasswordError::InvalidLine { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn tokens_share_the_user_namespace() {
+        let token = |name: &str, token: &str| TokenConfig {
+            name: name.to_string(),
+            token_hash: hash_token(token),
+            scopes: vec![TokenScope::Upload],
+            paths: Vec::new(),
+            expires: None,
+        };
+        let mut security = SecurityConfig {
+            tokens: vec![token("ci", "soop_one"), token("deploy", "soop_two")],
+            ..Default::default()
+        };
+        security
+            .groups
+            .insert("bots".to_string(), vec!["ci".to_string()]);
+
+        let users = Users::load(&security).unwrap();
+        assert!(users.in_group("ci", "bots"));
+        assert_eq!(
+            users.verify_token("soop_two").map(|(name, _)| name),
+            Some("deploy")
+        );
+        assert!(users.verify_token("soop_three").is_none());
+        // tokens are not passwords
+        assert!(!users.verify("ci", "soop_one"));
+
+        security.username = Some("ci".to_string());
+        security.password = Some("plain".to_string());
+        assert!(matches!(
+            Users::load(&security),
+            Err(PasswordError::DuplicateUser(user)) if user == "ci"
+        ));
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"hello", b"hello"));
+        assert!(!constant_time_eq(b"hello", b"world"));
+        assert!(!constant_time_eq(b"hello", b"hell"));
+        assert!(!constant_time_eq(b"hell", b"hello"));
+        assert!(!constant_time_eq(b"", b"hello"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
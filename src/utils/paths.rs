@@ -26,6 +26,9 @@ pub enum PathTraversalError {
 
     #[error("backslash not allowed in path")]
     Backslash,
+
+    #[error("parent segments not allowed in path")]
+    ParentSegment,
 }
 
 const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &CONTROLS
@@ -61,18 +64,45 @@ pub fn encode_path_segments(path: &str) -> String {
     encoded
 }
 
-/// safely join a path component to a base directory, preventing traversal attacks
-/// this is the core security function that prevents directory traversal
-pub fn join_path_jailed(base_dir: &Path, component: &str) -> Result<PathBuf, PathTraversalError> {
-    join_path_jailed_follow_parents(base_dir, component)
+/// percent-decode a request path once and normalise it: empty and `.` segments
+/// are dropped and a trailing slash is kept, while `..` segments, encoded
+/// slashes, backslashes and nul bytes are refused
+pub fn decode_request_path(path: &str) -> Result<String, PathTraversalError> {
+    if contains_encoded_slash(path) {
+        return Err(PathTraversalError::EncodedSlash);
+    }
+    let decoded = percent_decode_str(path)
+        .decode_utf8()
+        .map_err(|_| PathTraversalError::InvalidEncoding)?;
+    check_decoded(&decoded)?;
+
+    let mut normalized = String::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(PathTraversalError::ParentSegment),
+            segment => {
+                normalized.push('/');
+                normalized.push_str(segment);
+            }
+        }
+    }
+    if normalized.is_empty() || decoded.ends_with('/') {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
 }
 
-/// resolve a jailed path while following symlinks in existing parent directories
-pub fn join_path_jailed_follow_parents(
+/// safely join a path already decoded by `decode_request_path` to a base
+/// directory without decoding it a second time, following symlinks in existing
+/// parent directories; this is the core security function that prevents
+/// directory traversal
+pub fn join_decoded_path_jailed(
     base_dir: &Path,
-    component: &str,
+    path: &str,
 ) -> Result<PathBuf, PathTraversalError> {
-    let normalized = normalize_path_component(component)?;
+    let normalized = normalize_decoded(path.trim_start_matches('/'))?;
     let canonical_base = base_dir
         .canonicalize()
         .map_err(|_| PathTraversalError::InvalidBasePath)?;
@@ -126,28 +156,22 @@ pub fn join_path_jailed_follow_parents(
     Ok(current)
 }
 
-/// normalize a path component by url-decoding and cleaning up dangerous elements
-fn normalize_path_component(component: &str) -> Result<PathBuf, PathTraversalError> {
-    if contains_encoded_slash(component) {
-        return Err(PathTraversalError::EncodedSlash);
-    }
-
-    // url decode the component
-    let decoded = percent_decode_str(component)
-        .decode_utf8()
-        .map_err(|_| PathTraversalError::InvalidEncoding)?;
-
+fn check_decoded(decoded: &str) -> Result<(), PathTraversalError> {
     if decoded.contains('\0') {
         return Err(PathTraversalError::InvalidEncoding);
     }
     if decoded.contains('\\') {
         return Err(PathTraversalError::Backslash);
     }
+    Ok(())
+}
 
-    // build normalized path from components
+// build normalized path from components
+fn normalize_decoded(decoded: &str) -> Result<PathBuf, PathTraversalError> {
+    check_decoded(decoded)?;
     let mut normalized = PathBuf::new();
 
-    for component in Path::new(decoded.as_ref()).components() {
+    for component in Path::new(decoded).components() {
         match component {
             Component::Normal(name) => normalized.push(name),
             Component::CurDir => {} // ignore "."
@@ -199,12 +223,12 @@ mod tests {
         fs::write(base_path.join("test.txt"), "content").unwrap();
 
         // valid paths should succeed
-        assert!(join_path_jailed(base_path, "test.txt").is_ok());
+        assert!(join_decoded_path_jailed(base_path, "/test.txt").is_ok());
 
         // create subdirectory
         fs::create_dir(base_path.join("subdir")).unwrap();
         fs::write(base_path.join("subdir/nested.txt"), "content").unwrap();
-        assert!(join_path_jailed(base_path, "subdir/nested.txt").is_ok());
+        assert!(join_decoded_path_jailed(base_path, "subdir/nested.txt").is_ok());
     }
 
     #[test]
@@ -217,24 +241,32 @@ mod tests {
         fs::write(&outside_file, "secret").unwrap();
 
         // traversal attempts should fail
-        assert!(join_path_jailed(base_path, "../outside.txt").is_err());
-        assert!(join_path_jailed(base_path, "../../etc/passwd").is_err());
-        assert!(join_path_jailed(base_path, "/etc/passwd").is_err());
+        assert!(join_decoded_path_jailed(base_path, "../outside.txt").is_err());
+        assert!(join_decoded_path_jailed(base_path, "/../../etc/passwd").is_err());
+        assert!(join_decoded_path_jailed(base_path, "a\\..\\..\\outside.txt").is_err());
 
         // encoded traversal attempts should also fail
-        assert!(join_path_jailed(base_path, "%2e%2e/outside.txt").is_err());
-        assert!(join_path_jailed(base_path, "..%2foutside.txt").is_err());
+        assert!(decode_request_path("/%2e%2e/outside.txt").is_err());
+        assert!(decode_request_path("/..%2foutside.txt").is_err());
+        assert!(decode_request_path("/a%00b").is_err());
     }
 
     #[test]
-    fn test_path_normalization() {
-        assert!(normalize_path_component("normal_file.txt").is_ok());
-        assert!(normalize_path_component("dir/file.txt").is_ok());
-        assert!(normalize_path_component("../file.txt").is_ok()); // allowed, caught later
-
-        // url encoding should be handled
-        let result = normalize_path_component("file%20with%20spaces.txt").unwrap();
-        assert_eq!(result, PathBuf::from("file with spaces.txt"));
+    fn test_decode_request_path() {
+        assert_eq!(
+            decode_request_path("/%62uilds/a%20b").unwrap(),
+            "/builds/a b"
+        );
+        assert_eq!(decode_request_path("//dir/./sub/").unwrap(), "/dir/sub/");
+        assert_eq!(decode_request_path("/").unwrap(), "/");
+        assert_eq!(decode_request_path("/100%25.txt").unwrap(), "/100%.txt");
+        assert!(decode_request_path("/a/%2e%2e/b").is_err());
+        assert!(decode_request_path("/a%2fb").is_err());
+
+        // the decoded path is joined as is, never decoded twice
+        let temp_dir = TempDir::new().unwrap();
+        let joined = join_decoded_path_jailed(temp_dir.path(), "/100%25.txt").unwrap();
+        assert!(joined.ends_with("100%25.txt"));
     }
 
     #[test]
@@ -0,0 +1,344 @@
+// hmac signed, expiring share links
+
+use axum::http::Method;
+use base64::prelude::*;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use ring::hmac;
+use std::time::Duration;
+use thiserror::Error;
+
+use super::access::AccessPath;
+use super::paths::encode_path_segments;
+
+/// shortest `share_secret` accepted, in bytes
+pub const MIN_SECRET_LEN: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum ShareError {
+    #[error("share links are disabled, set security.share_secret")]
+    Disabled,
+    #[error("invalid share path '{0}', expected an absolute path without '..'")]
+    InvalidPath(String),
+    #[error("invalid duration '{0}', expected a number with s, m, h, d or w such as 24h")]
+    InvalidDuration(String),
+}
+
+/// what a signed query grants, checked by [`ShareLinks::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShareCheck {
+    /// the request carries no signature
+    NotShared,
+    /// a good signature, by the user who minted the link; `None` for links
+    /// from `soop3 sign`
+    Valid {
+        by: Option<String>,
+    },
+    Invalid(&'static str),
+}
+
+/// signs and checks share links with the site's `share_secret`
+#[derive(Debug, Clone, Default)]
+pub struct ShareLinks {
+    key: Option<hmac::Key>,
+    /// the site links are signed for, so vhosts sharing a secret never
+    /// accept each other's links; see [`crate::config::VhostConfig::canonical_name`]
+    site: String,
+}
+
+impl ShareLinks {
+    pub fn new(secret: Option<&str>, site: &str) -> Self {
+        Self {
+            key: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            site: site.to_string(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// the url path and query of a link granting `method` on `path`, or on
+    /// everything below it when `prefix` is set, until `expires` (unix seconds);
+    /// `by` names the user minting it, whose access rules the link keeps to
+    pub fn sign(
+        &self,
+        path: &str,
+        prefix: bool,
+        method: &Method,
+        by: Option<&str>,
+        expires: i64,
+    ) -> Result<String, ShareError> {
+        let key = self.key.as_ref().ok_or(ShareError::Disabled)?;
+        let scope = AccessPath::from_uri(path)
+            .filter(|_| path.starts_with('/'))
+            .ok_or_else(|| ShareError::InvalidPath(path.to_string()))?;
+
+        let message = signed_message(&self.site, method, scope.as_str(), prefix, by, expires);
+        let sig = BASE64_URL_SAFE_NO_PAD.encode(hmac::sign(key, message.as_bytes()));
+
+        let decoded = percent_decode_str(path).decode_utf8_lossy();
+        let mut url = encode_path_segments(&decoded);
+        url.push('?');
+        if prefix {
+            url.push_str("prefix=");
+            url.push_str(&utf8_percent_encode(scope.as_str(), NON_ALPHANUMERIC).to_string());
+            url.push('&');
+        }
+        if method != Method::GET {
+            url.push_str(&format!("method={method}&"));
+        }
+        url.push_str(&format!("expires={expires}&"));
+        if let Some(by) = by {
+            url.push_str("by=");
+            url.push_str(&utf8_percent_encode(by, NON_ALPHANUMERIC).to_string());
+            url.push('&');
+        }
+        url.push_str(&format!("sig={sig}"));
+        Ok(url)
+    }
+
+    /// check the signed query of a request at `now` (unix seconds)
+    pub fn verify(
+        &self,
+        method: &Method,
+        path: &AccessPath,
+        query: Option<&str>,
+        now: i64,
+    ) -> ShareCheck {
+        let (Some(key), Some(query)) = (&self.key, query) else {
+            return ShareCheck::NotShared;
+        };
+        let Some(link) = SignedQuery::parse(query) else {
+            return ShareCheck::NotShared;
+        };
+
+        let Some(expires) = link.expires else {
+            return ShareCheck::Invalid("missing expiry");
+        };
+        let Ok(sig) = BASE64_URL_SAFE_NO_PAD.decode(&link.sig) else {
+            return ShareCheck::Invalid("malformed signature");
+        };
+        let link_method = link.method.unwrap_or(Method::GET);
+        if *method != link_method && !(link_method == Method::GET && *method == Method::HEAD) {
+            return ShareCheck::Invalid("link is for another method");
+        }
+        let scope = match &link.prefix {
+            Some(prefix) => match AccessPath::from_uri(prefix) {
+                Some(prefix) if path.starts_with(prefix.as_str()) => prefix,
+                _ => return ShareCheck::Invalid("path is outside the shared prefix"),
+            },
+            None => path.clone(),
+        };
+
+        let message = signed_message(
+            &self.site,
+            &link_method,
+            scope.as_str(),
+            link.prefix.is_some(),
+            link.by.as_deref(),
+            expires,
+        );
+        if hmac::verify(key, message.as_bytes(), &sig).is_err() {
+            return ShareCheck::Invalid("bad signature");
+        }
+        if expires <= now {
+            return ShareCheck::Invalid("link expired");
+        }
+        ShareCheck::Valid { by: link.by }
+    }
+}
+
+// everything a signature vouches for, one field per line; the site, path and
+// user are encoded so none can carry a line break into the next field
+fn signed_message(
+    site: &str,
+    method: &Method,
+    path: &str,
+    prefix: bool,
+    by: Option<&str>,
+    expires: i64,
+) -> String {
+    let kind = if prefix { "prefix" } else { "path" };
+    let site = utf8_percent_encode(site, NON_ALPHANUMERIC);
+    let path = utf8_percent_encode(path, NON_ALPHANUMERIC);
+    let by = utf8_percent_encode(by.unwrap_or_default(), NON_ALPHANUMERIC);
+    format!("{site}\n{method}\n{kind}\n{path}\n{by}\n{expires}")
+}
+
+// the share parameters of a query string; other keys are left to the handlers
+#[derive(Debug, Default)]
+struct SignedQuery {
+    sig: String,
+    expires: Option<i64>,
+    method: Option<Method>,
+    prefix: Option<String>,
+    by: Option<String>,
+}
+
+impl SignedQuery {
+    fn parse(query: &str) -> Option<Self> {
+        let mut link = Self::default();
+        let mut signed = false;
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "sig" => {
+                    signed = true;
+                    link.sig = value.into_owned();
+                }
+                "expires" => link.expires = value.parse().ok(),
+                "method" => link.method = Method::from_bytes(value.as_bytes()).ok(),
+                "prefix" => link.prefix = Some(value.into_owned()),
+                "by" => link.by = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        signed.then_some(link)
+    }
+}
+
+/// parse a link lifetime such as `90m`, `24h` or `7d`; bare numbers are seconds
+pub fn parse_duration(value: &str) -> Result<Duration, ShareError> {
+    let invalid = || ShareError::InvalidDuration(value.to_string());
+    let split = value
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    match number.checked_mul(seconds) {
+        Some(0) | None => Err(invalid()),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn check(links: &ShareLinks, method: Method, url: &str, now: i64) -> ShareCheck {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        links.verify(
+            &method,
+            &AccessPath::from_uri(path).unwrap(),
+            Some(query),
+            now,
+        )
+    }
+
+    #[test]
+    fn signed_paths_verify_until_they_expire() {
+        let links = ShareLinks::new(Some(SECRET), "");
+        let url = links
+            .sign("/docs/report 2024.pdf", false, &Method::GET, None, 1000)
+            .unwrap();
+        assert!(
+            url.starts_with("/docs/report%202024.pdf?expires=1000&sig="),
+            "{url}"
+        );
+
+        let valid = ShareCheck::Valid { by: None };
+        assert_eq!(check(&links, Method::GET, &url, 999), valid);
+        assert_eq!(check(&links, Method::HEAD, &url, 999), valid);
+        assert!(matches!(
+            check(&links, Method::GET, &url, 1000),
+            ShareCheck::Invalid("link expired")
+        ));
+        assert!(matches!(
+            check(&links, Method::POST, &url, 999),
+            ShareCheck::Invalid(_)
+        ));
+
+        let query = url.split_once('?').unwrap().1;
+        for tampered in [
+            format!("/docs/other.pdf?{query}"),
+            url.replace("expires=1000", "expires=9999"),
+            format!("{url}&method=POST"),
+            format!("{url}&by=alice"),
+        ] {
+            assert!(
+                matches!(
+                    check(&links, Method::GET, &tampered, 999),
+                    ShareCheck::Invalid(_)
+                ),
+                "{tampered}"
+            );
+        }
+
+        let other_secret = ShareLinks::new(Some("another secret, just as long......"), "");
+        assert!(matches!(
+            check(&other_secret, Method::GET, &url, 999),
+            ShareCheck::Invalid("bad signature")
+        ));
+        let other_site = ShareLinks::new(Some(SECRET), "docs.local");
+        assert!(matches!(
+            check(&other_site, Method::GET, &url, 999),
+            ShareCheck::Invalid("bad signature")
+        ));
+        assert_eq!(
+            check(&ShareLinks::default(), Method::GET, &url, 999),
+            ShareCheck::NotShared
+        );
+        assert_eq!(
+            check(&links, Method::GET, "/docs/a.pdf?format=json", 999),
+            ShareCheck::NotShared
+        );
+    }
+
+    #[test]
+    fn prefixes_cover_paths_below_them() {
+        let links = ShareLinks::new(Some(SECRET), "");
+        let url = links
+            .sign("/builds/", true, &Method::POST, Some("ci bot"), 1000)
+            .unwrap();
+        assert!(
+            url.starts_with("/builds/?prefix=%2Fbuilds&method=POST&"),
+            "{url}"
+        );
+        assert!(url.contains("&by=ci%20bot&"), "{url}");
+        let query = url.split_once('?').unwrap().1;
+
+        for (path, method, valid) in [
+            ("/builds/", Method::POST, true),
+            ("/builds/nightly/", Method::POST, true),
+            ("/builds/", Method::GET, false),
+            ("/buildsx/", Method::POST, false),
+            ("/", Method::POST, false),
+        ] {
+            let result = check(&links, method.clone(), &format!("{path}?{query}"), 999);
+            let by = Some("ci bot".to_string());
+            assert_eq!(result == ShareCheck::Valid { by }, valid, "{method} {path}");
+        }
+
+        assert!(matches!(
+            links.sign("builds", false, &Method::GET, None, 1000),
+            Err(ShareError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            ShareLinks::default().sign("/builds", false, &Method::GET, None, 1000),
+            Err(ShareError::Disabled)
+        ));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_duration("24h").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604800));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::from_secs(1209600));
+        for invalid in ["", "h", "0h", "24x", "1.5h", "-1h"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+}
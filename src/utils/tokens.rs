@@ -0,0 +1,196 @@
+// bearer tokens for scripts and ci
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::Method;
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::access::AccessPath;
+use super::passwords::constant_time_eq;
+use crate::config::{TokenConfig, TokenScope};
+
+/// marks generated tokens so they are easy to spot in logs and secret scanners
+pub const TOKEN_PREFIX: &str = "soop_";
+
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("invalid token hash, expected 'sha256:' and 64 hex digits")]
+    InvalidHash,
+    #[error("a token needs at least one scope")]
+    NoScopes,
+    #[error("invalid token path '{0}', expected a prefix starting with '/'")]
+    InvalidPath(String),
+}
+
+/// a `[[security.token]]` entry ready to check requests against
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    digest: [u8; 32],
+    scopes: Vec<TokenScope>,
+    paths: Vec<String>,
+    expires: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn from_config(config: &TokenConfig) -> Result<Self, TokenError> {
+        let digest = config
+            .token_hash
+            .strip_prefix("sha256:")
+            .and_then(parse_hex_digest)
+            .ok_or(TokenError::InvalidHash)?;
+        if config.scopes.is_empty() {
+            return Err(TokenError::NoScopes);
+        }
+        if let Some(path) = config
+            .paths
+            .iter()
+            .find(|path| !path.starts_with('/') || AccessPath::from_uri(path).is_none())
+        {
+            return Err(TokenError::InvalidPath(path.clone()));
+        }
+
+        Ok(Self {
+            digest,
+            scopes: config.scopes.clone(),
+            paths: config.paths.clone(),
+            expires: config.expires,
+        })
+    }
+
+    /// compare a presented token against the stored digest
+    pub fn matches(&self, digest: &[u8; 32]) -> bool {
+        constant_time_eq(&self.digest, digest)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// whether the token's scopes and path prefixes allow a request
+    pub fn covers(&self, method: &Method, path: &AccessPath) -> bool {
+        let scope = match *method {
+            Method::GET | Method::HEAD => TokenScope::Read,
+            Method::DELETE => TokenScope::Delete,
+            _ => TokenScope::Upload,
+        };
+        self.scopes.contains(&scope)
+            && (self.paths.is_empty() || self.paths.iter().any(|prefix| path.starts_with(prefix)))
+    }
+}
+
+/// a new random token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// the sha-256 digest a token is looked up by
+pub fn token_digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// the `token_hash` value stored in the config for a token
+pub fn hash_token(token: &str) -> String {
+    let hex: String = token_digest(token)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256:{hex}")
+}
+
+fn parse_hex_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(token: &str, scopes: &[TokenScope], paths: &[&str]) -> TokenConfig {
+        TokenConfig {
+            name: "ci".to_string(),
+            token_hash: hash_token(token),
+            scopes: scopes.to_vec(),
+            paths: paths.iter().map(ToString::to_string).collect(),
+            expires: None,
+        }
+    }
+
+    #[test]
+    fn generated_tokens_match_their_hash() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 43);
+        assert_ne!(token, generate_token());
+
+        let api_token = ApiToken::from_config(&config(&token, &[TokenScope::Read], &[])).unwrap();
+        assert!(api_token.matches(&token_digest(&token)));
+        assert!(!api_token.matches(&token_digest("soop_other")));
+        assert_eq!(
+            hash_token("abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let mut invalid = config("t", &[TokenScope::Read], &[]);
+        invalid.token_hash = "sha256:abc".to_string();
+        assert!(matches!(
+            ApiToken::from_config(&invalid),
+            Err(TokenError::InvalidHash)
+        ));
+        invalid.token_hash = hash_token("t").replace("sha256:", "md5:");
+        assert!(ApiToken::from_config(&invalid).is_err());
+
+        assert!(matches!(
+            ApiToken::from_config(&config("t", &[], &[])),
+            Err(TokenError::NoScopes)
+        ));
+        for path in ["builds", "/builds/../etc"] {
+            assert!(matches!(
+                ApiToken::from_config(&config("t", &[TokenScope::Read], &[path])),
+                Err(TokenError::InvalidPath(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scopes_paths_and_expiry_limit_requests() {
+        let path = |uri: &str| AccessPath::from_uri(uri).unwrap();
+        let token = ApiToken::from_config(&config(
+            "t",
+            &[TokenScope::Read, TokenScope::Upload],
+            &["/builds/"],
+        ))
+        .unwrap();
+
+        assert!(token.covers(&Method::GET, &path("/builds/a.tar")));
+        assert!(token.covers(&Method::POST, &path("/builds/")));
+        assert!(!token.covers(&Method::DELETE, &path("/builds/a.tar")));
+        assert!(!token.covers(&Method::GET, &path("/")));
+        assert!(!token.covers(&Method::GET, &path("/buildsx/a.tar")));
+
+        let anywhere = ApiToken::from_config(&config("t", &[TokenScope::Delete], &[])).unwrap();
+        assert!(anywhere.covers(&Method::DELETE, &path("/a.txt")));
+        assert!(!anywhere.covers(&Method::GET, &path("/a.txt")));
+
+        let now = Utc::now();
+        assert!(!token.is_expired(now));
+        let mut expiring = config("t", &[TokenScope::Read], &[]);
+        expiring.expires = Some(now);
+        let expiring = ApiToken::from_config(&expiring).unwrap();
+        assert!(expiring.is_expired(now));
+        assert!(!expiring.is_expired(now - chrono::Duration::seconds(1)));
+    }
+}
@@ -0,0 +1,71 @@
+// change detection for files that are re-read while the server runs
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// modification time and size of a file, `None` while it is missing or not a
+/// regular file
+pub type FileStamp = Option<(SystemTime, u64)>;
+
+pub fn file_stamp(path: &Path) -> FileStamp {
+    let metadata = fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub fn file_stamps(paths: &[PathBuf]) -> Vec<FileStamp> {
+    paths.iter().map(|path| file_stamp(path)).collect()
+}
+
+/// call `reload` on SIGHUP and whenever one of `files` changes on disk;
+/// `what` names the reloaded thing in the logs
+pub fn spawn_reloader<F>(what: &'static str, files: Vec<PathBuf>, mut reload: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(signal) => Some(signal),
+            Err(err) => {
+                warn!("failed to install SIGHUP handler: {}", err);
+                None
+            }
+        };
+
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        poll.tick().await;
+        let mut stamps = file_stamps(&files);
+
+        loop {
+            #[cfg(unix)]
+            let hangup_received = async {
+                match hangup.as_mut() {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup_received = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup_received => info!("received SIGHUP, reloading {}", what),
+                _ = poll.tick() => {
+                    if file_stamps(&files) == stamps {
+                        continue;
+                    }
+                    info!("{} changed on disk, reloading", what);
+                }
+            }
+
+            stamps = file_stamps(&files);
+            reload();
+        }
+    });
+}
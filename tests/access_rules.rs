@@ -0,0 +1,164 @@
+// per-path access rules for users and groups
+
+mod support;
+
+use axum::http::{Request, StatusCode, header};
+use soop3::config::{AccessRuleConfig, AppConfig, Permission, SecurityPolicy, UserConfig};
+use std::fs;
+use std::path::Path;
+use support::{
+    BOUNDARY, app, auth_header, base_config, body_string, get_with_headers, multipart_body,
+    multipart_request,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+// bcrypt of "secret" at the lowest cost
+const HASH: &str = "$2y$04$EoghMZk4uSDfdUv29XJXwOzR81UMFmKlknhNb/j13bk4CkcNpnOIO";
+
+fn rule(
+    path: &str,
+    users: &[&str],
+    groups: &[&str],
+    permissions: &[Permission],
+) -> AccessRuleConfig {
+    AccessRuleConfig {
+        path: path.to_string(),
+        methods: Vec::new(),
+        users: users.iter().map(ToString::to_string).collect(),
+        groups: groups.iter().map(ToString::to_string).collect(),
+        permissions: permissions.to_vec(),
+    }
+}
+
+fn team_config(public_dir: &Path) -> AppConfig {
+    fs::create_dir(public_dir.join("public")).unwrap();
+    fs::create_dir(public_dir.join("team")).unwrap();
+    fs::create_dir(public_dir.join("incoming")).unwrap();
+    fs::write(public_dir.join("public/a.txt"), "public").unwrap();
+    fs::write(public_dir.join("team/notes.txt"), "notes").unwrap();
+    fs::write(public_dir.join("readme.txt"), "readme").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.server.enable_upload = true;
+    config.upload.prepend_timestamp = false;
+    config.security.policy = SecurityPolicy::AuthenticateAll;
+    config.security.users = ["alice", "bob", "ci"]
+        .into_iter()
+        .map(|name| UserConfig {
+            username: name.to_string(),
+            password_hash: HASH.to_string(),
+        })
+        .collect();
+    config
+        .security
+        .groups
+        .insert("dev".to_string(), vec!["alice".to_string()]);
+
+    use Permission::*;
+    let mut public = rule("/public/**", &[], &[], &[Read, List]);
+    public.methods = vec!["GET".to_string(), "HEAD".to_string()];
+    config.access_rules = vec![
+        public,
+        rule("/team/**", &[], &["dev"], &[Read, List, Write]),
+        rule("/incoming/", &["ci"], &[], &[Write]),
+    ];
+    config
+}
+
+fn as_user(
+    mut request: Request<axum::body::Body>,
+    user: Option<&str>,
+) -> Request<axum::body::Body> {
+    if let Some(user) = user {
+        let value = format!("Basic {}", auth_header(user, "secret"));
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, value.parse().unwrap());
+    }
+    request
+}
+
+#[tokio::test]
+async fn rules_grant_and_refuse_by_user_and_group() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(team_config(temp_dir.path()));
+
+    for (path, user, status) in [
+        // anonymous reads despite the authenticate_all policy
+        ("/public/a.txt", None, StatusCode::OK),
+        ("/public/", None, StatusCode::OK),
+        ("/team/notes.txt", None, StatusCode::UNAUTHORIZED),
+        ("/team/notes.txt", Some("bob"), StatusCode::FORBIDDEN),
+        ("/team/notes.txt", Some("alice"), StatusCode::OK),
+        ("/incoming/", Some("ci"), StatusCode::FORBIDDEN),
+        // no rule matches, the policy decides
+        ("/readme.txt", None, StatusCode::UNAUTHORIZED),
+        ("/readme.txt", Some("bob"), StatusCode::OK),
+        ("/public/../team/notes.txt", None, StatusCode::BAD_REQUEST),
+        ("/te%61m/notes.txt", Some("bob"), StatusCode::FORBIDDEN),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(as_user(get_with_headers(path, &[]), user))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{path} as {user:?}");
+    }
+}
+
+#[tokio::test]
+async fn upload_only_directory_accepts_its_user() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(team_config(temp_dir.path()));
+
+    for (path, user, status) in [
+        ("/incoming/", Some("ci"), StatusCode::NO_CONTENT),
+        ("/incoming/", None, StatusCode::UNAUTHORIZED),
+        ("/incoming/", Some("bob"), StatusCode::FORBIDDEN),
+        ("/public/", None, StatusCode::UNAUTHORIZED),
+        ("/public/", Some("alice"), StatusCode::NO_CONTENT),
+    ] {
+        let body = multipart_body(
+            BOUNDARY,
+            &format!("{}.tar", user.unwrap_or("anon")),
+            b"build",
+        );
+        let response = app
+            .clone()
+            .oneshot(as_user(multipart_request(path, BOUNDARY, body), user))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{path} as {user:?}");
+    }
+    assert!(temp_dir.path().join("incoming/ci.tar").exists());
+}
+
+#[tokio::test]
+async fn listings_hide_entries_the_user_cannot_read() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(team_config(temp_dir.path()));
+
+    for (user, visible) in [
+        ("alice", vec!["public", "readme.txt", "team"]),
+        ("bob", vec!["public", "readme.txt"]),
+        ("ci", vec!["public", "readme.txt"]),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(as_user(get_with_headers("/?format=json", &[]), Some(user)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let listing: serde_json::Value =
+            serde_json::from_str(&body_string(response).await).unwrap();
+        let mut names: Vec<&str> = listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, visible, "{user}");
+    }
+}
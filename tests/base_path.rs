@@ -0,0 +1,126 @@
+// serving under a configured base path
+
+mod support;
+
+use axum::http::{StatusCode, header};
+use soop3::config::{AppConfig, UploadConfig};
+use std::fs;
+use std::path::Path;
+use support::{
+    BOUNDARY, app, base_config, body_string, get, multipart_body, multipart_request, upload_config,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn mounted(public_dir: &Path) -> AppConfig {
+    let mut config = base_config(public_dir);
+    config.server.base_path = Some("/files".to_string());
+    config
+}
+
+#[tokio::test]
+async fn serves_files_under_the_prefix_only() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("hello.txt"), "hello").unwrap();
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files/hello.txt"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "hello");
+
+    for outside in [
+        "/hello.txt",
+        "/",
+        "/filesx/hello.txt",
+        "/__soop_static/style.css",
+    ] {
+        let response = app(mounted(temp_dir.path()))
+            .oneshot(get(outside))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{outside}");
+    }
+}
+
+#[tokio::test]
+async fn redirects_and_links_include_the_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("docs")).unwrap();
+    fs::write(temp_dir.path().join("docs/readme.txt"), "read me").unwrap();
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/files/");
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files/docs"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "/files/docs/"
+    );
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files/docs/"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_string(response).await;
+    assert!(
+        body.contains("href=\"/files/__soop_static/style.css\""),
+        "{body}"
+    );
+    assert!(body.contains("<a href=\"/files/\">"), "{body}");
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files/__soop_static/style.css"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app(mounted(temp_dir.path()))
+        .oneshot(get("/files/docs/?format=json"))
+        .await
+        .unwrap();
+    let listing: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let urls: Vec<&str> = listing["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(urls, vec!["/files/docs/readme.txt"]);
+}
+
+#[tokio::test]
+async fn uploads_under_the_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("docs")).unwrap();
+
+    let mut config = upload_config(
+        temp_dir.path(),
+        UploadConfig {
+            prepend_timestamp: false,
+            ..Default::default()
+        },
+    );
+    config.server.base_path = Some("/files/".to_string());
+
+    let body = multipart_body(BOUNDARY, "upload.txt", b"uploaded");
+    let response = app(config)
+        .oneshot(multipart_request("/files/docs/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("docs/upload.txt")).unwrap(),
+        "uploaded"
+    );
+}
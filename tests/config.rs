@@ -7,7 +7,7 @@ use support::{BOUNDARY, app, base_config, multipart_body, multipart_request};
 use tempfile::TempDir;
 use tower::ServiceExt;
 
-use soop3::config::{Cli, ListingConfig, UploadConfig, load_configuration};
+use soop3::config::{Cli, ListingConfig, Permission, TokenScope, UploadConfig, load_configuration};
 use std::fs;
 
 #[test]
@@ -41,6 +41,11 @@ public_dir = "{}"
         verbose: 0,
         quiet: 0,
         cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
     };
 
     let config = load_configuration(&cli).unwrap();
@@ -126,6 +131,7 @@ async fn listing_uses_custom_ignore_file() {
     let mut config = base_config(public_dir);
     config.listing = ListingConfig {
         ignore_file: Some("custom.ignore".into()),
+        ..Default::default()
     };
 
     let app = app(config);
@@ -169,8 +175,643 @@ policy = "authenticate_all"
         verbose: 0,
         quiet: 0,
         cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
     };
 
     let result = load_configuration(&cli);
     assert!(result.is_err());
 }
+
+#[test]
+fn validation_rejects_invalid_ignore_patterns() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path().join("public");
+    fs::create_dir_all(&public_dir).unwrap();
+    fs::write(public_dir.join(".gitignore"), "*.log\nfile[z-a].txt\n").unwrap();
+
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+[server]
+public_dir = "{}"
+
+[listing]
+ignore_file = ".gitignore"
+"#,
+            public_dir.display()
+        ),
+    )
+    .unwrap();
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    let err = load_configuration(&cli).unwrap_err();
+    assert!(format!("{err:#}").contains("invalid ignore file"));
+}
+
+#[test]
+fn validation_rejects_invalid_nested_ignore_files_when_enforced() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path().join("public");
+    fs::create_dir_all(public_dir.join("sub")).unwrap();
+    fs::write(public_dir.join(".gitignore"), "*.pem\n").unwrap();
+    fs::write(public_dir.join("sub/.gitignore"), "file[z-a]\n").unwrap();
+
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |enforce: bool| {
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+[server]
+public_dir = "{}"
+
+[listing]
+ignore_file = ".gitignore"
+enforce_ignore = {enforce}
+"#,
+                public_dir.display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    // nested files are only read lazily for listings
+    write_config(false);
+    assert!(load_configuration(&cli).is_ok());
+
+    write_config(true);
+    let err = load_configuration(&cli).unwrap_err();
+    assert!(format!("{err:#}").contains("sub/.gitignore"), "{err:#}");
+}
+
+#[test]
+fn tls_flags_enable_https_and_require_a_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+
+    let mut cli = Cli {
+        public_dir: Some(temp_dir.path().to_path_buf()),
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: None,
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: Some(fixtures.join("first.crt")),
+        tls_key: Some(fixtures.join("first.key")),
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    let config = load_configuration(&cli).unwrap();
+    assert!(config.tls.is_enabled());
+    assert_eq!(config.tls.cert, Some(fixtures.join("first.crt")));
+
+    cli.tls_key = None;
+    assert!(load_configuration(&cli).is_err());
+}
+
+#[test]
+fn listen_addresses_load_from_file_and_cli() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+[server]
+public_dir = "{}"
+listen = ["127.0.0.1:9000", "[::1]:9000"]
+"#,
+            temp_dir.path().display()
+        ),
+    )
+    .unwrap();
+
+    let mut cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.server.listen, vec!["127.0.0.1:9000", "[::1]:9000"]);
+
+    cli.listen = vec!["localhost:9001".to_string()];
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.server.listen, vec!["localhost:9001"]);
+
+    cli.listen = vec!["::1:9001".to_string()];
+    assert!(load_configuration(&cli).is_err());
+}
+
+#[test]
+fn trusted_proxies_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |server: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\n{server}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config("trusted_proxies = [\"10.0.0.0/8\", \"::1\"]\nproxy_protocol = true");
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.server.trusted_proxies, vec!["10.0.0.0/8", "::1"]);
+    assert!(config.server.proxy_protocol);
+
+    write_config("trusted_proxies = [\"10.0.0.0/40\"]");
+    let err = load_configuration(&cli).unwrap_err();
+    assert!(format!("{err:#}").contains("trusted_proxies"), "{err:#}");
+
+    write_config("proxy_protocol = true");
+    assert!(load_configuration(&cli).is_err());
+}
+
+#[test]
+fn base_path_is_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |base_path: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\nbase_path = \"{base_path}\"\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config("/files/");
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.base_path(), "/files");
+
+    write_config("/");
+    assert_eq!(load_configuration(&cli).unwrap().base_path(), "");
+
+    for invalid in ["files", "/files/../etc", "//files", "/fi?les"] {
+        write_config(invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn mounts_load_from_file_and_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let builds_dir = temp_dir.path().join("builds");
+    fs::create_dir(&builds_dir).unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |mounts: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\n{mounts}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+    let mount = |path: &str, dir: &std::path::Path| {
+        format!(
+            "[[mount]]\npath = \"{path}\"\ndir = \"{}\"\nenable_upload = true\npolicy = \"authenticate_upload\"\n",
+            dir.display()
+        )
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config(&mount("/builds", &builds_dir));
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.mounts.len(), 1);
+    assert_eq!(config.mounts[0].path, "/builds");
+    assert_eq!(config.mounts[0].enable_upload, Some(true));
+    assert_eq!(
+        config.mounts[0].policy,
+        Some(soop3::config::SecurityPolicy::AuthenticateUpload)
+    );
+
+    for invalid in [
+        mount("/builds/nightly", &builds_dir),
+        mount("builds", &builds_dir),
+        mount("/__soop_static", &builds_dir),
+        mount("/builds", &temp_dir.path().join("missing")),
+        format!(
+            "{}{}",
+            mount("/builds", &builds_dir),
+            mount("/builds/", &builds_dir)
+        ),
+    ] {
+        write_config(&invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn users_load_from_file_and_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let htpasswd = temp_dir.path().join("htpasswd");
+    let hash = "$2y$04$EoghMZk4uSDfdUv29XJXwOzR81UMFmKlknhNb/j13bk4CkcNpnOIO";
+    fs::write(&htpasswd, format!("carol:{hash}\n")).unwrap();
+    let write_config = |security: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\n[security]\npolicy = \"authenticate_all\"\n{security}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    let user = |name: &str, hash: &str| {
+        format!("[[security.user]]\nusername = \"{name}\"\npassword_hash = \"{hash}\"\n")
+    };
+    let htpasswd_file = format!("htpasswd_file = \"{}\"\n", htpasswd.display());
+
+    write_config(&format!("{htpasswd_file}{}", user("alice", hash)));
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.security.users.len(), 1);
+    assert_eq!(config.security.users[0].username, "alice");
+    assert_eq!(
+        config.security.htpasswd_file.as_deref(),
+        Some(htpasswd.as_path())
+    );
+
+    for invalid in [
+        user("alice", "plaintext"),
+        user("a:b", hash),
+        format!("{}{}", user("alice", hash), user("alice", hash)),
+        format!("{htpasswd_file}{}", user("carol", hash)),
+        format!(
+            "htpasswd_file = \"{}\"\n",
+            temp_dir.path().join("missing").display()
+        ),
+    ] {
+        write_config(&invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn tokens_load_from_file_and_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |tokens: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\n{tokens}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+    let hash = format!("sha256:{}", "ab".repeat(32));
+    let token = |name: &str, hash: &str, extra: &str| {
+        format!("[[security.token]]\nname = \"{name}\"\ntoken_hash = \"{hash}\"\n{extra}\n")
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config(&token(
+        "ci",
+        &hash,
+        "scopes = [\"read\", \"upload\"]\npaths = [\"/builds/\"]\nexpires = \"2027-01-01T00:00:00Z\"",
+    ));
+    let config = load_configuration(&cli).unwrap();
+    let ci = &config.security.tokens[0];
+    assert_eq!(ci.scopes, [TokenScope::Read, TokenScope::Upload]);
+    assert_eq!(ci.paths, ["/builds/"]);
+    assert_eq!(
+        ci.expires.unwrap().to_rfc3339(),
+        "2027-01-01T00:00:00+00:00"
+    );
+
+    for invalid in [
+        token("ci", "plaintext", "scopes = [\"read\"]"),
+        token("ci", &hash, ""),
+        token("ci", &hash, "scopes = [\"write\"]"),
+        token("ci", &hash, "scopes = [\"read\"]\npaths = [\"builds\"]"),
+        token("ci", &hash, "scopes = [\"read\"]\nexpires = \"next year\""),
+        format!(
+            "{}{}",
+            token("ci", &hash, "scopes = [\"read\"]"),
+            token("ci", &hash, "scopes = [\"upload\"]")
+        ),
+    ] {
+        write_config(&invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn share_secret_must_be_long_enough() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |secret: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\n[security]\nshare_secret = \"{secret}\"\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config(&"s".repeat(32));
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.security.share_secret.unwrap().len(), 32);
+
+    write_config("too short");
+    let err = load_configuration(&cli).unwrap_err();
+    assert!(format!("{err:#}").contains("share_secret"), "{err:#}");
+}
+
+#[test]
+fn access_rules_load_from_file_and_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |rules: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                r#"[server]
+public_dir = "{}"
+[security]
+username = "ci"
+password = "secret"
+[security.groups]
+dev = ["ci"]
+{rules}
+"#,
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config(
+        r#"[[access_rule]]
+path = "/public/**"
+methods = ["GET", "HEAD"]
+permissions = ["read", "list"]
+[[access_rule]]
+path = "/team/**"
+groups = ["dev"]
+permissions = ["read", "list", "write"]"#,
+    );
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.access_rules.len(), 2);
+    assert_eq!(config.access_rules[0].methods, ["GET", "HEAD"]);
+    assert_eq!(
+        config.access_rules[1].permissions,
+        [Permission::Read, Permission::List, Permission::Write]
+    );
+
+    for invalid in [
+        "[[access_rule]]\npath = \"team/**\"",
+        "[[access_rule]]\npath = \"/team\"\nusers = [\"mallory\"]",
+        "[[access_rule]]\npath = \"/team\"\ngroups = [\"ops\"]",
+        "[[access_rule]]\npath = \"/team\"\nmethods = [\"NOT A METHOD\"]",
+        "[[access_rule]]\npath = \"/team\"\npermissions = [\"delete\"]",
+    ] {
+        write_config(invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn vhosts_load_from_file_and_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let docs_dir = temp_dir.path().join("docs");
+    fs::create_dir(&docs_dir).unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let write_config = |vhosts: &str| {
+        fs::write(
+            &config_path,
+            format!(
+                "[server]\npublic_dir = \"{}\"\nport = 9000\n{vhosts}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+    };
+    let vhost = |names: &str, dir: &std::path::Path| {
+        format!(
+            "[[vhost]]\nnames = [{names}]\ndefault = {}\n[vhost.server]\npublic_dir = \"{}\"\nenable_upload = true\n[vhost.security]\npolicy = \"authenticate_upload\"\nusername = \"admin\"\npassword = \"secret\"\n",
+            names.is_empty(),
+            dir.display()
+        )
+    };
+
+    let cli = Cli {
+        public_dir: None,
+        enable_upload: false,
+        host: None,
+        port: None,
+        config_file: Some(config_path.clone()),
+        verbose: 0,
+        quiet: 0,
+        cors: vec![],
+        tls_cert: None,
+        tls_key: None,
+        tls_self_signed: false,
+        listen: vec![],
+        command: None,
+    };
+
+    write_config(&vhost("\"docs.local\", \"*.docs.local\"", &docs_dir));
+    let config = load_configuration(&cli).unwrap();
+    assert_eq!(config.vhosts.len(), 1);
+    assert_eq!(config.vhosts[0].names, vec!["docs.local", "*.docs.local"]);
+    assert!(config.vhosts[0].server.enable_upload);
+    // listener settings always come from the top level
+    let site = config.vhost_config(&config.vhosts[0]);
+    assert_eq!(site.security.username.as_deref(), Some("admin"));
+    assert_eq!(site.server.port, 9000);
+    assert_eq!(site.server.public_dir, docs_dir);
+
+    write_config(&vhost("", &docs_dir));
+    assert!(load_configuration(&cli).unwrap().vhosts[0].default);
+
+    for invalid in [
+        format!("{}{}", vhost("", &docs_dir), vhost("", &docs_dir)),
+        vhost("\"docs.*.local\"", &docs_dir),
+        vhost("\"docs.local\"", &temp_dir.path().join("missing")),
+        "[[vhost]]\nnames = [\"docs.local\"]\n".to_string(),
+        "[[vhost]]\nnames = [\"docs.local\"]\n[vhost.server]\nenable_upload = true\n".to_string(),
+        format!(
+            "{}{}",
+            vhost("\"docs.local\"", &docs_dir),
+            vhost("\"DOCS.local\"", &docs_dir)
+        ),
+    ] {
+        write_config(&invalid);
+        assert!(load_configuration(&cli).is_err(), "{invalid}");
+    }
+}
@@ -2,14 +2,26 @@
 
 mod support;
 
+use axum::body::Body;
+use axum::extract::ConnectInfo;
 use axum::http::{Method, StatusCode, header};
 use base64::Engine;
-use soop3::config::{SecurityConfig, SecurityPolicy, UploadConfig};
-use support::{BOUNDARY, app, auth_header, base_config, get, multipart_body, multipart_request};
+use soop3::config::{
+    AppConfig, RateLimitConfig, SecurityConfig, SecurityPolicy, TokenConfig, UploadConfig,
+    UserConfig,
+};
+use soop3::server::proxy::PeerAddr;
+use support::{
+    BOUNDARY, app, auth_header, base_config, get, get_with_headers, multipart_body,
+    multipart_request,
+};
 use tempfile::TempDir;
 use tower::ServiceExt;
 
 use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
 
 #[tokio::test]
 async fn basic_authentication_requires_credentials() {
@@ -23,6 +35,7 @@ async fn basic_authentication_requires_credentials() {
         username: Some("admin".to_string()),
         password: Some("secret".to_string()),
         policy: SecurityPolicy::AuthenticateAll,
+        ..Default::default()
     };
 
     let app = app(config);
@@ -61,6 +74,366 @@ async fn basic_authentication_requires_credentials() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
 
+fn hash_with_cli(password: &str, args: &[&str]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_soop3"))
+        .arg("hash-password")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(format!("{password}\n").as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[tokio::test]
+async fn hashed_users_authenticate() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path().join("public");
+    fs::create_dir(&public_dir).unwrap();
+    fs::write(public_dir.join("test.txt"), "content").unwrap();
+
+    let alice_hash = hash_with_cli("alice-pass", &[]);
+    assert!(alice_hash.starts_with("$argon2id$"), "{alice_hash}");
+    let htpasswd_line = hash_with_cli(
+        "bob-pass",
+        &["--algorithm", "sha512-crypt", "--username", "bob"],
+    );
+    assert!(htpasswd_line.starts_with("bob:$6$"), "{htpasswd_line}");
+    let htpasswd = temp_dir.path().join("htpasswd");
+    fs::write(&htpasswd, format!("{htpasswd_line}\n")).unwrap();
+
+    let mut config = base_config(&public_dir);
+    config.security = SecurityConfig {
+        policy: SecurityPolicy::AuthenticateAll,
+        users: vec![UserConfig {
+            username: "alice".to_string(),
+            password_hash: alice_hash,
+        }],
+        htpasswd_file: Some(htpasswd),
+        ..Default::default()
+    };
+    let app = app(config);
+
+    for (username, password, status) in [
+        ("alice", "alice-pass", StatusCode::OK),
+        ("bob", "bob-pass", StatusCode::OK),
+        ("alice", "bob-pass", StatusCode::UNAUTHORIZED),
+        ("carol", "alice-pass", StatusCode::UNAUTHORIZED),
+    ] {
+        let authorization = format!("Basic {}", auth_header(username, password));
+        let response = app
+            .clone()
+            .oneshot(get_with_headers(
+                "/test.txt",
+                &[(header::AUTHORIZATION, authorization.as_str())],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{username}:{password}");
+    }
+}
+
+// run `soop3 token create` and split its output into the token and its entry
+fn create_token_with_cli(args: &[&str]) -> (String, TokenConfig) {
+    let output = Command::new(env!("CARGO_BIN_EXE_soop3"))
+        .args(["token", "create"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (token, snippet) = stdout.split_once('\n').unwrap();
+    let snippet: toml::Table = toml::from_str(snippet).unwrap();
+    let entry = snippet["security"]["token"][0].clone().try_into().unwrap();
+    (token.to_string(), entry)
+}
+
+#[tokio::test]
+async fn bearer_tokens_authenticate_within_their_scopes() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+    fs::create_dir(public_dir.join("builds")).unwrap();
+    fs::write(public_dir.join("builds/a.tar"), "build").unwrap();
+    fs::write(public_dir.join("readme.txt"), "readme").unwrap();
+
+    let (ci_token, ci) = create_token_with_cli(&[
+        "ci", "--scope", "read", "--scope", "upload", "--path", "/builds/",
+    ]);
+    assert!(ci_token.starts_with("soop_"), "{ci_token}");
+    assert_eq!(ci.paths, ["/builds/"]);
+    let (old_token, mut old) = create_token_with_cli(&["old", "--scope", "read"]);
+    old.expires = Some(chrono::Utc::now() - chrono::Duration::days(1));
+
+    let mut config = base_config(public_dir);
+    config.server.enable_upload = true;
+    config.upload.prepend_timestamp = false;
+    config.security = SecurityConfig {
+        policy: SecurityPolicy::AuthenticateAll,
+        tokens: vec![ci, old],
+        ..Default::default()
+    };
+    let app = app(config);
+
+    for (path, token, status) in [
+        ("/builds/a.tar", ci_token.as_str(), StatusCode::OK),
+        ("/builds/", ci_token.as_str(), StatusCode::OK),
+        ("/readme.txt", ci_token.as_str(), StatusCode::FORBIDDEN),
+        ("/readme.txt", old_token.as_str(), StatusCode::UNAUTHORIZED),
+        ("/readme.txt", "soop_unknown", StatusCode::UNAUTHORIZED),
+    ] {
+        let authorization = format!("Bearer {token}");
+        let response = app
+            .clone()
+            .oneshot(get_with_headers(
+                path,
+                &[(header::AUTHORIZATION, authorization.as_str())],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{path} with {token}");
+        if status == StatusCode::UNAUTHORIZED {
+            let challenge = response.headers()[header::WWW_AUTHENTICATE]
+                .to_str()
+                .unwrap();
+            assert!(challenge.starts_with("Bearer "), "{challenge}");
+        }
+    }
+
+    for (path, status) in [
+        ("/builds/", StatusCode::NO_CONTENT),
+        ("/", StatusCode::FORBIDDEN),
+    ] {
+        let mut request =
+            multipart_request(path, BOUNDARY, multipart_body(BOUNDARY, "b.tar", b"build"));
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {ci_token}").parse().unwrap(),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), status, "upload to {path}");
+    }
+    assert!(public_dir.join("builds/b.tar").exists());
+    assert!(!public_dir.join("b.tar").exists());
+}
+
+fn login_request(user: &str, password: &str, peer: Option<&str>) -> axum::http::Request<Body> {
+    let mut request = get_with_headers(
+        "/test.txt",
+        &[(
+            header::AUTHORIZATION,
+            format!("Basic {}", auth_header(user, password)).as_str(),
+        )],
+    );
+    if let Some(peer) = peer {
+        let peer: SocketAddr = peer.parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(PeerAddr::Tcp(peer)));
+    }
+    request
+}
+
+fn rate_limited_config(public_dir: &std::path::Path, rate_limit: RateLimitConfig) -> AppConfig {
+    fs::write(public_dir.join("test.txt"), "content").unwrap();
+    let mut config = base_config(public_dir);
+    config.security = SecurityConfig {
+        username: Some("admin".to_string()),
+        password: Some("secret".to_string()),
+        policy: SecurityPolicy::AuthenticateAll,
+        rate_limit,
+        ..Default::default()
+    };
+    config
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_out_the_user() {
+    let temp_dir = TempDir::new().unwrap();
+    let rate_limit = RateLimitConfig {
+        max_failures: 3,
+        lockout: 60,
+        ..Default::default()
+    };
+    let app = app(rate_limited_config(temp_dir.path(), rate_limit));
+
+    for _ in 0..3 {
+        let response = app
+            .clone()
+            .oneshot(login_request("admin", "wrong", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // even the right password waits out the lockout
+    let response = app
+        .clone()
+        .oneshot(login_request("admin", "secret", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after), "{retry_after}");
+
+    // requests without credentials are not part of the lockout
+    let response = app.clone().oneshot(get("/test.txt")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn concurrent_guesses_cannot_outrun_the_lockout() {
+    let temp_dir = TempDir::new().unwrap();
+    let rate_limit = RateLimitConfig {
+        max_failures: 3,
+        ..Default::default()
+    };
+    let app = app(rate_limited_config(temp_dir.path(), rate_limit));
+
+    let guesses: Vec<_> = (0..10)
+        .map(|_| {
+            let request = login_request("admin", "wrong", Some("192.0.2.1:4000"));
+            tokio::spawn(app.clone().oneshot(request))
+        })
+        .collect();
+    let mut statuses = Vec::new();
+    for guess in guesses {
+        statuses.push(guess.await.unwrap().unwrap().status());
+    }
+
+    let verified = statuses
+        .iter()
+        .filter(|status| **status == StatusCode::UNAUTHORIZED)
+        .count();
+    assert_eq!(verified, 3, "{statuses:?}");
+    assert!(
+        statuses.iter().all(
+            |status| [StatusCode::UNAUTHORIZED, StatusCode::TOO_MANY_REQUESTS].contains(status)
+        ),
+        "{statuses:?}"
+    );
+}
+
+#[tokio::test]
+async fn failures_are_counted_per_client_ip() {
+    let temp_dir = TempDir::new().unwrap();
+    let rate_limit = RateLimitConfig {
+        max_failures: 3,
+        ..Default::default()
+    };
+    let app = app(rate_limited_config(temp_dir.path(), rate_limit));
+
+    // guessing different user names from one address still adds up
+    for user in ["root", "guest", "test"] {
+        let response = app
+            .clone()
+            .oneshot(login_request(user, "secret", Some("192.0.2.1:4000")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    for (peer, status) in [
+        ("192.0.2.1:4001", StatusCode::TOO_MANY_REQUESTS),
+        ("192.0.2.2:4000", StatusCode::OK),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(login_request("admin", "secret", Some(peer)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{peer}");
+    }
+}
+
+#[tokio::test]
+async fn user_lockouts_follow_every_client_unless_turned_off() {
+    for (per_user, status) in [
+        (true, StatusCode::TOO_MANY_REQUESTS),
+        (false, StatusCode::OK),
+    ] {
+        let temp_dir = TempDir::new().unwrap();
+        let rate_limit = RateLimitConfig {
+            per_user,
+            max_failures: 2,
+            ..Default::default()
+        };
+        let app = app(rate_limited_config(temp_dir.path(), rate_limit));
+
+        for _ in 0..3 {
+            let response = app
+                .clone()
+                .oneshot(login_request("admin", "wrong", Some("192.0.2.1:4000")))
+                .await
+                .unwrap();
+            assert_ne!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(login_request("admin", "secret", Some("192.0.2.2:4000")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "per_user = {per_user}");
+    }
+}
+
+#[tokio::test]
+async fn successful_logins_reset_the_count_and_limits_can_be_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    let rate_limit = RateLimitConfig {
+        max_failures: 2,
+        ..Default::default()
+    };
+    let app = app(rate_limited_config(temp_dir.path(), rate_limit));
+
+    for (password, status) in [
+        ("wrong", StatusCode::UNAUTHORIZED),
+        ("secret", StatusCode::OK),
+        ("wrong", StatusCode::UNAUTHORIZED),
+        ("secret", StatusCode::OK),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(login_request("admin", password, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{password}");
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let rate_limit = RateLimitConfig {
+        enabled: false,
+        max_failures: 1,
+        ..Default::default()
+    };
+    let app = support::app(rate_limited_config(temp_dir.path(), rate_limit));
+    for _ in 0..3 {
+        let response = app
+            .clone()
+            .oneshot(login_request("admin", "wrong", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = app
+        .oneshot(login_request("admin", "secret", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
 #[tokio::test]
 async fn authentication_policies_match_methods() {
     let temp_dir = TempDir::new().unwrap();
@@ -86,6 +459,7 @@ async fn authentication_policies_match_methods() {
             username: Some("admin".to_string()),
             password: Some("secret".to_string()),
             policy,
+            ..Default::default()
         };
         config.upload = UploadConfig {
             prepend_timestamp: false,
@@ -134,6 +508,7 @@ async fn authentication_rejects_malformed_headers() {
         username: Some("admin".to_string()),
         password: Some("secret".to_string()),
         policy: SecurityPolicy::AuthenticateAll,
+        ..Default::default()
     };
 
     let app = app(config);
@@ -179,6 +554,7 @@ async fn authentication_timing_consistency() {
         username: Some("admin".to_string()),
         password: Some("verylongpasswordthatmightrevealtiminginformation".to_string()),
         policy: SecurityPolicy::AuthenticateAll,
+        ..Default::default()
     };
 
     let app = app(config);
@@ -365,6 +741,7 @@ async fn cors_headers_present_on_auth_failure() {
         username: Some("admin".to_string()),
         password: Some("secret".to_string()),
         policy: SecurityPolicy::AuthenticateAll,
+        ..Default::default()
     };
 
     let app = app(config);
@@ -411,7 +788,7 @@ async fn cors_headers_present_on_payload_too_large() {
     };
 
     let app = app(config);
-    let body = multipart_body(BOUNDARY, "large.txt", &vec![b'x'; 128]);
+    let body = multipart_body(BOUNDARY, "large.txt", &[b'x'; 128]);
     let response = app
         .oneshot(
             axum::http::Request::builder()
@@ -532,6 +909,7 @@ async fn options_preflight_not_blocked_with_upload_auth() {
         username: Some("admin".to_string()),
         password: Some("secret".to_string()),
         policy: SecurityPolicy::AuthenticateUpload,
+        ..Default::default()
     };
 
     let app = app(config);
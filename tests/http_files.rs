@@ -3,7 +3,11 @@
 mod support;
 
 use axum::http::{StatusCode, header};
-use support::{app, base_config, body_string, get, get_with_range, head, head_with_range};
+use soop3::config::UploadConfig;
+use support::{
+    BOUNDARY, app, base_config, body_string, get, get_with_headers, get_with_range, head,
+    head_with_range, multipart_body, multipart_request, upload_config,
+};
 use tempfile::TempDir;
 use tower::ServiceExt;
 
@@ -107,6 +111,109 @@ async fn range_suffix_requests_return_tail() {
     assert_eq!(body, "ef");
 }
 
+#[tokio::test]
+async fn multi_range_requests_return_byteranges() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdefghij").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app
+        .clone()
+        .oneshot(get_with_range("/test.txt", "bytes=0-1,6-7"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap()
+        .to_string();
+    let content_length: usize = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_RANGE));
+
+    let body = body_string(response).await;
+    assert_eq!(body.len(), content_length);
+    assert_eq!(
+        body,
+        format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\nab\
+             \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 6-7/10\r\n\r\ngh\
+             \r\n--{boundary}--\r\n"
+        )
+    );
+
+    let response = app
+        .oneshot(head_with_range("/test.txt", "bytes=0-1,6-7"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers().get(header::CONTENT_LENGTH).unwrap(),
+        &content_length.to_string()
+    );
+    assert!(body_string(response).await.is_empty());
+}
+
+#[tokio::test]
+async fn overlapping_ranges_are_coalesced() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdefghij").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app
+        .oneshot(get_with_range("/test.txt", "bytes=0-3,2-5,6-6"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers().get(header::CONTENT_RANGE).unwrap(),
+        "bytes 0-6/10"
+    );
+    let body = body_string(response).await;
+    assert_eq!(body, "abcdefg");
+}
+
+#[tokio::test]
+async fn too_many_ranges_return_the_whole_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "a".repeat(200)).unwrap();
+
+    let ranges: Vec<_> = (0..100).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+    let app = app(base_config(public_dir));
+    let response = app
+        .oneshot(get_with_range(
+            "/test.txt",
+            &format!("bytes={}", ranges.join(",")),
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_RANGE).is_none());
+    assert_eq!(body_string(response).await.len(), 200);
+}
+
 #[tokio::test]
 async fn invalid_range_returns_416() {
     let temp_dir = TempDir::new().unwrap();
@@ -127,6 +234,409 @@ async fn invalid_range_returns_416() {
     );
 }
 
+#[tokio::test]
+async fn file_responses_include_validators() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdef").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app.clone().oneshot(get("/test.txt")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers().get(header::ETAG).unwrap().clone();
+    let last_modified = response
+        .headers()
+        .get(header::LAST_MODIFIED)
+        .unwrap()
+        .clone();
+
+    let response = app
+        .clone()
+        .oneshot(get_with_range("/test.txt", "bytes=0-1"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+    assert_eq!(
+        response.headers().get(header::LAST_MODIFIED).unwrap(),
+        &last_modified
+    );
+}
+
+#[tokio::test]
+async fn conditional_get_returns_not_modified() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdef").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app.clone().oneshot(get("/test.txt")).await.unwrap();
+    let etag = response.headers().get(header::ETAG).unwrap().clone();
+    let etag = etag.to_str().unwrap();
+    let last_modified = response.headers().get(header::LAST_MODIFIED).unwrap();
+    let last_modified = last_modified.to_str().unwrap().to_string();
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[(header::IF_NONE_MATCH, etag)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), etag);
+    assert!(body_string(response).await.is_empty());
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[(header::IF_MODIFIED_SINCE, &last_modified)],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[
+                (header::IF_NONE_MATCH, "\"stale\""),
+                (header::IF_MODIFIED_SINCE, &last_modified),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "abcdef");
+}
+
+#[tokio::test]
+async fn failed_preconditions_return_412() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdef").unwrap();
+
+    let app = app(base_config(public_dir));
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[(header::IF_MATCH, "\"stale\"")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[(header::IF_UNMODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = app
+        .oneshot(get_with_headers("/test.txt", &[(header::IF_MATCH, "*")]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn if_range_mismatch_serves_full_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("test.txt"), "abcdef").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app.clone().oneshot(get("/test.txt")).await.unwrap();
+    let etag = response.headers().get(header::ETAG).unwrap().clone();
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[
+                (header::RANGE, "bytes=0-1"),
+                (header::IF_RANGE, etag.to_str().unwrap()),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body_string(response).await, "ab");
+
+    let response = app
+        .oneshot(get_with_headers(
+            "/test.txt",
+            &[
+                (header::RANGE, "bytes=0-1"),
+                (header::IF_RANGE, "\"stale\""),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "abcdef");
+}
+
+#[tokio::test]
+async fn compresses_text_responses_when_accepted() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    let text = "soop3 log line\n".repeat(512);
+    fs::write(public_dir.join("build.log"), &text).unwrap();
+
+    let mut config = base_config(public_dir);
+    config.compression.enabled = true;
+    let app = app(config);
+
+    for encoding in ["gzip", "br", "zstd"] {
+        let response = app
+            .clone()
+            .oneshot(get_with_headers(
+                "/build.log",
+                &[(header::ACCEPT_ENCODING, encoding)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            encoding
+        );
+        assert_eq!(
+            response.headers().get(header::VARY).unwrap(),
+            "accept-encoding"
+        );
+        let etag = response.headers().get(header::ETAG).unwrap();
+        assert!(etag.to_str().unwrap().starts_with("W/"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.len() < text.len());
+    }
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers("/", &[(header::ACCEPT_ENCODING, "gzip")]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.oneshot(get("/build.log")).await.unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(body_string(response).await, text);
+}
+
+#[tokio::test]
+async fn skips_compression_for_ranges_and_compressed_types() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    let text = "a".repeat(4096);
+    fs::write(public_dir.join("data.txt"), &text).unwrap();
+    fs::write(public_dir.join("archive.zip"), &text).unwrap();
+
+    let mut config = base_config(public_dir);
+    config.compression.enabled = true;
+    let app = app(config);
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/data.txt",
+            &[
+                (header::ACCEPT_ENCODING, "gzip"),
+                (header::RANGE, "bytes=0-2047"),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/data.txt",
+            &[
+                (header::ACCEPT_ENCODING, "gzip"),
+                (header::RANGE, "bytes=0-9,20-29"),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+    let response = app
+        .oneshot(get_with_headers(
+            "/archive.zip",
+            &[(header::ACCEPT_ENCODING, "gzip")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(
+        response.headers().get(header::CONTENT_LENGTH).unwrap(),
+        "4096"
+    );
+}
+
+#[tokio::test]
+async fn compression_respects_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("small.txt"), "a".repeat(512)).unwrap();
+    fs::write(public_dir.join("large.txt"), "a".repeat(4096)).unwrap();
+
+    let response = app(base_config(public_dir))
+        .oneshot(get_with_headers(
+            "/large.txt",
+            &[(header::ACCEPT_ENCODING, "gzip")],
+        ))
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(
+        response.headers().get(header::CONTENT_LENGTH).unwrap(),
+        "4096"
+    );
+
+    let mut config = base_config(public_dir);
+    config.compression.enabled = true;
+    let response = app(config)
+        .oneshot(get_with_headers(
+            "/small.txt",
+            &[(header::ACCEPT_ENCODING, "gzip")],
+        ))
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+}
+
+#[tokio::test]
+async fn serves_precompressed_siblings_when_enabled() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("app.js"), "console.log('plain');").unwrap();
+    fs::write(public_dir.join("app.js.br"), "brotli-bytes").unwrap();
+    fs::write(public_dir.join("app.js.gz"), "gzip-bytes").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.server.precompressed = true;
+    let app = app(config);
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/app.js",
+            &[(header::ACCEPT_ENCODING, "gzip, br")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_ENCODING).unwrap(),
+        "br"
+    );
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/javascript"
+    );
+    assert_eq!(
+        response.headers().get(header::VARY).unwrap(),
+        "accept-encoding"
+    );
+    assert!(
+        !response
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("W/")
+    );
+    assert_eq!(body_string(response).await, "brotli-bytes");
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/app.js",
+            &[(header::ACCEPT_ENCODING, "gzip, br;q=0")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(header::CONTENT_ENCODING).unwrap(),
+        "gzip"
+    );
+    assert_eq!(body_string(response).await, "gzip-bytes");
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/app.js",
+            &[
+                (header::ACCEPT_ENCODING, "br"),
+                (header::RANGE, "bytes=0-5"),
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers().get(header::CONTENT_ENCODING).unwrap(),
+        "br"
+    );
+    assert_eq!(
+        response.headers().get(header::CONTENT_RANGE).unwrap(),
+        "bytes 0-5/12"
+    );
+    assert_eq!(body_string(response).await, "brotli");
+
+    let response = app.oneshot(get("/app.js")).await.unwrap();
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(body_string(response).await, "console.log('plain');");
+}
+
+#[tokio::test]
+async fn precompressed_siblings_ignored_when_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("app.js"), "console.log('plain');").unwrap();
+    fs::write(public_dir.join("app.js.br"), "brotli-bytes").unwrap();
+
+    let app = app(base_config(public_dir));
+    let response = app
+        .oneshot(get_with_headers(
+            "/app.js",
+            &[(header::ACCEPT_ENCODING, "br")],
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(body_string(response).await, "console.log('plain');");
+}
+
 #[tokio::test]
 async fn returns_not_found_when_path_component_is_file() {
     let temp_dir = TempDir::new().unwrap();
@@ -283,6 +793,318 @@ async fn applies_ignore_file_in_nested_directories() {
     assert!(!body.contains("hidden.log"));
 }
 
+#[tokio::test]
+async fn listing_applies_gitignore_semantics_and_nested_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::create_dir_all(public_dir.join("project/build")).unwrap();
+    fs::write(public_dir.join("project/build/out.bin"), "bin").unwrap();
+    fs::write(public_dir.join("project/keep.log"), "keep").unwrap();
+    fs::write(public_dir.join("project/drop.log"), "drop").unwrap();
+    fs::write(public_dir.join("project/secret.txt"), "secret").unwrap();
+    fs::write(public_dir.join("secret.txt"), "root secret").unwrap();
+    fs::write(
+        public_dir.join(".gitignore"),
+        "# logs are noise\n*.log\n!keep.log\nbuild/\n",
+    )
+    .unwrap();
+    fs::write(public_dir.join("project/.gitignore"), "/secret.txt\n").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.listing.ignore_file = Some(".gitignore".into());
+    let app = app(config);
+
+    let response = app.clone().oneshot(get("/project/")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_string(response).await;
+    assert!(body.contains("keep.log"));
+    assert!(!body.contains("drop.log"));
+    assert!(!body.contains("build/"));
+    assert!(!body.contains("secret.txt"));
+
+    let response = app.oneshot(get("/")).await.unwrap();
+    let body = body_string(response).await;
+    assert!(body.contains("secret.txt"));
+    assert!(body.contains("project/"));
+}
+
+#[tokio::test]
+async fn enforced_ignore_rules_block_direct_access() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::create_dir_all(public_dir.join("secrets")).unwrap();
+    fs::write(public_dir.join("secrets/key.pem"), "private").unwrap();
+    fs::write(public_dir.join(".env"), "TOKEN=1").unwrap();
+    fs::write(public_dir.join("public.txt"), "public").unwrap();
+    fs::write(public_dir.join(".gitignore"), ".env\nsecrets/\n").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.listing.ignore_file = Some(".gitignore".into());
+
+    // listing-only filtering leaves direct access untouched
+    let response = app(config.clone()).oneshot(get("/.env")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    config.listing.enforce_ignore = true;
+    let app = app(config);
+
+    for path in ["/.env", "/secrets/key.pem", "/secrets/", "/secrets"] {
+        let response = app.clone().oneshot(get(path)).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::NOT_FOUND,
+            "{path} was served"
+        );
+    }
+
+    let response = app.clone().oneshot(head("/.env")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.oneshot(get("/public.txt")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn enforced_ignore_rules_fail_closed_and_hide_ignore_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::create_dir_all(public_dir.join("sub")).unwrap();
+    fs::write(public_dir.join("sub/key.pem"), "private").unwrap();
+    fs::write(public_dir.join(".gitignore"), "*.pem\n").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.listing.ignore_file = Some(".gitignore".into());
+    config.listing.enforce_ignore = true;
+    let app = app(config);
+
+    let response = app.clone().oneshot(get("/sub/key.pem")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // a nested ignore file that fails to compile hides everything it governs
+    fs::write(public_dir.join("sub/.gitignore"), "file[z-a]\n").unwrap();
+    for path in ["/sub/key.pem", "/sub/.gitignore", "/.gitignore"] {
+        let response = app.clone().oneshot(get(path)).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::NOT_FOUND,
+            "{path} was served"
+        );
+    }
+
+    let response = app.oneshot(get("/sub/")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_string(response).await;
+    assert!(!body.contains("key.pem"));
+    assert!(!body.contains(".gitignore"));
+}
+
+#[tokio::test]
+async fn enforced_ignore_rules_reject_uploads() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::create_dir_all(public_dir.join("secrets")).unwrap();
+    fs::write(public_dir.join(".gitignore"), "*.pem\nsecrets/\n").unwrap();
+
+    let mut config = upload_config(
+        public_dir,
+        UploadConfig {
+            prepend_timestamp: false,
+            prevent_overwrite: false,
+            ..Default::default()
+        },
+    );
+    config.listing.ignore_file = Some(".gitignore".into());
+    config.listing.enforce_ignore = true;
+    let app = app(config);
+
+    let body = multipart_body(BOUNDARY, "key.pem", b"private");
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!public_dir.join("key.pem").exists());
+
+    let body = multipart_body(BOUNDARY, "notes.txt", b"data");
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/secrets/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!public_dir.join("secrets/notes.txt").exists());
+
+    // ignore files could rewrite the rules, so they cannot be uploaded
+    let body = multipart_body(BOUNDARY, ".gitignore", b"!*.pem\n");
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/secrets/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!public_dir.join("secrets/.gitignore").exists());
+
+    let body = multipart_body(BOUNDARY, "notes.txt", b"data");
+    let response = app
+        .oneshot(multipart_request("/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn enforced_ignore_rules_match_uploads_before_the_timestamp() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join(".gitignore"), "secret*\n").unwrap();
+
+    let mut config = upload_config(public_dir, UploadConfig::default());
+    config.listing.ignore_file = Some(".gitignore".into());
+    config.listing.enforce_ignore = true;
+    let app = app(config);
+
+    let body = multipart_body(BOUNDARY, "secret.txt", b"private");
+    let response = app
+        .oneshot(multipart_request("/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(fs::read_dir(public_dir).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn serves_json_directory_listing() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::create_dir(public_dir.join("sub dir")).unwrap();
+    fs::write(public_dir.join("notes.txt"), "hello").unwrap();
+    fs::write(public_dir.join("debug.log"), "hidden").unwrap();
+    fs::write(public_dir.join(".gitignore"), "*.log\n").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.listing.ignore_file = Some(".gitignore".into());
+    let app = app(config);
+
+    for request in [
+        get("/?format=json"),
+        get_with_headers("/", &[(header::ACCEPT, "application/json")]),
+    ] {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body = body_string(response).await;
+        let listing: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(listing["path"], "/");
+
+        let entries = listing["entries"].as_array().unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["sub dir", ".gitignore", "notes.txt"]);
+
+        let dir = &entries[0];
+        assert_eq!(dir["is_dir"], true);
+        assert_eq!(dir["url"], "/sub%20dir/");
+        assert!(dir["mime_type"].is_null());
+
+        let file = &entries[2];
+        assert_eq!(file["is_dir"], false);
+        assert_eq!(file["size"], 5);
+        assert_eq!(file["mime_type"], "text/plain");
+        assert_eq!(file["url"], "/notes.txt");
+        assert!(file["mtime"].as_str().unwrap().ends_with('Z'));
+    }
+
+    let response = app
+        .clone()
+        .oneshot(get_with_headers(
+            "/",
+            &[(header::ACCEPT, "text/html,application/json;q=0.9")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+
+    let response = app.oneshot(get("/sub%20dir?format=json")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "/sub%20dir/?format=json"
+    );
+}
+
+#[tokio::test]
+async fn listing_supports_sort_and_filter_parameters() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path();
+
+    fs::write(public_dir.join("file10.txt"), "a").unwrap();
+    fs::write(public_dir.join("file2.txt"), "abc").unwrap();
+    fs::write(public_dir.join("file1.txt"), "ab").unwrap();
+    fs::write(public_dir.join("notes.md"), "abcd").unwrap();
+
+    let app = app(base_config(public_dir));
+
+    let names = |body: &str| -> Vec<String> {
+        let listing: serde_json::Value = serde_json::from_str(body).unwrap();
+        listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let response = app.clone().oneshot(get("/?format=json")).await.unwrap();
+    let body = body_string(response).await;
+    assert_eq!(
+        names(&body),
+        vec!["file1.txt", "file2.txt", "file10.txt", "notes.md"]
+    );
+
+    let response = app
+        .clone()
+        .oneshot(get("/?format=json&sort=size&order=desc"))
+        .await
+        .unwrap();
+    let body = body_string(response).await;
+    assert_eq!(
+        names(&body),
+        vec!["notes.md", "file2.txt", "file1.txt", "file10.txt"]
+    );
+
+    let response = app
+        .clone()
+        .oneshot(get("/?format=json&filter=file1*"))
+        .await
+        .unwrap();
+    let body = body_string(response).await;
+    assert_eq!(names(&body), vec!["file1.txt", "file10.txt"]);
+
+    let response = app.oneshot(get("/?sort=name&filter=*.txt")).await.unwrap();
+    let body = body_string(response).await;
+    assert!(body.contains(
+        "<th class=\"sorted asc\"><a href=\"?sort=name&amp;order=desc&amp;filter=%2A%2Etxt\">name</a></th>"
+    ));
+    assert!(body.contains("<a href=\"?sort=size&amp;order=asc&amp;filter=%2A%2Etxt\">size</a>"));
+    assert!(!body.contains("notes.md"));
+}
+
 #[tokio::test]
 async fn rejects_path_traversal_attempts() {
     let temp_dir = TempDir::new().unwrap();
@@ -7,6 +7,7 @@ use support::{BOUNDARY, app, base_config, multipart_body, multipart_request, upl
 use tempfile::TempDir;
 use tower::ServiceExt;
 
+use futures_util::StreamExt;
 use std::fs;
 
 #[tokio::test]
@@ -432,3 +433,45 @@ async fn upload_rejects_long_filenames() {
 
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
+
+#[tokio::test]
+async fn abandoned_upload_leaves_no_partial_files() {
+    for prevent_overwrite in [false, true] {
+        let temp_dir = TempDir::new().unwrap();
+        let public_dir = temp_dir.path();
+
+        let config = upload_config(
+            public_dir,
+            soop3::config::UploadConfig {
+                prepend_timestamp: false,
+                prevent_overwrite,
+                ..Default::default()
+            },
+        );
+
+        // send the start of the file, then stall as a disconnecting client would
+        let head = multipart_body(BOUNDARY, "partial.bin", b"first chunk");
+        let head = head[..head.len() - BOUNDARY.len() - 8].to_vec();
+        let stream = futures_util::stream::iter([Ok::<_, std::io::Error>(head)])
+            .chain(futures_util::stream::pending());
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri("/")
+            .header(
+                axum::http::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(axum::body::Body::from_stream(stream))
+            .unwrap();
+
+        let upload = app(config).oneshot(request);
+        let result = tokio::time::timeout(std::time::Duration::from_millis(200), upload).await;
+        assert!(result.is_err(), "stalled upload should not complete");
+
+        let leftovers: Vec<_> = fs::read_dir(public_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(leftovers.is_empty(), "left behind {leftovers:?}");
+    }
+}
@@ -0,0 +1,213 @@
+// directories mounted under their own url paths
+
+mod support;
+
+use axum::http::{StatusCode, header};
+use soop3::config::{AppConfig, MountConfig, SecurityPolicy};
+use std::fs;
+use std::path::Path;
+use support::{
+    BOUNDARY, app, auth_header, base_config, body_string, get, get_with_headers, multipart_body,
+    multipart_request,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn mount(path: &str, dir: &Path) -> MountConfig {
+    MountConfig {
+        path: path.to_string(),
+        dir: dir.to_path_buf(),
+        enable_upload: None,
+        ignore_file: None,
+        policy: None,
+    }
+}
+
+fn mounted(root: &Path, builds: &Path) -> AppConfig {
+    let mut config = base_config(root);
+    config.mounts = vec![mount("/builds", builds)];
+    config
+}
+
+#[tokio::test]
+async fn serves_files_from_the_mount_serving_the_path() {
+    let root = TempDir::new().unwrap();
+    let builds = TempDir::new().unwrap();
+    fs::write(root.path().join("root.txt"), "root").unwrap();
+    fs::write(builds.path().join("app.tar"), "build").unwrap();
+    fs::write(root.path().join("buildsx"), "not a mount").unwrap();
+
+    let config = mounted(root.path(), builds.path());
+    for (path, body) in [
+        ("/root.txt", "root"),
+        ("/builds/app.tar", "build"),
+        ("/buildsx", "not a mount"),
+    ] {
+        let response = app(config.clone()).oneshot(get(path)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+        assert_eq!(body_string(response).await, body);
+    }
+
+    let response = app(config.clone())
+        .oneshot(get("/builds/root.txt"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app(config.clone())
+        .oneshot(get("/builds/../root.txt"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app(config).oneshot(get("/builds")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "/builds/"
+    );
+}
+
+#[tokio::test]
+async fn root_listing_shows_mounts_as_directories() {
+    let root = TempDir::new().unwrap();
+    let builds = TempDir::new().unwrap();
+    fs::write(root.path().join("root.txt"), "root").unwrap();
+    // a real entry with the mount's name is shadowed by the mount
+    fs::write(root.path().join("builds"), "shadowed").unwrap();
+
+    let response = app(mounted(root.path(), builds.path()))
+        .oneshot(get("/?format=json"))
+        .await
+        .unwrap();
+    let listing: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let entries: Vec<(&str, bool, &str)> = listing["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["name"].as_str().unwrap(),
+                entry["is_dir"].as_bool().unwrap(),
+                entry["url"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("builds", true, "/builds/"),
+            ("root.txt", false, "/root.txt")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn uploads_follow_each_mounts_setting() {
+    let root = TempDir::new().unwrap();
+    let builds = TempDir::new().unwrap();
+
+    let mut config = base_config(root.path());
+    config.upload.prepend_timestamp = false;
+    config.mounts = vec![MountConfig {
+        enable_upload: Some(true),
+        ..mount("/builds", builds.path())
+    }];
+
+    let body = multipart_body(BOUNDARY, "app.tar", b"build");
+    let response = app(config.clone())
+        .oneshot(multipart_request("/builds/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        fs::read_to_string(builds.path().join("app.tar")).unwrap(),
+        "build"
+    );
+
+    let body = multipart_body(BOUNDARY, "app.tar", b"build");
+    let response = app(config)
+        .oneshot(multipart_request("/", BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!root.path().join("app.tar").exists());
+}
+
+#[tokio::test]
+async fn mounts_apply_their_own_policy_and_ignore_file() {
+    let root = TempDir::new().unwrap();
+    let datasets = TempDir::new().unwrap();
+    fs::write(root.path().join("public.txt"), "public").unwrap();
+    fs::write(datasets.path().join("data.csv"), "a,b").unwrap();
+    fs::write(datasets.path().join("secret.key"), "key").unwrap();
+    fs::write(datasets.path().join(".ignore"), "*.key\n").unwrap();
+
+    let mut config = base_config(root.path());
+    config.security.username = Some("admin".to_string());
+    config.security.password = Some("secret".to_string());
+    config.listing.enforce_ignore = true;
+    config.mounts = vec![MountConfig {
+        policy: Some(SecurityPolicy::AuthenticateAll),
+        ignore_file: Some(".ignore".into()),
+        ..mount("/datasets", datasets.path())
+    }];
+
+    let response = app(config.clone())
+        .oneshot(get("/public.txt"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app(config.clone())
+        .oneshot(get("/datasets/data.csv"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let authorization = format!("Basic {}", auth_header("admin", "secret"));
+    let authorized = [(header::AUTHORIZATION, authorization.as_str())];
+    let response = app(config.clone())
+        .oneshot(get_with_headers("/datasets/data.csv", &authorized))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app(config)
+        .oneshot(get_with_headers("/datasets/secret.key", &authorized))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn encoded_paths_resolve_to_the_same_mount() {
+    let root = TempDir::new().unwrap();
+    let builds = TempDir::new().unwrap();
+    fs::write(builds.path().join("app 1.tar"), "build").unwrap();
+
+    let mut config = base_config(root.path());
+    config.security.username = Some("admin".to_string());
+    config.security.password = Some("secret".to_string());
+    config.mounts = vec![MountConfig {
+        policy: Some(SecurityPolicy::AuthenticateAll),
+        ..mount("/builds", builds.path())
+    }];
+
+    // an encoded mount prefix must not slip past the mount's policy
+    for path in ["/%62uilds/app%201.tar", "//builds/./app%201.tar"] {
+        let response = app(config.clone()).oneshot(get(path)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+    }
+
+    let authorization = format!("Basic {}", auth_header("admin", "secret"));
+    let response = app(config)
+        .oneshot(get_with_headers(
+            "/%62uilds/app%201.tar",
+            &[(header::AUTHORIZATION, authorization.as_str())],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "build");
+}
@@ -0,0 +1,174 @@
+// reverse proxy headers and proxy protocol listeners
+
+mod support;
+
+use axum::{
+    Router,
+    extract::ConnectInfo,
+    http::{StatusCode, header},
+    routing::get,
+};
+use soop3::server::proxy::{PeerAddr, ProxyProtocolListener};
+use soop3::server::shutdown::serve_until_shutdown;
+use soop3::utils::cidr::TrustedProxies;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use support::{app, base_config, get_with_headers};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+fn proxied_from(
+    peer: &str,
+    headers: &[(header::HeaderName, &str)],
+) -> axum::http::Request<axum::body::Body> {
+    let mut request = get_with_headers("/docs", headers);
+    let peer: SocketAddr = peer.parse().unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(PeerAddr::Tcp(peer)));
+    request
+}
+
+fn forwarding_headers() -> Vec<(header::HeaderName, &'static str)> {
+    vec![
+        (
+            header::HeaderName::from_static("x-forwarded-proto"),
+            "https",
+        ),
+        (
+            header::HeaderName::from_static("x-forwarded-host"),
+            "files.example.com",
+        ),
+        (
+            header::HeaderName::from_static("x-forwarded-prefix"),
+            "/files",
+        ),
+    ]
+}
+
+#[tokio::test]
+async fn directory_redirect_keeps_forwarded_prefix_and_scheme() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("docs")).unwrap();
+
+    let mut config = base_config(temp_dir.path());
+    config.server.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+
+    let response = app(config)
+        .oneshot(proxied_from("10.0.0.1:40000", &forwarding_headers()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://files.example.com/files/docs/"
+    );
+}
+
+#[tokio::test]
+async fn directory_redirect_ignores_client_supplied_forwarded_host() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("docs")).unwrap();
+
+    let mut config = base_config(temp_dir.path());
+    config.server.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+
+    let response = app(config)
+        .oneshot(proxied_from(
+            "10.0.0.1:40000",
+            &[(
+                header::FORWARDED,
+                "host=evil;proto=https, for=203.0.113.9;proto=https;host=files.example.com",
+            )],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://files.example.com/docs/"
+    );
+}
+
+#[tokio::test]
+async fn ignores_forwarding_headers_from_untrusted_peers() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("docs")).unwrap();
+
+    let mut config = base_config(temp_dir.path());
+    config.server.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+
+    let response = app(config)
+        .oneshot(proxied_from("192.0.2.1:40000", &forwarding_headers()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/docs/");
+}
+
+async fn spawn_proxy_protocol_server(trusted: &str) -> SocketAddr {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let trusted = Arc::new(TrustedProxies::new(&[trusted.to_string()]).unwrap());
+    let listener = ProxyProtocolListener::new(tcp, trusted).unwrap();
+    let addr = axum::serve::Listener::local_addr(&listener).unwrap();
+
+    let app = Router::new().route(
+        "/",
+        get(|ConnectInfo(peer): ConnectInfo<PeerAddr>| async move { format!("{peer:?}") }),
+    );
+    tokio::spawn(serve_until_shutdown(
+        listener,
+        app,
+        CancellationToken::new(),
+        Duration::from_secs(1),
+    ));
+    addr
+}
+
+#[tokio::test]
+async fn proxy_protocol_reports_client_address() {
+    let addr = spawn_proxy_protocol_server("127.0.0.1").await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"PROXY TCP4 203.0.113.9 127.0.0.1 51000 80\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("Tcp(203.0.113.9:51000)"), "{response}");
+}
+
+#[tokio::test]
+async fn proxy_protocol_closes_untrusted_and_headerless_connections() {
+    let addr = spawn_proxy_protocol_server("10.0.0.0/8").await;
+
+    // the test client is not a trusted proxy; its connection may be reset
+    // rather than closed, either way nothing is served
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let _ = stream
+        .write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 51000 80\r\n")
+        .await;
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(response.is_empty());
+
+    let addr = spawn_proxy_protocol_server("127.0.0.1").await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(response.is_empty());
+}
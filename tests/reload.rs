@@ -0,0 +1,106 @@
+// configuration reloading in a running server
+#![cfg(unix)]
+
+mod support;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use support::auth_header;
+use tempfile::TempDir;
+
+fn write_config(config_path: &Path, public_dir: &Path, socket_path: &Path, password: &str) {
+    fs::write(
+        config_path,
+        format!(
+            "[server]\npublic_dir = \"{}\"\nlisten = [\"unix:{}\"]\n\n\
+             [security]\npolicy = \"authenticate_all\"\nusername = \"admin\"\npassword = \"{password}\"\n",
+            public_dir.display(),
+            socket_path.display()
+        ),
+    )
+    .unwrap();
+}
+
+fn status_with_password(socket_path: &Path, password: &str) -> u16 {
+    let mut stream = UnixStream::connect(socket_path).unwrap();
+    write!(
+        stream,
+        "GET /hello.txt HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\nConnection: close\r\n\r\n",
+        auth_header("admin", password)
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response[9..12].parse().unwrap()
+}
+
+fn signal(child: &Child, signal: &str) {
+    let status = Command::new("kill")
+        .args([signal, &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+// poll until `check` holds, as reloads happen in the background
+fn wait_for(mut check: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn reloads_on_sighup_and_keeps_config_when_invalid() {
+    let temp_dir = TempDir::new().unwrap();
+    let public_dir = temp_dir.path().join("public");
+    fs::create_dir(&public_dir).unwrap();
+    fs::write(public_dir.join("hello.txt"), "hello").unwrap();
+    let socket_path = temp_dir.path().join("soop3.sock");
+    let config_path = temp_dir.path().join("config.toml");
+    write_config(&config_path, &public_dir, &socket_path, "first");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_soop3"))
+        .arg("--config-file")
+        .arg(&config_path)
+        .arg("--quiet")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    assert!(wait_for(|| socket_path.exists()));
+    assert_eq!(status_with_password(&socket_path, "first"), 200);
+    assert_eq!(status_with_password(&socket_path, "second"), 401);
+
+    write_config(&config_path, &public_dir, &socket_path, "second");
+    signal(&child, "-HUP");
+    assert!(wait_for(
+        || status_with_password(&socket_path, "second") == 200
+    ));
+    assert_eq!(status_with_password(&socket_path, "first"), 401);
+
+    // a config that fails validation leaves the running one in place
+    write_config(
+        &config_path,
+        &temp_dir.path().join("missing"),
+        &socket_path,
+        "third",
+    );
+    signal(&child, "-HUP");
+    sleep(Duration::from_millis(500));
+    assert_eq!(status_with_password(&socket_path, "second"), 200);
+    assert_eq!(status_with_password(&socket_path, "third"), 401);
+
+    signal(&child, "-TERM");
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,260 @@
+// signed, expiring share links
+
+mod support;
+
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use soop3::config::{AccessRuleConfig, AppConfig, SecurityPolicy, TokenConfig, TokenScope};
+use soop3::utils::share::ShareLinks;
+use soop3::utils::tokens::hash_token;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use support::{
+    BOUNDARY, app, auth_header, base_config, body_string, get, multipart_body, multipart_request,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+fn share_config(public_dir: &Path) -> AppConfig {
+    fs::create_dir(public_dir.join("docs")).unwrap();
+    fs::create_dir(public_dir.join("drop")).unwrap();
+    fs::create_dir(public_dir.join("private")).unwrap();
+    fs::write(public_dir.join("docs/a.txt"), "shared").unwrap();
+    fs::write(public_dir.join("docs/b.txt"), "not shared").unwrap();
+
+    let mut config = base_config(public_dir);
+    config.server.enable_upload = true;
+    config.upload.prepend_timestamp = false;
+    config.security.policy = SecurityPolicy::AuthenticateAll;
+    config.security.username = Some("alice".to_string());
+    config.security.password = Some("secret".to_string());
+    config.security.share_secret = Some(SECRET.to_string());
+    config.access_rules = vec![AccessRuleConfig {
+        path: "/private/**".to_string(),
+        methods: Vec::new(),
+        users: Vec::new(),
+        groups: Vec::new(),
+        permissions: Vec::new(),
+    }];
+    config
+}
+
+fn mint(query: &str, login: bool) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/__soop_share?{query}"));
+    if login {
+        builder = builder.header(
+            header::AUTHORIZATION,
+            format!("Basic {}", auth_header("alice", "secret")),
+        );
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+async fn mint_url(app: &axum::Router, query: &str) -> String {
+    let response = app.clone().oneshot(mint(query, true)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{query}");
+    let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert!(body["expires"].as_str().unwrap().ends_with('Z'));
+    body["url"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn signed_links_replace_credentials_for_their_path() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(share_config(temp_dir.path()));
+
+    let url = mint_url(&app, "path=/docs/a.txt&ttl=1h").await;
+    assert!(url.starts_with("/docs/a.txt?expires="), "{url}");
+    let query = url.split_once('?').unwrap().1;
+
+    let response = app.clone().oneshot(get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "shared");
+
+    let expired = ShareLinks::new(Some(SECRET), "")
+        .sign("/docs/a.txt", false, &Method::GET, None, 1)
+        .unwrap();
+    for (uri, status) in [
+        ("/docs/a.txt".to_string(), StatusCode::UNAUTHORIZED),
+        (format!("/docs/b.txt?{query}"), StatusCode::FORBIDDEN),
+        (url.replace("sig=", "sig=x"), StatusCode::FORBIDDEN),
+        (expired, StatusCode::FORBIDDEN),
+    ] {
+        let response = app.clone().oneshot(get(&uri)).await.unwrap();
+        assert_eq!(response.status(), status, "{uri}");
+    }
+
+    let body = multipart_body(BOUNDARY, "a.txt", b"overwrite");
+    let response = app
+        .clone()
+        .oneshot(multipart_request(&url, BOUNDARY, body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn minting_needs_a_user_allowed_on_the_path() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(share_config(temp_dir.path()));
+
+    for (query, login, status) in [
+        ("path=/docs/a.txt", false, StatusCode::UNAUTHORIZED),
+        ("path=/private/x.txt", true, StatusCode::FORBIDDEN),
+        ("path=docs/a.txt", true, StatusCode::BAD_REQUEST),
+        ("path=/docs/a.txt&ttl=soon", true, StatusCode::BAD_REQUEST),
+        ("ttl=1h", true, StatusCode::BAD_REQUEST),
+    ] {
+        let response = app.clone().oneshot(mint(query, login)).await.unwrap();
+        assert_eq!(response.status(), status, "{query}");
+    }
+
+    let mut config = share_config(&temp_dir.path().join("docs"));
+    config.security.share_secret = None;
+    let response = support::app(config)
+        .oneshot(mint("path=/a.txt", true))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn prefix_links_accept_uploads_below_them() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(share_config(temp_dir.path()));
+
+    let url = mint_url(&app, "path=/drop/&prefix=true&method=post").await;
+    let query = url.split_once('?').unwrap().1;
+
+    for (path, status) in [
+        ("/drop/", StatusCode::NO_CONTENT),
+        ("/docs/", StatusCode::FORBIDDEN),
+    ] {
+        let body = multipart_body(BOUNDARY, "report.pdf", b"report");
+        let response = app
+            .clone()
+            .oneshot(multipart_request(
+                &format!("{path}?{query}"),
+                BOUNDARY,
+                body,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{path}");
+    }
+    assert!(temp_dir.path().join("drop/report.pdf").exists());
+
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/drop/?{query}")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn links_keep_to_the_access_rules_of_their_signer() {
+    let temp_dir = TempDir::new().unwrap();
+    let app = app(share_config(temp_dir.path()));
+    fs::write(temp_dir.path().join("private/x.txt"), "private").unwrap();
+
+    // a prefix above a denied directory does not open it
+    let url = mint_url(&app, "path=/&prefix=true").await;
+    let query = url.split_once('?').unwrap().1;
+    for (path, status) in [
+        ("/docs/a.txt", StatusCode::OK),
+        ("/private/x.txt", StatusCode::FORBIDDEN),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(get(&format!("{path}?{query}")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "{path}");
+    }
+
+    // the signer is part of the signature
+    let forged = url.replace("by=alice", "by=bob");
+    let response = app.clone().oneshot(get(&forged)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn tokens_share_only_what_they_cover() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = share_config(temp_dir.path());
+    config.security.tokens = vec![
+        TokenConfig {
+            name: "uploader".to_string(),
+            token_hash: hash_token("upload-token"),
+            scopes: vec![TokenScope::Upload],
+            paths: Vec::new(),
+            expires: None,
+        },
+        TokenConfig {
+            name: "reader".to_string(),
+            token_hash: hash_token("read-token"),
+            scopes: vec![TokenScope::Read],
+            paths: vec!["/docs/".to_string()],
+            expires: None,
+        },
+    ];
+    let app = app(config);
+
+    for (token, query, status) in [
+        ("upload-token", "path=/docs/a.txt", StatusCode::FORBIDDEN),
+        (
+            "upload-token",
+            "path=/drop/&prefix=true&method=post",
+            StatusCode::OK,
+        ),
+        ("read-token", "path=/docs/a.txt", StatusCode::OK),
+        ("read-token", "path=/&prefix=true", StatusCode::FORBIDDEN),
+        (
+            "read-token",
+            "path=/drop/&method=post",
+            StatusCode::FORBIDDEN,
+        ),
+    ] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("/__soop_share?{query}"))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), status, "{token} {query}");
+    }
+}
+
+#[tokio::test]
+async fn sign_command_uses_the_config_secret() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = share_config(temp_dir.path());
+    let config_path = temp_dir.path().join("soop3.toml");
+    fs::write(
+        &config_path,
+        format!(
+            "[server]\npublic_dir = \"{}\"\n[security]\nshare_secret = \"{SECRET}\"\n",
+            temp_dir.path().display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_soop3"))
+        .arg("-c")
+        .arg(&config_path)
+        .args(["sign", "/docs/a.txt", "--ttl", "10m"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let url = String::from_utf8(output.stdout).unwrap().trim().to_string();
+
+    let response = app(config).oneshot(get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{url}");
+}
@@ -0,0 +1,81 @@
+// graceful shutdown and connection draining
+
+mod support;
+
+use soop3::server::shutdown::serve_until_shutdown;
+use std::fs;
+use std::time::{Duration, Instant};
+use support::{app, base_config};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn finishes_in_flight_requests_then_stops_accepting() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("hello.txt"), "hello").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve_until_shutdown(
+        listener,
+        app(base_config(temp_dir.path())),
+        shutdown.clone(),
+        Duration::from_secs(10),
+    ));
+
+    // start a request and shut down before it has been fully sent
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown.cancel();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    stream.write_all(b"\r\n").await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("hello"));
+
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn drain_timeout_bounds_shutdown() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve_until_shutdown(
+        listener,
+        app(base_config(temp_dir.path())),
+        shutdown.clone(),
+        Duration::from_millis(200),
+    ));
+
+    // a client that never finishes its request must not hold up shutdown forever
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = Instant::now();
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
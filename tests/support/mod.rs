@@ -55,6 +55,14 @@ pub fn get_with_range(uri: &str, range: &str) -> Request<Body> {
         .unwrap()
 }
 
+pub fn get_with_headers(uri: &str, headers: &[(header::HeaderName, &str)]) -> Request<Body> {
+    let mut builder = Request::builder().method(Method::GET).uri(uri);
+    for (name, value) in headers {
+        builder = builder.header(name, *value);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
 pub fn head(uri: &str) -> Request<Body> {
     Request::builder()
         .method(Method::HEAD)
@@ -0,0 +1,59 @@
+// service manager notifications, checked against a fake notify socket
+#![cfg(unix)]
+
+use std::os::unix::net::UnixDatagram;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn recv_state(socket: &UnixDatagram) -> String {
+    let mut buf = [0u8; 256];
+    let len = socket.recv(&mut buf).expect("no notification received");
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn terminate(child: &Child) {
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn notifies_ready_watchdog_and_stopping() {
+    let temp_dir = TempDir::new().unwrap();
+    let notify_path = temp_dir.path().join("notify.sock");
+    let notify = UnixDatagram::bind(&notify_path).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let socket_path = temp_dir.path().join("soop3.sock");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_soop3"))
+        .arg(temp_dir.path())
+        .arg("--listen")
+        .arg(format!("unix:{}", socket_path.display()))
+        .arg("--quiet")
+        .env("NOTIFY_SOCKET", &notify_path)
+        .env("WATCHDOG_USEC", "200000")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    assert_eq!(recv_state(&notify), "READY=1");
+    // listeners are bound before readiness is reported
+    assert!(socket_path.exists());
+    assert_eq!(recv_state(&notify), "WATCHDOG=1");
+
+    terminate(&child);
+    let mut state = recv_state(&notify);
+    while state == "WATCHDOG=1" {
+        state = recv_state(&notify);
+    }
+    assert_eq!(state, "STOPPING=1");
+
+    let status = child.wait().unwrap();
+    assert!(status.success());
+}
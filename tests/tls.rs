@@ -0,0 +1,150 @@
+// https listener integration tests
+
+mod support;
+
+use rustls::pki_types::{CertificateDer, ServerName, pem::PemObject};
+use soop3::server::tls::{self, CertificateResolver, TlsListener};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use support::{app, base_config};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+fn fixture_cert(name: &str) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(fixture(name)).unwrap()
+}
+
+fn connector() -> TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(fixture_cert("first.crt")).unwrap();
+    roots.add(fixture_cert("second.crt")).unwrap();
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+// fetch a path over https, returning the raw response and the served certificate
+async fn https_get(addr: std::net::SocketAddr, path: &str) -> (String, CertificateDer<'static>) {
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut stream = connector().connect(server_name, tcp).await.unwrap();
+
+    let peer_cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+
+    (String::from_utf8_lossy(&response).into_owned(), peer_cert)
+}
+
+async fn spawn_https_server(
+    public_dir: &Path,
+    resolver: Arc<CertificateResolver>,
+) -> std::net::SocketAddr {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener = TlsListener::new(tcp, tls::server_config(resolver).unwrap()).unwrap();
+    let addr = axum::serve::Listener::local_addr(&listener).unwrap();
+
+    let app = app(base_config(public_dir));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn serves_files_over_https() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("hello.txt"), "hello over tls").unwrap();
+
+    let resolver =
+        Arc::new(CertificateResolver::load(&fixture("first.crt"), &fixture("first.key")).unwrap());
+    let addr = spawn_https_server(temp_dir.path(), resolver).await;
+
+    let (response, _) = https_get(addr, "/hello.txt").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("hello over tls"));
+}
+
+#[tokio::test]
+async fn reloads_certificate_for_new_connections() {
+    let temp_dir = TempDir::new().unwrap();
+    let cert_path = temp_dir.path().join("cert.pem");
+    let key_path = temp_dir.path().join("key.pem");
+    fs::copy(fixture("first.crt"), &cert_path).unwrap();
+    fs::copy(fixture("first.key"), &key_path).unwrap();
+
+    let resolver = Arc::new(CertificateResolver::load(&cert_path, &key_path).unwrap());
+    let addr = spawn_https_server(temp_dir.path(), resolver.clone()).await;
+
+    let (_, served) = https_get(addr, "/").await;
+    assert_eq!(served, fixture_cert("first.crt"));
+
+    // a half-written renewal is rejected and the current certificate kept
+    fs::copy(fixture("second.crt"), &cert_path).unwrap();
+    assert!(resolver.reload().is_err());
+    let (_, served) = https_get(addr, "/").await;
+    assert_eq!(served, fixture_cert("first.crt"));
+
+    fs::copy(fixture("second.key"), &key_path).unwrap();
+    resolver.reload().unwrap();
+    let (response, served) = https_get(addr, "/").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert_eq!(served, fixture_cert("second.crt"));
+}
+
+#[test]
+fn rejects_mismatched_key() {
+    let result = tls::load_certified_key(&fixture("first.crt"), &fixture("second.key"));
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn serves_self_signed_certificate() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("hello.txt"), "hello").unwrap();
+
+    let certified = tls::self_signed_certificate("127.0.0.1").unwrap();
+    let expected = certified.cert[0].clone();
+    let resolver = Arc::new(CertificateResolver::fixed(certified));
+    let fingerprint = resolver.fingerprint();
+    let addr = spawn_https_server(temp_dir.path(), resolver).await;
+
+    // trust exactly the generated certificate, as a client pinning it would
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(expected.clone()).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("127.0.0.1").unwrap();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .unwrap();
+
+    let served = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+    assert_eq!(served, expected);
+    assert_eq!(tls::certificate_fingerprint(&served), fingerprint);
+}
@@ -0,0 +1,259 @@
+// name based virtual hosts
+
+mod support;
+
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use soop3::config::{
+    AppConfig, RateLimitConfig, SecurityConfig, SecurityPolicy, ServerConfig, UploadConfig,
+    VhostConfig,
+};
+use std::fs;
+use std::path::Path;
+use support::{
+    BOUNDARY, app, auth_header, base_config, body_string, get_with_headers, multipart_body,
+    multipart_request,
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn vhost(names: &[&str], public_dir: &Path) -> VhostConfig {
+    VhostConfig {
+        names: names.iter().map(|name| name.to_string()).collect(),
+        default: false,
+        server: ServerConfig {
+            public_dir: public_dir.to_path_buf(),
+            ..Default::default()
+        },
+        security: None,
+        upload: None,
+        mounts: Vec::new(),
+        access_rules: Vec::new(),
+    }
+}
+
+struct Sites {
+    root: TempDir,
+    docs: TempDir,
+    artifacts: TempDir,
+}
+
+impl Sites {
+    fn new() -> Self {
+        let sites = Self {
+            root: TempDir::new().unwrap(),
+            docs: TempDir::new().unwrap(),
+            artifacts: TempDir::new().unwrap(),
+        };
+        for (dir, name) in [
+            (&sites.root, "root"),
+            (&sites.docs, "docs"),
+            (&sites.artifacts, "artifacts"),
+        ] {
+            fs::write(dir.path().join("site.txt"), name).unwrap();
+        }
+        sites
+    }
+
+    fn config(&self) -> AppConfig {
+        let mut config = base_config(self.root.path());
+        config.vhosts = vec![
+            vhost(&["docs.local"], self.docs.path()),
+            vhost(
+                &["artifacts.local", "*.artifacts.local"],
+                self.artifacts.path(),
+            ),
+        ];
+        config
+    }
+}
+
+async fn site_for(config: AppConfig, host: &str) -> (StatusCode, String) {
+    let response = app(config)
+        .oneshot(get_with_headers("/site.txt", &[(header::HOST, host)]))
+        .await
+        .unwrap();
+    (response.status(), body_string(response).await)
+}
+
+#[tokio::test]
+async fn routes_requests_by_host_name() {
+    let sites = Sites::new();
+
+    for (host, site) in [
+        ("docs.local", "docs"),
+        ("DOCS.local:8000", "docs"),
+        ("artifacts.local", "artifacts"),
+        ("ci.artifacts.local", "artifacts"),
+        ("www.docs.local", "root"),
+        ("other.local", "root"),
+    ] {
+        let (status, body) = site_for(sites.config(), host).await;
+        assert_eq!(status, StatusCode::OK, "{host}");
+        assert_eq!(body, site, "{host}");
+    }
+}
+
+#[tokio::test]
+async fn default_vhost_serves_unmatched_hosts() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.vhosts[0].default = true;
+
+    assert_eq!(site_for(config.clone(), "other.local").await.1, "docs");
+    assert_eq!(site_for(config, "artifacts.local").await.1, "artifacts");
+}
+
+#[tokio::test]
+async fn vhosts_apply_their_own_security_and_uploads() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.vhosts[1].server.enable_upload = true;
+    config.vhosts[1].upload = Some(UploadConfig {
+        prepend_timestamp: false,
+        ..Default::default()
+    });
+    config.vhosts[0].security = Some(SecurityConfig {
+        username: Some("admin".to_string()),
+        password: Some("secret".to_string()),
+        policy: SecurityPolicy::AuthenticateAll,
+        ..Default::default()
+    });
+
+    let (status, _) = site_for(config.clone(), "docs.local").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = site_for(config.clone(), "artifacts.local").await;
+    assert_eq!(status, StatusCode::OK);
+
+    for (host, expected) in [
+        ("artifacts.local", StatusCode::NO_CONTENT),
+        ("other.local", StatusCode::FORBIDDEN),
+    ] {
+        let mut request =
+            multipart_request("/", BOUNDARY, multipart_body(BOUNDARY, "build.zip", b"zip"));
+        request
+            .headers_mut()
+            .insert(header::HOST, host.parse().unwrap());
+        let response = app(config.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), expected, "{host}");
+    }
+    assert!(sites.artifacts.path().join("build.zip").exists());
+    assert!(!sites.root.path().join("build.zip").exists());
+}
+
+#[tokio::test]
+async fn vhosts_without_security_inherit_it() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.security.username = Some("admin".to_string());
+    config.security.password = Some("secret".to_string());
+    config.security.policy = SecurityPolicy::AuthenticateAll;
+
+    for host in ["docs.local", "artifacts.local", "other.local"] {
+        let (status, _) = site_for(config.clone(), host).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{host}");
+    }
+}
+
+#[tokio::test]
+async fn vhosts_without_upload_settings_inherit_them() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.upload.prepend_timestamp = false;
+    config.vhosts[1].server.enable_upload = true;
+
+    let mut request =
+        multipart_request("/", BOUNDARY, multipart_body(BOUNDARY, "build.zip", b"zip"));
+    request
+        .headers_mut()
+        .insert(header::HOST, "artifacts.local".parse().unwrap());
+    let response = app(config).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(sites.artifacts.path().join("build.zip").exists());
+}
+
+#[tokio::test]
+async fn failed_logins_lock_out_one_vhost_only() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.security = SecurityConfig {
+        username: Some("admin".to_string()),
+        password: Some("secret".to_string()),
+        policy: SecurityPolicy::AuthenticateAll,
+        rate_limit: RateLimitConfig {
+            max_failures: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let app = app(config);
+
+    let login = |host: &'static str, password: &str| {
+        let authorization = format!("Basic {}", auth_header("admin", password));
+        get_with_headers(
+            "/site.txt",
+            &[
+                (header::HOST, host),
+                (header::AUTHORIZATION, &authorization),
+            ],
+        )
+    };
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(login("docs.local", "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    for (host, expected) in [
+        ("docs.local", StatusCode::TOO_MANY_REQUESTS),
+        ("artifacts.local", StatusCode::OK),
+    ] {
+        let response = app.clone().oneshot(login(host, "secret")).await.unwrap();
+        assert_eq!(response.status(), expected, "{host}");
+    }
+}
+
+#[tokio::test]
+async fn share_links_stay_on_their_vhost() {
+    let sites = Sites::new();
+    let mut config = sites.config();
+    config.security = SecurityConfig {
+        username: Some("admin".to_string()),
+        password: Some("secret".to_string()),
+        policy: SecurityPolicy::AuthenticateAll,
+        share_secret: Some("0123456789abcdef0123456789abcdef".to_string()),
+        ..Default::default()
+    };
+    let app = app(config);
+
+    let mint = Request::builder()
+        .method(Method::POST)
+        .uri("/__soop_share?path=/site.txt&ttl=1h")
+        .header(header::HOST, "docs.local")
+        .header(
+            header::AUTHORIZATION,
+            format!("Basic {}", auth_header("admin", "secret")),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(mint).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let url = body["url"].as_str().unwrap();
+
+    for (host, expected) in [
+        ("docs.local", StatusCode::OK),
+        ("artifacts.local", StatusCode::FORBIDDEN),
+        ("other.local", StatusCode::FORBIDDEN),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(get_with_headers(url, &[(header::HOST, host)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected, "{host}");
+    }
+}